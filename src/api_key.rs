@@ -0,0 +1,37 @@
+//! the hashed credential identifier recorded as the `api_key.hash` attribute
+
+use sha2::{Digest, Sha256};
+
+/// the number of hex characters of the recorded hash, 64 bits
+const HASH_LEN: usize = 16;
+
+/// returns the truncated SHA-256 of `salt` and `key` in hex, stable across processes sharing the salt.
+///
+/// the salt keeps the hashes of low entropy keys from being reversed with a dictionary.
+pub(crate) fn hash(salt: &str, key: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update([0]);
+    hasher.update(key.as_bytes());
+    let digest = hasher.finalize();
+    let mut hex = String::with_capacity(HASH_LEN);
+    for byte in &digest[..HASH_LEN / 2] {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        let hashed = hash("salt", "secret-key");
+        assert_eq!(hashed.len(), HASH_LEN);
+        assert!(!hashed.contains("secret"));
+        assert_eq!(hashed, hash("salt", "secret-key"));
+        assert_ne!(hashed, hash("pepper", "secret-key"));
+        assert_ne!(hashed, hash("salt", "other-key"));
+    }
+}
@@ -0,0 +1,66 @@
+//! helpers to register application (business) instruments next to the HTTP metrics
+
+use opentelemetry::metrics::{
+    Counter, Gauge, Histogram, HistogramBuilder, InstrumentBuilder, Meter, UpDownCounter,
+};
+
+/// A handle to register application instruments on the meter of the HTTP metrics.
+///
+/// the instruments share the provider, resource, exporter and instrument prefix of the HTTP metrics,
+/// so they end up on the same `/metrics` endpoint or OTLP pipeline.
+/// the handle is cheap to clone, put it in the application state to create instruments from handlers.
+///
+/// ```
+/// use axum_otel_metrics::HttpMetricsLayerBuilder;
+///
+/// let metrics = HttpMetricsLayerBuilder::new().build();
+/// let jobs = metrics
+///     .app_metrics()
+///     .counter("jobs.processed")
+///     .with_description("The number of processed jobs.")
+///     .init();
+/// jobs.add(1, &[]);
+/// ```
+#[derive(Clone)]
+pub struct AppMetrics {
+    meter: Meter,
+    prefix: Option<String>,
+}
+
+impl AppMetrics {
+    pub(crate) fn new(meter: Meter, prefix: Option<String>) -> Self {
+        Self { meter, prefix }
+    }
+
+    /// returns the underlying meter, without the instrument prefix applied
+    pub fn meter(&self) -> &Meter {
+        &self.meter
+    }
+
+    /// start building a monotonic `u64` counter
+    pub fn counter(&self, name: impl Into<String>) -> InstrumentBuilder<'_, Counter<u64>> {
+        self.meter.u64_counter(self.name(name.into()))
+    }
+
+    /// start building an `i64` up/down counter
+    pub fn up_down_counter(&self, name: impl Into<String>) -> InstrumentBuilder<'_, UpDownCounter<i64>> {
+        self.meter.i64_up_down_counter(self.name(name.into()))
+    }
+
+    /// start building a `f64` histogram
+    pub fn histogram(&self, name: impl Into<String>) -> HistogramBuilder<'_, Histogram<f64>> {
+        self.meter.f64_histogram(self.name(name.into()))
+    }
+
+    /// start building a `f64` gauge
+    pub fn gauge(&self, name: impl Into<String>) -> InstrumentBuilder<'_, Gauge<f64>> {
+        self.meter.f64_gauge(self.name(name.into()))
+    }
+
+    pub(crate) fn name(&self, name: String) -> String {
+        match self.prefix {
+            Some(ref prefix) => format!("{}.{}", prefix, name),
+            None => name,
+        }
+    }
+}
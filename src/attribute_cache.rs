@@ -0,0 +1,192 @@
+//! cache of the processed attribute sets of the request metrics
+//!
+//! most responses only differ by method, route and status, so the attribute set built, filtered,
+//! redacted and limited for one of them is reused by the next ones instead of being built again.
+//! only these low cardinality attributes are cached, the ones taken from the request, e.g. `server.address`,
+//! are processed for every request, so a client sending made-up hosts can not fill the cache.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use opentelemetry::KeyValue;
+
+/// the number of independently locked shards of the cache
+const SHARDS: usize = 16;
+
+/// the attributes a cached attribute set is built from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct AttributeKey<'a> {
+    pub(crate) method: &'a str,
+    pub(crate) route: &'a str,
+    pub(crate) status: u16,
+    pub(crate) protocol_version: Option<&'static str>,
+}
+
+/// the owned copy of an [AttributeKey], to tell apart the keys with the same hash
+#[derive(Debug)]
+struct OwnedKey {
+    method: String,
+    route: String,
+    status: u16,
+    protocol_version: Option<&'static str>,
+}
+
+impl OwnedKey {
+    fn matches(&self, key: &AttributeKey<'_>) -> bool {
+        self.method == key.method
+            && self.route == key.route
+            && self.status == key.status
+            && self.protocol_version == key.protocol_version
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    key: OwnedKey,
+    attributes: Arc<[KeyValue]>,
+    /// the tick of the shard clock of the last lookup, the lowest one is evicted first
+    last_used: AtomicU64,
+}
+
+/// the attribute sets of a shard, keyed by the hash of their [AttributeKey] so a lookup allocates nothing
+#[derive(Debug, Default)]
+struct Shard {
+    clock: AtomicU64,
+    entries: RwLock<HashMap<u64, Vec<Entry>>>,
+}
+
+/// a sharded least recently used cache of the attribute sets
+///
+/// at most `max_entries` sets are cached, a new set evicts the least recently used one of its shard.
+#[derive(Debug)]
+pub(crate) struct AttributeCache {
+    max_shard_entries: usize,
+    hasher: RandomState,
+    shards: Box<[Shard]>,
+}
+
+impl AttributeCache {
+    pub(crate) fn new(max_entries: usize) -> Self {
+        Self {
+            max_shard_entries: max_entries.div_ceil(SHARDS).max(1),
+            hasher: RandomState::new(),
+            shards: (0..SHARDS).map(|_| Shard::default()).collect(),
+        }
+    }
+
+    fn hash(&self, key: &AttributeKey<'_>) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn shard(&self, hash: u64) -> &Shard {
+        &self.shards[hash as usize % SHARDS]
+    }
+
+    pub(crate) fn get(&self, key: &AttributeKey<'_>) -> Option<Arc<[KeyValue]>> {
+        let hash = self.hash(key);
+        let shard = self.shard(hash);
+        let entries = shard.entries.read().unwrap();
+        let entry = entries.get(&hash)?.iter().find(|entry| entry.key.matches(key))?;
+        entry
+            .last_used
+            .store(shard.clock.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+        Some(entry.attributes.clone())
+    }
+
+    pub(crate) fn insert(&self, key: &AttributeKey<'_>, attributes: Arc<[KeyValue]>) {
+        let hash = self.hash(key);
+        let shard = self.shard(hash);
+        let mut entries = shard.entries.write().unwrap();
+        if entries
+            .get(&hash)
+            .is_some_and(|bucket| bucket.iter().any(|entry| entry.key.matches(key)))
+        {
+            return;
+        }
+        if entries.values().map(Vec::len).sum::<usize>() >= self.max_shard_entries {
+            evict_least_recently_used(&mut entries);
+        }
+        let entry = Entry {
+            key: OwnedKey {
+                method: key.method.to_owned(),
+                route: key.route.to_owned(),
+                status: key.status,
+                protocol_version: key.protocol_version,
+            },
+            attributes,
+            last_used: AtomicU64::new(shard.clock.fetch_add(1, Ordering::Relaxed)),
+        };
+        entries.entry(hash).or_default().push(entry);
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.entries.read().unwrap().values().map(Vec::len).sum::<usize>())
+            .sum()
+    }
+}
+
+fn evict_least_recently_used(entries: &mut HashMap<u64, Vec<Entry>>) {
+    let oldest = entries
+        .iter()
+        .flat_map(|(hash, bucket)| {
+            bucket
+                .iter()
+                .enumerate()
+                .map(move |(index, entry)| (entry.last_used.load(Ordering::Relaxed), *hash, index))
+        })
+        .min();
+    if let Some((_, hash, index)) = oldest {
+        let bucket = entries.get_mut(&hash).unwrap();
+        bucket.swap_remove(index);
+        if bucket.is_empty() {
+            entries.remove(&hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(route: &str) -> AttributeKey<'_> {
+        AttributeKey {
+            method: "GET",
+            route,
+            status: 200,
+            protocol_version: None,
+        }
+    }
+
+    #[test]
+    fn test_attribute_cache() {
+        let cache = AttributeCache::new(1);
+        assert!(cache.get(&key("/users")).is_none());
+
+        let attributes: Arc<[KeyValue]> = vec![KeyValue::new("http.route", "/users")].into();
+        cache.insert(&key("/users"), attributes.clone());
+        assert!(Arc::ptr_eq(&cache.get(&key("/users")).unwrap(), &attributes));
+    }
+
+    #[test]
+    fn test_attribute_cache_full() {
+        let cache = AttributeCache::new(SHARDS);
+        for i in 0..1000 {
+            let route = format!("/cold/{i}");
+            cache.insert(&key(&route), vec![KeyValue::new("http.route", route.clone())].into());
+        }
+        assert!(cache.len() <= SHARDS);
+
+        // a full cache still takes the new hot keys
+        cache.insert(&key("/hot"), vec![KeyValue::new("http.route", "/hot")].into());
+        assert!(cache.get(&key("/hot")).is_some());
+        assert!(cache.len() <= SHARDS);
+    }
+}
@@ -0,0 +1,77 @@
+//! metric attributes from the W3C `baggage` request header
+//!
+//! ref https://www.w3.org/TR/baggage/
+
+use axum::http::HeaderMap;
+use opentelemetry::KeyValue;
+
+/// the attributes of the baggage entries named in `keys`, in the order of `keys`
+///
+/// the first entry wins when a key is repeated, the entry properties are ignored.
+pub(crate) fn extract(headers: &HeaderMap, keys: &[String]) -> Vec<KeyValue> {
+    let mut values: Vec<Option<String>> = vec![None; keys.len()];
+    let members = headers
+        .get_all("baggage")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+    for member in members {
+        let pair = member.split(';').next().unwrap_or_default();
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let key = key.trim();
+        if let Some(i) = keys.iter().position(|k| k == key) {
+            if values[i].is_none() {
+                values[i] = percent_decode(value.trim());
+            }
+        }
+    }
+    keys.iter()
+        .zip(values)
+        .filter_map(|(key, value)| value.map(|value| KeyValue::new(key.clone(), value)))
+        .collect()
+}
+
+/// decode the percent encoded baggage value, `None` if it is not valid UTF-8
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_baggage() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "baggage",
+            "userId=alice, tenant=acme%20corp;ttl=60, plan=pro, tenant=other".parse().unwrap(),
+        );
+        let keys = vec!["tenant".to_string(), "plan".to_string(), "region".to_string()];
+        assert_eq!(
+            extract(&headers, &keys),
+            vec![KeyValue::new("tenant", "acme corp"), KeyValue::new("plan", "pro")]
+        );
+    }
+}
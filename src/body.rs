@@ -0,0 +1,222 @@
+//! the request and response bodies of the metrics middleware, for the measurements only known once the body is streamed
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::Buf;
+use futures_util::ready;
+use http_body::{Body, Frame, SizeHint};
+use opentelemetry::metrics::Counter;
+use opentelemetry::KeyValue;
+use pin_project_lite::pin_project;
+
+use crate::compression_ratio::CompressedBody;
+use crate::grpc::{self, GrpcCall};
+use crate::sse::SseStream;
+
+pin_project! {
+    /// The request and response body of [crate::HttpMetrics].
+    ///
+    /// it forwards the inner body and counts its streamed bytes, records the gRPC call, if any,
+    /// once the trailers are sent, the events of a Server-Sent Events stream as they are sent,
+    /// and the compression of a compressed response once it is sent to its end.
+    pub struct MetricsBody<B> {
+        #[pin]
+        inner: B,
+        grpc_call: Option<GrpcCall>,
+        sse_stream: Option<SseStream>,
+        bytes: Option<BodyBytes>,
+        compressed: Option<CompressedBody>,
+    }
+
+    impl<B> PinnedDrop for MetricsBody<B> {
+        fn drop(this: Pin<&mut Self>) {
+            // the client went away before the end of the response
+            if let Some(call) = this.project().grpc_call.take() {
+                call.finish(grpc::STATUS_CANCELLED);
+            }
+        }
+    }
+}
+
+impl<B> MetricsBody<B> {
+    pub(crate) fn new(inner: B) -> Self {
+        Self::recorded(inner, None, None, None)
+    }
+
+    pub(crate) fn grpc(inner: B, call: GrpcCall) -> Self {
+        Self {
+            inner,
+            grpc_call: Some(call),
+            sse_stream: None,
+            bytes: None,
+            compressed: None,
+        }
+    }
+
+    pub(crate) fn recorded(
+        inner: B,
+        stream: Option<SseStream>,
+        bytes: Option<BodyBytes>,
+        compressed: Option<CompressedBody>,
+    ) -> Self {
+        Self {
+            inner,
+            grpc_call: None,
+            sse_stream: stream,
+            bytes,
+            compressed,
+        }
+    }
+}
+
+impl<B: Body> Body for MetricsBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        let status = match frame {
+            Some(Ok(ref frame)) => frame
+                .trailers_ref()
+                .map(|trailers| grpc::status(trailers).unwrap_or(grpc::STATUS_UNKNOWN)),
+            Some(Err(_)) | None => Some(grpc::STATUS_UNKNOWN),
+        };
+        if let Some(status) = status {
+            if let Some(call) = this.grpc_call.take() {
+                call.finish(status);
+            }
+        }
+        match frame {
+            Some(Ok(ref frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    if let Some(bytes) = this.bytes.as_ref() {
+                        bytes.add(data.remaining());
+                    }
+                    if let Some(stream) = this.sse_stream.as_mut() {
+                        stream.count(data.chunk());
+                    }
+                    if let Some(compressed) = this.compressed.as_mut() {
+                        compressed.count(data.remaining());
+                    }
+                }
+            }
+            // the stream is recorded as closed when dropped
+            Some(Err(_)) => {
+                this.sse_stream.take();
+                this.compressed.take();
+            }
+            None => {
+                this.sse_stream.take();
+                if let Some(compressed) = this.compressed.take() {
+                    compressed.finish();
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// counts the bytes of a body as they are streamed
+pub(crate) struct BodyBytes {
+    counter: Counter<u64>,
+    attributes: Vec<KeyValue>,
+}
+
+impl BodyBytes {
+    pub(crate) fn new(counter: Counter<u64>, attributes: Vec<KeyValue>) -> Self {
+        Self { counter, attributes }
+    }
+
+    fn add(&self, len: usize) {
+        if len > 0 {
+            self.counter.add(len as u64, &self.attributes);
+        }
+    }
+}
+
+/// the bytes of a request body, counted with the attributes of the request once its route is final
+///
+/// the bytes streamed before the response is ready are added up, the later ones are counted as they are streamed.
+#[derive(Clone, Default)]
+pub(crate) struct RequestBodyBytes(Arc<Mutex<RequestBytesState>>);
+
+enum RequestBytesState {
+    Pending(u64),
+    Counting(BodyBytes),
+}
+
+impl Default for RequestBytesState {
+    fn default() -> Self {
+        Self::Pending(0)
+    }
+}
+
+impl RequestBodyBytes {
+    fn add(&self, len: usize) {
+        match *self.0.lock().unwrap() {
+            RequestBytesState::Pending(ref mut pending) => *pending += len as u64,
+            RequestBytesState::Counting(ref bytes) => bytes.add(len),
+        }
+    }
+
+    /// count the bytes streamed so far, and the next ones, with `bytes`
+    pub(crate) fn count(&self, bytes: BodyBytes) {
+        let mut state = self.0.lock().unwrap();
+        if let RequestBytesState::Pending(pending @ 1..) = *state {
+            bytes.add(pending as usize);
+        }
+        *state = RequestBytesState::Counting(bytes);
+    }
+}
+
+pin_project! {
+    /// The request body handed by [crate::HttpMetrics] to the inner service.
+    ///
+    /// it forwards the body of the request and counts its streamed bytes when the throughput metrics are enabled.
+    pub struct RequestBody<B> {
+        #[pin]
+        inner: B,
+        bytes: Option<RequestBodyBytes>,
+    }
+}
+
+impl<B> RequestBody<B> {
+    pub(crate) fn new(inner: B, bytes: Option<RequestBodyBytes>) -> Self {
+        Self { inner, bytes }
+    }
+}
+
+impl<B: Body> Body for RequestBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        if let Some(Ok(ref frame)) = frame {
+            if let (Some(data), Some(bytes)) = (frame.data_ref(), this.bytes.as_ref()) {
+                bytes.add(data.remaining());
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
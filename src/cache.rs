@@ -0,0 +1,98 @@
+//! a short lived cache of the encoded metrics endpoint response, shared by concurrent scrapes
+
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+use crate::exposition::{Encoded, Format};
+
+struct Entry {
+    encoded: Encoded,
+    encoded_at: Instant,
+}
+
+/// caches the encoded payload of every format for `ttl`
+///
+/// the slot of a format stays locked while its payload is encoded,
+/// so the concurrent scrapes of an expired payload wait for a single encode pass.
+pub(crate) struct ResponseCache {
+    ttl: Duration,
+    slots: [Mutex<Option<Entry>>; 3],
+}
+
+impl ResponseCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            slots: Default::default(),
+        }
+    }
+
+    /// returns the cached payload of `format` if it is fresh, otherwise caches the result of `encode`
+    ///
+    /// a failed encoding is not cached, the next scrape tries again.
+    pub(crate) async fn get_or_encode<E>(
+        &self,
+        format: Format,
+        encode: impl FnOnce() -> Result<Encoded, E>,
+    ) -> Result<Encoded, E> {
+        let mut slot = self.slots[slot(format)].lock().await;
+        if let Some(ref entry) = *slot {
+            if entry.encoded_at.elapsed() < self.ttl {
+                return Ok(entry.encoded.clone());
+            }
+        }
+        let encoded = encode()?;
+        *slot = Some(Entry {
+            encoded: encoded.clone(),
+            encoded_at: Instant::now(),
+        });
+        Ok(encoded)
+    }
+}
+
+fn slot(format: Format) -> usize {
+    match format {
+        Format::Text => 0,
+        Format::OpenMetrics => 1,
+        Format::Protobuf => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(body: &'static [u8]) -> Result<Encoded, ()> {
+        Ok(Encoded {
+            body: Some(body.into()),
+            etag: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_get_or_encode() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let first = cache.get_or_encode(Format::Text, || encoded(b"first")).await.unwrap();
+        assert_eq!(first.body.as_deref(), Some(&b"first"[..]));
+
+        let cached = cache.get_or_encode(Format::Text, || encoded(b"second")).await.unwrap();
+        assert_eq!(cached.body.as_deref(), Some(&b"first"[..]));
+
+        let other = cache.get_or_encode(Format::OpenMetrics, || encoded(b"other")).await.unwrap();
+        assert_eq!(other.body.as_deref(), Some(&b"other"[..]));
+
+        let cache = ResponseCache::new(Duration::ZERO);
+        cache.get_or_encode(Format::Text, || encoded(b"first")).await.unwrap();
+        let expired = cache.get_or_encode(Format::Text, || encoded(b"second")).await.unwrap();
+        assert_eq!(expired.body.as_deref(), Some(&b"second"[..]));
+    }
+
+    #[tokio::test]
+    async fn test_get_or_encode_error() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        assert!(cache.get_or_encode(Format::Text, || Err(())).await.is_err());
+        let retried = cache.get_or_encode(Format::Text, || encoded(b"first")).await.unwrap();
+        assert_eq!(retried.body.as_deref(), Some(&b"first"[..]));
+    }
+}
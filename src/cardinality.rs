@@ -0,0 +1,99 @@
+//! cardinality limiter for metric attributes
+//!
+//! every distinct attribute value creates a new time series in the backend,
+//! so a route fallback or a spoofed `Host` header can easily explode the number of series.
+//! the limiter caps the number of distinct values per attribute key and records any value
+//! beyond the cap as [OVERFLOW_VALUE].
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use opentelemetry::{Key, KeyValue, Value};
+
+/// the attribute value recorded once the cardinality limit of an attribute is reached
+pub const OVERFLOW_VALUE: &str = "__overflow__";
+
+/// a set of at most `max` distinct values, shared across requests
+///
+/// values are never evicted: the series of an admitted value keeps being exported
+/// by the cumulative exporters anyway, so evicting it would only let more series in.
+#[derive(Debug)]
+pub(crate) struct BoundedSet {
+    max: usize,
+    seen: RwLock<HashSet<String>>,
+}
+
+impl BoundedSet {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            max,
+            seen: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// returns `true` if `value` was seen before or there is still room for it
+    pub(crate) fn admit(&self, value: &str) -> bool {
+        if self.seen.read().unwrap().contains(value) {
+            return true;
+        }
+        let mut seen = self.seen.write().unwrap();
+        if seen.contains(value) {
+            return true;
+        }
+        if seen.len() < self.max {
+            seen.insert(value.to_owned());
+            return true;
+        }
+        false
+    }
+}
+
+/// limits the number of distinct values per attribute key
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CardinalityLimiter {
+    limits: Arc<HashMap<Key, BoundedSet>>,
+}
+
+impl CardinalityLimiter {
+    pub(crate) fn new(limits: HashMap<Key, usize>) -> Self {
+        Self {
+            limits: Arc::new(limits.into_iter().map(|(k, max)| (k, BoundedSet::new(max))).collect()),
+        }
+    }
+
+    /// replace the values of `attributes` exceeding their key's limit with [OVERFLOW_VALUE]
+    pub(crate) fn limit(&self, attributes: &mut [KeyValue]) {
+        if self.limits.is_empty() {
+            return;
+        }
+        for kv in attributes.iter_mut() {
+            if let Some(set) = self.limits.get(&kv.key) {
+                if !set.admit(&kv.value.as_str()) {
+                    kv.value = Value::from(OVERFLOW_VALUE);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_overflow() {
+        let limiter = CardinalityLimiter::new([(Key::from_static_str("http.route"), 2)].into_iter().collect());
+        let record = |route: &'static str| {
+            let mut attrs = [
+                KeyValue::new("http.route", route),
+                KeyValue::new("http.request.method", "GET"),
+            ];
+            limiter.limit(&mut attrs);
+            attrs[0].value.as_str().into_owned()
+        };
+        assert_eq!(record("/a"), "/a");
+        assert_eq!(record("/b"), "/b");
+        assert_eq!(record("/c"), OVERFLOW_VALUE);
+        assert_eq!(record("/a"), "/a");
+    }
+}
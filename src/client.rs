@@ -0,0 +1,198 @@
+//! metrics of the outbound HTTP requests, following the HTTP client semantic conventions
+//!
+//! ref https://opentelemetry.io/docs/specs/semconv/http/http-metrics/#http-client
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::http::{Request, Response, Uri};
+use futures_util::ready;
+use http_body::Body as httpBody;
+use opentelemetry::metrics::{Counter, Histogram};
+use opentelemetry::KeyValue;
+use pin_project_lite::pin_project;
+use tower::{Layer, Service};
+
+use crate::{AppMetrics, HTTP_REQ_DURATION_HISTOGRAM_BUCKETS, HTTP_REQ_SIZE_HISTOGRAM_BUCKETS};
+
+/// the client instruments
+struct ClientInstruments {
+    duration: Histogram<f64>,
+    request_size: Histogram<u64>,
+    response_size: Histogram<u64>,
+    errors: Counter<u64>,
+}
+
+/// A tower layer recording the outbound HTTP requests of a client, e.g. a hyper client.
+///
+/// - `http.client.request.duration`: histogram of the durations until the response headers
+/// - `http.client.request.body.size` and `http.client.response.body.size`: histograms of the body sizes,
+///   from the body size hints
+/// - `http.client.request.errors`: counter of the requests failing without a response, with the `error.type`
+///
+/// labeled by `http.request.method`, `server.address`, `server.port`, `url.scheme`
+/// and `http.response.status_code`.
+///
+/// create it with [crate::HttpMetricsLayer::client_layer] to share the provider and registry of the server metrics.
+#[derive(Clone)]
+pub struct HttpClientMetricsLayer {
+    instruments: Arc<ClientInstruments>,
+}
+
+impl HttpClientMetricsLayer {
+    pub(crate) fn new(metrics: &AppMetrics) -> Self {
+        let meter = metrics.meter();
+        let instruments = ClientInstruments {
+            duration: metrics
+                .histogram("http.client.request.duration")
+                .with_unit("s")
+                .with_description("The HTTP client request latencies in seconds.")
+                .with_boundaries(HTTP_REQ_DURATION_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            request_size: meter
+                .u64_histogram(metrics.name("http.client.request.body.size".to_string()))
+                .with_unit("By")
+                .with_description("The HTTP client request sizes in bytes.")
+                .with_boundaries(HTTP_REQ_SIZE_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            response_size: meter
+                .u64_histogram(metrics.name("http.client.response.body.size".to_string()))
+                .with_unit("By")
+                .with_description("The HTTP client response sizes in bytes.")
+                .with_boundaries(HTTP_REQ_SIZE_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            errors: metrics
+                .counter("http.client.request.errors")
+                .with_description("The number of HTTP client requests failed without a response.")
+                .init(),
+        };
+        Self {
+            instruments: Arc::new(instruments),
+        }
+    }
+}
+
+impl<S> Layer<S> for HttpClientMetricsLayer {
+    type Service = HttpClientMetrics<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        HttpClientMetrics {
+            instruments: self.instruments.clone(),
+            service,
+        }
+    }
+}
+
+/// the client service wrapper
+#[derive(Clone)]
+pub struct HttpClientMetrics<S> {
+    instruments: Arc<ClientInstruments>,
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for HttpClientMetrics<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ReqBody: httpBody,
+    ResBody: httpBody,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ClientResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let (scheme, address, port) = server(req.uri());
+        let mut attributes = vec![
+            KeyValue::new("http.request.method", req.method().as_str().to_string()),
+            KeyValue::new("server.address", address),
+            KeyValue::new("url.scheme", scheme),
+        ];
+        if let Some(port) = port {
+            attributes.push(KeyValue::new("server.port", port as i64));
+        }
+        let request_size = req.body().size_hint().upper().unwrap_or(0);
+
+        ClientResponseFuture {
+            inner: self.service.call(req),
+            instruments: self.instruments.clone(),
+            attributes,
+            request_size,
+            start: Instant::now(),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [HttpClientMetrics].
+    pub struct ClientResponseFuture<F> {
+        #[pin]
+        inner: F,
+        instruments: Arc<ClientInstruments>,
+        attributes: Vec<KeyValue>,
+        request_size: u64,
+        start: Instant,
+    }
+}
+
+impl<F, B: httpBody, E> Future for ClientResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+        let latency = this.start.elapsed().as_secs_f64();
+        let mut attributes = std::mem::take(this.attributes);
+
+        match result {
+            Ok(ref response) => {
+                attributes.push(KeyValue::new("http.response.status_code", response.status().as_u16() as i64));
+                let response_size = response.body().size_hint().upper().unwrap_or(0);
+                this.instruments.response_size.record(response_size, &attributes);
+            }
+            Err(_) => {
+                attributes.push(KeyValue::new("error.type", std::any::type_name::<E>()));
+                this.instruments.errors.add(1, &attributes);
+            }
+        }
+        this.instruments.duration.record(latency, &attributes);
+        this.instruments.request_size.record(*this.request_size, &attributes);
+
+        Poll::Ready(result)
+    }
+}
+
+/// the `url.scheme`, `server.address` and `server.port` of an absolute request uri,
+/// the port defaults to the one of the scheme
+fn server(uri: &Uri) -> (String, String, Option<u16>) {
+    let scheme = uri.scheme_str().unwrap_or("http").to_string();
+    let address = uri.host().unwrap_or("unknown").to_string();
+    let port = uri.port_u16().or(match scheme.as_str() {
+        "http" => Some(80),
+        "https" => Some(443),
+        _ => None,
+    });
+    (scheme, address, port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server() {
+        let uri: Uri = "https://example.com/path".parse().unwrap();
+        assert_eq!(server(&uri), ("https".to_string(), "example.com".to_string(), Some(443)));
+        let uri: Uri = "http://localhost:8080/".parse().unwrap();
+        assert_eq!(server(&uri), ("http".to_string(), "localhost".to_string(), Some(8080)));
+    }
+}
@@ -0,0 +1,95 @@
+//! compression of the metrics endpoint response, negotiated with the `Accept-Encoding` request header
+
+use std::io::Write;
+
+use axum::http::HeaderMap;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+
+/// the encodings the metrics endpoint can compress with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// the `Content-Encoding` header value
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub(crate) fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// pick the supported encoding with the highest quality in the `Accept-Encoding` headers,
+/// gzip wins ties, `None` means the response is sent uncompressed
+pub(crate) fn negotiate(headers: &HeaderMap) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    let values = headers
+        .get_all(http::header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok());
+    for item in values.flat_map(|value| value.split(',')) {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("q").then(|| value.trim().parse::<f32>().ok())?
+            })
+            .unwrap_or(1.0);
+        let encoding = if coding.eq_ignore_ascii_case("gzip") || coding == "*" {
+            Encoding::Gzip
+        } else if coding.eq_ignore_ascii_case("deflate") {
+            Encoding::Deflate
+        } else {
+            continue;
+        };
+        if quality <= 0.0 {
+            continue;
+        }
+        match best {
+            Some((current, q)) if q > quality || (q == quality && current == Encoding::Gzip) => {}
+            _ => best = Some((encoding, quality)),
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept_encoding(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::ACCEPT_ENCODING, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(&HeaderMap::new()), None);
+        assert_eq!(negotiate(&accept_encoding("identity")), None);
+        assert_eq!(negotiate(&accept_encoding("gzip")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&accept_encoding("deflate, gzip")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&accept_encoding("gzip;q=0.5, deflate")), Some(Encoding::Deflate));
+        assert_eq!(negotiate(&accept_encoding("gzip;q=0")), None);
+    }
+}
@@ -0,0 +1,248 @@
+//! metrics of the responses compressed by a layer beneath the metrics middleware, e.g. the `CompressionLayer` of tower-http
+//!
+//! the metrics middleware only sees the compressed body, an [UncompressedSizeLayer] beneath the compression layer
+//! counts the bytes of the body before it is compressed.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::http::{HeaderMap, Request, Response};
+use bytes::Buf;
+use futures_util::ready;
+use http_body::{Body, Frame, SizeHint};
+use opentelemetry::metrics::Histogram;
+use opentelemetry::KeyValue;
+use pin_project_lite::pin_project;
+use tower::{Layer, Service};
+
+use crate::{AppMetrics, HTTP_REQ_SIZE_HISTOGRAM_BUCKETS};
+
+/// from no gain to the ratio of highly repetitive payloads
+const COMPRESSION_RATIO_HISTOGRAM_BUCKETS: &[f64] = &[1.0, 1.25, 1.5, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0, 32.0];
+
+/// the `Content-Encoding` of a compressed response, `None` for an uncompressed one
+pub(crate) fn content_encoding(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(http::header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty() && !v.eq_ignore_ascii_case("identity"))
+}
+
+/// the bytes of a response body before its compression, shared through a request extension
+/// between the metrics middleware and the [UncompressedSizeLayer]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UncompressedBytes(Arc<AtomicU64>);
+
+impl UncompressedBytes {
+    fn add(&self, len: usize) {
+        self.0.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// the compression instruments
+#[derive(Clone)]
+pub(crate) struct CompressionMetrics {
+    uncompressed_size: Histogram<f64>,
+    compressed_size: Histogram<f64>,
+    ratio: Histogram<f64>,
+}
+
+impl CompressionMetrics {
+    pub(crate) fn new(metrics: &AppMetrics) -> Self {
+        Self {
+            uncompressed_size: metrics
+                .histogram("http.server.response.body.uncompressed_size")
+                .with_unit("By")
+                .with_description("The HTTP response body sizes before compression in bytes.")
+                .with_boundaries(HTTP_REQ_SIZE_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            compressed_size: metrics
+                .histogram("http.server.response.body.compressed_size")
+                .with_unit("By")
+                .with_description("The HTTP response body sizes after compression in bytes.")
+                .with_boundaries(HTTP_REQ_SIZE_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            ratio: metrics
+                .histogram("http.server.response.compression")
+                .with_unit("1")
+                .with_description("The HTTP response body sizes before compression divided by the sizes after.")
+                .with_boundaries(COMPRESSION_RATIO_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+        }
+    }
+
+    /// start recording a compressed response body with `attributes`
+    pub(crate) fn start(&self, uncompressed: UncompressedBytes, attributes: Vec<KeyValue>) -> CompressedBody {
+        CompressedBody {
+            metrics: self.clone(),
+            uncompressed,
+            compressed: 0,
+            attributes,
+        }
+    }
+}
+
+/// a compressed response body in flight, recorded once it is sent to its end
+pub(crate) struct CompressedBody {
+    metrics: CompressionMetrics,
+    uncompressed: UncompressedBytes,
+    compressed: u64,
+    attributes: Vec<KeyValue>,
+}
+
+impl CompressedBody {
+    pub(crate) fn count(&mut self, len: usize) {
+        self.compressed += len as u64;
+    }
+
+    /// record the sizes and their ratio, unless nothing was counted beneath the compression layer
+    pub(crate) fn finish(self) {
+        let uncompressed = self.uncompressed.get();
+        if uncompressed == 0 || self.compressed == 0 {
+            return;
+        }
+        self.metrics.uncompressed_size.record(uncompressed as f64, &self.attributes);
+        self.metrics.compressed_size.record(self.compressed as f64, &self.attributes);
+        self.metrics
+            .ratio
+            .record(uncompressed as f64 / self.compressed as f64, &self.attributes);
+    }
+}
+
+/// A tower layer counting the response body bytes before they are compressed, for the compression metrics
+/// enabled with [crate::HttpMetricsLayerBuilder::with_compression_metrics].
+///
+/// add it beneath the compression layer, itself beneath the [crate::HttpMetricsLayer]:
+///
+/// ```rust,ignore
+/// let app = Router::new()
+///     .route("/", get(handler))
+///     .layer(UncompressedSizeLayer::new())
+///     .layer(CompressionLayer::new())
+///     .layer(metrics);
+/// ```
+///
+/// requests that did not go through the metrics layer are passed through.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UncompressedSizeLayer;
+
+impl UncompressedSizeLayer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<S> Layer<S> for UncompressedSizeLayer {
+    type Service = UncompressedSize<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        UncompressedSize { service }
+    }
+}
+
+/// The service of [UncompressedSizeLayer].
+#[derive(Clone)]
+pub struct UncompressedSize<S> {
+    service: S,
+}
+
+impl<S, R, ResBody> Service<Request<R>> for UncompressedSize<S>
+where
+    S: Service<Request<R>, Response = Response<ResBody>>,
+    ResBody: Body,
+{
+    type Response = Response<UncompressedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = UncompressedSizeFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<R>) -> Self::Future {
+        let bytes = req.extensions().get::<UncompressedBytes>().cloned();
+        UncompressedSizeFuture {
+            inner: self.service.call(req),
+            bytes,
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [UncompressedSize].
+    pub struct UncompressedSizeFuture<F> {
+        #[pin]
+        inner: F,
+        bytes: Option<UncompressedBytes>,
+    }
+}
+
+impl<F, B, E> Future for UncompressedSizeFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<UncompressedBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = ready!(this.inner.poll(cx))?;
+        let bytes = this.bytes.take();
+        Poll::Ready(Ok(response.map(|inner| UncompressedBody { inner, bytes })))
+    }
+}
+
+pin_project! {
+    /// The response body of [UncompressedSize], counting the bytes of the inner body.
+    pub struct UncompressedBody<B> {
+        #[pin]
+        inner: B,
+        bytes: Option<UncompressedBytes>,
+    }
+}
+
+impl<B: Body> Body for UncompressedBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        if let (Some(Ok(frame)), Some(bytes)) = (&frame, this.bytes.as_ref()) {
+            if let Some(data) = frame.data_ref() {
+                bytes.add(data.remaining());
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_encoding() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(content_encoding(&headers), None);
+        headers.insert(http::header::CONTENT_ENCODING, "identity".parse().unwrap());
+        assert_eq!(content_encoding(&headers), None);
+        headers.insert(http::header::CONTENT_ENCODING, "gzip".parse().unwrap());
+        assert_eq!(content_encoding(&headers), Some("gzip"));
+    }
+}
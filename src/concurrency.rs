@@ -0,0 +1,158 @@
+//! metrics of the tower concurrency limit and load shedding layers
+//!
+//! ref https://docs.rs/tower/latest/tower/limit/concurrency/struct.ConcurrencyLimit.html
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures_util::ready;
+use opentelemetry::metrics::{Counter, Histogram};
+use pin_project_lite::pin_project;
+use tower::load_shed::error::Overloaded;
+use tower::{BoxError, Layer, Service};
+
+use crate::{AppMetrics, HTTP_REQ_DURATION_HISTOGRAM_BUCKETS};
+
+/// the concurrency instruments
+struct ConcurrencyInstruments {
+    permit_wait: Histogram<f64>,
+    shed: Counter<u64>,
+}
+
+/// A tower layer recording the requests held back by the layers beneath it:
+///
+/// - `http.server.concurrency.wait`: histogram of the time spent waiting for the inner service to be ready,
+///   e.g. for a permit of a `ConcurrencyLimit`
+/// - `http.server.shed_requests`: counter of the requests rejected with the `Overloaded` error of a `LoadShed`
+///
+/// a `LoadShed` is always ready, so add one instance outside of the `LoadShed` to count the shed requests,
+/// and one right outside of the `ConcurrencyLimit` to time the permits when the requests are queued instead:
+///
+/// ```rust,ignore
+/// let app = Router::new().route("/", get(handler)).layer(
+///     ServiceBuilder::new()
+///         .layer(HandleErrorLayer::new(|_: BoxError| async { StatusCode::SERVICE_UNAVAILABLE }))
+///         .layer(metrics.concurrency_layer())
+///         .layer(LoadShedLayer::new())
+///         .layer(ConcurrencyLimitLayer::new(64)),
+/// );
+/// ```
+///
+/// the errors of the inner service are boxed, like the ones of the tower layers.
+///
+/// create it with [crate::HttpMetricsLayer::concurrency_layer] to share the provider and registry of the server metrics.
+#[derive(Clone)]
+pub struct ConcurrencyMetricsLayer {
+    instruments: Arc<ConcurrencyInstruments>,
+}
+
+impl ConcurrencyMetricsLayer {
+    pub(crate) fn new(metrics: &AppMetrics) -> Self {
+        let instruments = ConcurrencyInstruments {
+            permit_wait: metrics
+                .histogram("http.server.concurrency.wait")
+                .with_unit("s")
+                .with_description("The time the HTTP requests waited for the inner service to be ready in seconds.")
+                .with_boundaries(HTTP_REQ_DURATION_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            shed: metrics
+                .counter("http.server.shed_requests")
+                .with_description("The number of HTTP requests rejected by the load shedding.")
+                .init(),
+        };
+        Self {
+            instruments: Arc::new(instruments),
+        }
+    }
+}
+
+impl<S> Layer<S> for ConcurrencyMetricsLayer {
+    type Service = ConcurrencyMetrics<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        ConcurrencyMetrics {
+            instruments: self.instruments.clone(),
+            service,
+            waiting_since: None,
+        }
+    }
+}
+
+/// The service of [ConcurrencyMetricsLayer].
+pub struct ConcurrencyMetrics<S> {
+    instruments: Arc<ConcurrencyInstruments>,
+    service: S,
+    /// the first `poll_ready` of the request to come
+    waiting_since: Option<Instant>,
+}
+
+impl<S: Clone> Clone for ConcurrencyMetrics<S> {
+    fn clone(&self) -> Self {
+        Self {
+            instruments: self.instruments.clone(),
+            service: self.service.clone(),
+            // a clone waits for a permit of its own
+            waiting_since: None,
+        }
+    }
+}
+
+impl<S, Request> Service<Request> for ConcurrencyMetrics<S>
+where
+    S: Service<Request>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ConcurrencyFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let waiting_since = *self.waiting_since.get_or_insert_with(Instant::now);
+        let result = ready!(self.service.poll_ready(cx));
+        self.waiting_since = None;
+        if result.is_ok() {
+            self.instruments
+                .permit_wait
+                .record(waiting_since.elapsed().as_secs_f64(), &[]);
+        }
+        Poll::Ready(result.map_err(Into::into))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        ConcurrencyFuture {
+            inner: self.service.call(req),
+            instruments: self.instruments.clone(),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [ConcurrencyMetrics].
+    pub struct ConcurrencyFuture<F> {
+        #[pin]
+        inner: F,
+        instruments: Arc<ConcurrencyInstruments>,
+    }
+}
+
+impl<F, T, E> Future for ConcurrencyFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx)).map_err(Into::into);
+        if let Err(ref err) = result {
+            if err.is::<Overloaded>() {
+                this.instruments.shed.add(1, &[]);
+            }
+        }
+        Poll::Ready(result)
+    }
+}
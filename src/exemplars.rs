@@ -0,0 +1,168 @@
+//! exemplars linking the HTTP histograms to example traces
+//!
+//! the OTel SDK does not sample exemplars yet and the prometheus crate can not encode them,
+//! so the latest exemplar of every histogram series is kept here
+//! and attached to the matching bucket by the OpenMetrics exposition.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use opentelemetry::trace::SpanContext;
+use opentelemetry::KeyValue;
+use prometheus::proto::LabelPair;
+
+/// the number of series an instrument keeps exemplars for, the extra series are ignored
+const MAX_SERIES: usize = 10_000;
+
+/// an example measurement and the trace it was recorded in
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Exemplar {
+    pub(crate) trace_id: String,
+    pub(crate) span_id: String,
+    pub(crate) value: f64,
+    /// seconds since unix epoch
+    pub(crate) timestamp: f64,
+}
+
+/// the latest exemplar of every series of a histogram
+#[derive(Debug)]
+pub(crate) struct Series {
+    /// the instrument name as it appears in the prometheus metric names
+    name: String,
+    /// the prometheus label names of all the attributes recorded so far
+    label_names: RwLock<HashSet<String>>,
+    latest: RwLock<HashMap<Vec<(String, String)>, Exemplar>>,
+}
+
+impl Series {
+    fn new(instrument_name: &str) -> Self {
+        Self {
+            name: sanitize(instrument_name),
+            label_names: RwLock::new(HashSet::new()),
+            latest: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// keep `value` as the exemplar of the series of `attributes` if `span_context` is sampled
+    pub(crate) fn record(&self, attributes: &[KeyValue], value: f64, span_context: &SpanContext) {
+        if !span_context.is_valid() || !span_context.is_sampled() {
+            return;
+        }
+        let mut key: Vec<(String, String)> = attributes
+            .iter()
+            .map(|kv| (sanitize(kv.key.as_str()), kv.value.as_str().into_owned()))
+            .collect();
+        key.sort();
+
+        let mut latest = self.latest.write().unwrap();
+        if latest.len() >= MAX_SERIES && !latest.contains_key(&key) {
+            return;
+        }
+        {
+            let mut label_names = self.label_names.write().unwrap();
+            for (name, _) in key.iter() {
+                if !label_names.contains(name) {
+                    label_names.insert(name.clone());
+                }
+            }
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        latest.insert(
+            key,
+            Exemplar {
+                trace_id: span_context.trace_id().to_string(),
+                span_id: span_context.span_id().to_string(),
+                value,
+                timestamp,
+            },
+        );
+    }
+
+    /// the latest exemplar of the prometheus series `family_name{labels}`
+    ///
+    /// the labels added by the exporter, e.g. the scope or the registry const labels, are ignored.
+    fn find(&self, family_name: &str, labels: &[LabelPair]) -> Option<Exemplar> {
+        if !family_name.contains(self.name.as_str()) {
+            return None;
+        }
+        let label_names = self.label_names.read().unwrap();
+        let mut key: Vec<(String, String)> = labels
+            .iter()
+            .filter(|label| label_names.contains(label.get_name()))
+            .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+            .collect();
+        key.sort();
+        self.latest.read().unwrap().get(&key).cloned()
+    }
+}
+
+/// the exemplars of the duration and size histograms
+#[derive(Debug)]
+pub(crate) struct ExemplarStore {
+    pub(crate) duration: Series,
+    pub(crate) request_size: Series,
+    pub(crate) response_size: Series,
+}
+
+impl ExemplarStore {
+    pub(crate) fn new(duration: &str, request_size: &str, response_size: &str) -> Self {
+        Self {
+            duration: Series::new(duration),
+            request_size: Series::new(request_size),
+            response_size: Series::new(response_size),
+        }
+    }
+
+    /// the latest exemplar of the prometheus histogram series `family_name{labels}`
+    pub(crate) fn find(&self, family_name: &str, labels: &[LabelPair]) -> Option<Exemplar> {
+        [&self.duration, &self.request_size, &self.response_size]
+            .into_iter()
+            .find_map(|series| series.find(family_name, labels))
+    }
+}
+
+/// the prometheus exporter replaces the characters not allowed in metric and label names with `_`
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanId, TraceFlags, TraceId, TraceState};
+
+    #[test]
+    fn test_find_exemplar() {
+        let store = ExemplarStore::new("http.server.request.duration", "http.server.request.size", "http.server.response.size");
+        let span_context = SpanContext::new(
+            TraceId::from_u128(1),
+            SpanId::from_u64(2),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        store
+            .duration
+            .record(&[KeyValue::new("http.route", "/users/:id")], 0.042, &span_context);
+
+        let mut route = LabelPair::new();
+        route.set_name("http_route".to_string());
+        route.set_value("/users/:id".to_string());
+        let mut scope = LabelPair::new();
+        scope.set_name("otel_scope_name".to_string());
+        scope.set_value("axum-otel-metrics".to_string());
+
+        let exemplar = store
+            .find("http_server_request_duration_seconds", &[route.clone(), scope])
+            .unwrap();
+        assert_eq!(exemplar.value, 0.042);
+        assert_eq!(exemplar.span_id, "0000000000000002");
+        assert!(store.find("http_server_request_size_bytes", &[route]).is_none());
+    }
+}
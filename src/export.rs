@@ -0,0 +1,76 @@
+//! observes the exports of the push (otlp) pipeline
+//!
+//! the [PeriodicReader](opentelemetry_sdk::metrics::PeriodicReader) only reports a failed export
+//! to the global error handler, so a broken push pipeline goes unnoticed unless someone reads the logs.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use opentelemetry::metrics::{MetricsError, Result};
+use opentelemetry_sdk::metrics::data::{ResourceMetrics, Temporality};
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::metrics::reader::TemporalitySelector;
+use opentelemetry_sdk::metrics::InstrumentKind;
+
+/// called with the error of every failed export
+pub type ExportErrorFn = dyn Fn(&MetricsError) + Send + Sync;
+
+/// the number of export attempts and failures, shared with the observable counters
+#[derive(Debug, Default)]
+pub(crate) struct ExportStats {
+    attempts: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl ExportStats {
+    pub(crate) fn attempts(&self) -> u64 {
+        self.attempts.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+
+/// a [PushMetricsExporter] counting the exports of the wrapped exporter
+pub(crate) struct ObservedExporter<E> {
+    inner: E,
+    stats: Arc<ExportStats>,
+    on_error: Option<Arc<ExportErrorFn>>,
+}
+
+impl<E> ObservedExporter<E> {
+    pub(crate) fn new(inner: E, stats: Arc<ExportStats>, on_error: Option<Arc<ExportErrorFn>>) -> Self {
+        Self { inner, stats, on_error }
+    }
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for ObservedExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for ObservedExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> Result<()> {
+        self.stats.attempts.fetch_add(1, Ordering::Relaxed);
+        let result = self.inner.export(metrics).await;
+        if let Err(ref err) = result {
+            self.stats.failures.fetch_add(1, Ordering::Relaxed);
+            if let Some(ref on_error) = self.on_error {
+                on_error(err);
+            }
+        }
+        result
+    }
+
+    async fn force_flush(&self) -> Result<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.inner.shutdown()
+    }
+}
@@ -0,0 +1,449 @@
+//! the exposition formats of the metrics endpoint, negotiated with the `Accept` request header
+//!
+//! the prometheus crate encodes the classic text and the delimited protobuf formats,
+//! the OpenMetrics text format is encoded here from the gathered metric families.
+
+use std::fmt::Write;
+
+use axum::body::Bytes;
+use axum::http::HeaderMap;
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+
+use crate::exemplars::{Exemplar, ExemplarStore};
+
+pub(crate) const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+pub(crate) const TEXT_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+pub(crate) const PROTOBUF_CONTENT_TYPE: &str =
+    "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited";
+
+/// the exposition formats served by the metrics endpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// the classic prometheus text format
+    Text,
+    /// the OpenMetrics text format
+    OpenMetrics,
+    /// the length delimited `io.prometheus.client.MetricFamily` protobuf messages
+    Protobuf,
+}
+
+impl Format {
+    pub(crate) fn content_type(&self) -> &'static str {
+        match self {
+            Format::Text => TEXT_CONTENT_TYPE,
+            Format::OpenMetrics => OPENMETRICS_CONTENT_TYPE,
+            Format::Protobuf => PROTOBUF_CONTENT_TYPE,
+        }
+    }
+}
+
+/// the encoded payload of the metrics endpoint
+#[derive(Clone, Debug)]
+pub(crate) struct Encoded {
+    /// `None` when the encoding was skipped because the scraper already has this payload
+    pub(crate) body: Option<Bytes>,
+    /// the `ETag` of the payload when the conditional GET is enabled
+    pub(crate) etag: Option<String>,
+}
+
+/// pick the format with the highest quality in the `Accept` headers, the classic text format wins ties
+/// and is the fallback when nothing supported is accepted
+pub(crate) fn negotiate(headers: &HeaderMap) -> Format {
+    let mut best = (Format::Text, 0.0_f32);
+    let values = headers
+        .get_all(http::header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok());
+    for item in values.flat_map(|value| value.split(',')) {
+        let mut parts = item.split(';');
+        let media_type = parts.next().unwrap_or_default().trim();
+        let params: Vec<(&str, &str)> = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .collect();
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| *value)
+        };
+        let quality = param("q").and_then(|q| q.parse::<f32>().ok()).unwrap_or(1.0);
+        let format = if media_type.eq_ignore_ascii_case("application/openmetrics-text") {
+            Format::OpenMetrics
+        } else if media_type.eq_ignore_ascii_case("text/plain") {
+            Format::Text
+        } else if media_type.eq_ignore_ascii_case("application/vnd.google.protobuf")
+            && param("proto") == Some("io.prometheus.client.MetricFamily")
+            && param("encoding") == Some("delimited")
+        {
+            Format::Protobuf
+        } else {
+            continue;
+        };
+        if quality > best.1 {
+            best = (format, quality);
+        }
+    }
+    best.0
+}
+
+/// encode `families` in the OpenMetrics text format, without the trailing `# EOF`
+/// so the families of several registries can be concatenated
+///
+/// the histogram series with an exemplar in `exemplars` get it attached to the bucket of its value.
+pub(crate) fn encode_openmetrics(families: &[MetricFamily], exemplars: Option<&ExemplarStore>, buffer: &mut String) {
+    for family in families {
+        let name = family.get_name();
+        let metric_type = family.get_field_type();
+        // the counter family name has no `_total` suffix, only its samples have
+        let family_name = match metric_type {
+            MetricType::COUNTER => name.strip_suffix("_total").unwrap_or(name),
+            _ => name,
+        };
+        let type_name = match metric_type {
+            MetricType::COUNTER => "counter",
+            MetricType::GAUGE => "gauge",
+            MetricType::HISTOGRAM => "histogram",
+            MetricType::SUMMARY => "summary",
+            MetricType::UNTYPED => "unknown",
+        };
+        if !family.get_help().is_empty() {
+            let _ = writeln!(buffer, "# HELP {} {}", family_name, escape(family.get_help()));
+        }
+        let _ = writeln!(buffer, "# TYPE {} {}", family_name, type_name);
+
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+            let timestamp = metric.get_timestamp_ms();
+            match metric_type {
+                MetricType::COUNTER => {
+                    let name = format!("{}_total", family_name);
+                    let value = metric.get_counter().get_value();
+                    sample(buffer, &name, labels, None, value, timestamp, None);
+                }
+                MetricType::GAUGE => {
+                    sample(buffer, name, labels, None, metric.get_gauge().get_value(), timestamp, None);
+                }
+                MetricType::UNTYPED => {
+                    sample(buffer, name, labels, None, metric.get_untyped().get_value(), timestamp, None);
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let mut exemplar = exemplars.and_then(|exemplars| exemplars.find(name, labels));
+                    let bucket_name = format!("{}_bucket", name);
+                    let mut has_inf = false;
+                    for bucket in histogram.get_bucket() {
+                        let upper_bound = bucket.get_upper_bound();
+                        has_inf |= upper_bound == f64::INFINITY;
+                        let le = ("le", float(upper_bound));
+                        let count = bucket.get_cumulative_count() as f64;
+                        // the exemplar goes to the first bucket containing its value
+                        let bucket_exemplar = if exemplar.as_ref().is_some_and(|e| e.value <= upper_bound) {
+                            exemplar.take()
+                        } else {
+                            None
+                        };
+                        sample(buffer, &bucket_name, labels, Some(le), count, timestamp, bucket_exemplar.as_ref());
+                    }
+                    if !has_inf {
+                        let le = ("le", "+Inf".to_string());
+                        let count = histogram.get_sample_count() as f64;
+                        sample(buffer, &bucket_name, labels, Some(le), count, timestamp, exemplar.as_ref());
+                    }
+                    let sum_name = format!("{}_sum", name);
+                    sample(buffer, &sum_name, labels, None, histogram.get_sample_sum(), timestamp, None);
+                    let count_name = format!("{}_count", name);
+                    let count = histogram.get_sample_count() as f64;
+                    sample(buffer, &count_name, labels, None, count, timestamp, None);
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        let label = ("quantile", float(quantile.get_quantile()));
+                        sample(buffer, name, labels, Some(label), quantile.get_value(), timestamp, None);
+                    }
+                    let sum_name = format!("{}_sum", name);
+                    sample(buffer, &sum_name, labels, None, summary.get_sample_sum(), timestamp, None);
+                    let count_name = format!("{}_count", name);
+                    let count = summary.get_sample_count() as f64;
+                    sample(buffer, &count_name, labels, None, count, timestamp, None);
+                }
+            }
+        }
+    }
+}
+
+/// the metric families as JSON, for the dashboards and tests that do not want to parse the text format
+///
+/// `[{"name", "help", "type", "metrics": [{"labels": {..}, "value"}]}]`, the histograms have
+/// `count`, `sum` and cumulative `buckets` instead of `value`, the summaries `count`, `sum` and `quantiles`.
+pub(crate) fn encode_json(families: &[MetricFamily]) -> serde_json::Value {
+    use serde_json::{json, Map, Value};
+
+    let families = families.iter().map(|family| {
+        let metrics = family.get_metric().iter().map(|metric| {
+            let labels: Map<String, Value> = metric
+                .get_label()
+                .iter()
+                .map(|l| (l.get_name().to_string(), Value::from(l.get_value())))
+                .collect();
+            match family.get_field_type() {
+                MetricType::COUNTER => json!({"labels": labels, "value": metric.get_counter().get_value()}),
+                MetricType::GAUGE => json!({"labels": labels, "value": metric.get_gauge().get_value()}),
+                MetricType::UNTYPED => json!({"labels": labels, "value": metric.get_untyped().get_value()}),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let buckets: Vec<Value> = histogram
+                        .get_bucket()
+                        .iter()
+                        .map(|b| json!({"le": json_float(b.get_upper_bound()), "count": b.get_cumulative_count()}))
+                        .collect();
+                    json!({
+                        "labels": labels,
+                        "count": histogram.get_sample_count(),
+                        "sum": histogram.get_sample_sum(),
+                        "buckets": buckets,
+                    })
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    let quantiles: Vec<Value> = summary
+                        .get_quantile()
+                        .iter()
+                        .map(|q| json!({"quantile": q.get_quantile(), "value": json_float(q.get_value())}))
+                        .collect();
+                    json!({
+                        "labels": labels,
+                        "count": summary.get_sample_count(),
+                        "sum": summary.get_sample_sum(),
+                        "quantiles": quantiles,
+                    })
+                }
+            }
+        });
+        let type_name = match family.get_field_type() {
+            MetricType::COUNTER => "counter",
+            MetricType::GAUGE => "gauge",
+            MetricType::HISTOGRAM => "histogram",
+            MetricType::SUMMARY => "summary",
+            MetricType::UNTYPED => "untyped",
+        };
+        json!({
+            "name": family.get_name(),
+            "help": family.get_help(),
+            "type": type_name,
+            "metrics": metrics.collect::<Vec<_>>(),
+        })
+    });
+    Value::Array(families.collect())
+}
+
+/// a weak `ETag` of `families` served as `format`, from a hash of the gathered values
+///
+/// the families are hashed instead of the encoded payload, so an unchanged registry is detected
+/// without paying for the encoding. the tag is weak since the compressed and uncompressed
+/// representations share it.
+pub(crate) fn etag<'a>(format: Format, families: impl IntoIterator<Item = &'a MetricFamily>) -> String {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    format.content_type().hash(&mut hasher);
+    for family in families {
+        family.get_name().hash(&mut hasher);
+        family.get_help().hash(&mut hasher);
+        (family.get_field_type() as i32).hash(&mut hasher);
+        for metric in family.get_metric() {
+            for label in metric.get_label() {
+                label.get_name().hash(&mut hasher);
+                label.get_value().hash(&mut hasher);
+            }
+            metric.get_timestamp_ms().hash(&mut hasher);
+            match family.get_field_type() {
+                MetricType::COUNTER => metric.get_counter().get_value().to_bits().hash(&mut hasher),
+                MetricType::GAUGE => metric.get_gauge().get_value().to_bits().hash(&mut hasher),
+                MetricType::UNTYPED => metric.get_untyped().get_value().to_bits().hash(&mut hasher),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    histogram.get_sample_count().hash(&mut hasher);
+                    histogram.get_sample_sum().to_bits().hash(&mut hasher);
+                    for bucket in histogram.get_bucket() {
+                        bucket.get_upper_bound().to_bits().hash(&mut hasher);
+                        bucket.get_cumulative_count().hash(&mut hasher);
+                    }
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    summary.get_sample_count().hash(&mut hasher);
+                    summary.get_sample_sum().to_bits().hash(&mut hasher);
+                    for quantile in summary.get_quantile() {
+                        quantile.get_quantile().to_bits().hash(&mut hasher);
+                        quantile.get_value().to_bits().hash(&mut hasher);
+                    }
+                }
+            }
+        }
+    }
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
+/// whether the `If-None-Match` headers match `etag`, with the weak comparison of RFC 9110
+pub(crate) fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+    headers
+        .get_all(http::header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+/// JSON has no infinite or NaN numbers, they are encoded as strings like in the text format
+fn json_float(value: f64) -> serde_json::Value {
+    if value.is_finite() {
+        serde_json::Value::from(value)
+    } else {
+        serde_json::Value::from(float(value))
+    }
+}
+
+fn sample(
+    buffer: &mut String,
+    name: &str,
+    labels: &[LabelPair],
+    extra: Option<(&str, String)>,
+    value: f64,
+    timestamp_ms: i64,
+    exemplar: Option<&Exemplar>,
+) {
+    buffer.push_str(name);
+    if !labels.is_empty() || extra.is_some() {
+        buffer.push('{');
+        let labels = labels.iter().map(|l| (l.get_name(), l.get_value()));
+        let extra = extra.as_ref().map(|(name, value)| (*name, value.as_str()));
+        for (i, (name, value)) in labels.chain(extra).enumerate() {
+            if i > 0 {
+                buffer.push(',');
+            }
+            let _ = write!(buffer, "{}=\"{}\"", name, escape(value));
+        }
+        buffer.push('}');
+    }
+    let _ = write!(buffer, " {}", float(value));
+    // OpenMetrics timestamps are in seconds
+    if timestamp_ms != 0 {
+        let _ = write!(buffer, " {}", float(timestamp_ms as f64 / 1000.0));
+    }
+    if let Some(exemplar) = exemplar {
+        let _ = write!(
+            buffer,
+            " # {{trace_id=\"{}\",span_id=\"{}\"}} {} {}",
+            exemplar.trace_id,
+            exemplar.span_id,
+            float(exemplar.value),
+            float(exemplar.timestamp)
+        );
+    }
+    buffer.push('\n');
+}
+
+fn float(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else if value.is_nan() {
+        "NaN".to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(negotiate(&headers), Format::Text);
+        headers.insert(
+            http::header::ACCEPT,
+            "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5,*/*;q=0.1"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(negotiate(&headers), Format::OpenMetrics);
+        headers.insert(http::header::ACCEPT, "text/plain".parse().unwrap());
+        assert_eq!(negotiate(&headers), Format::Text);
+        headers.insert(
+            http::header::ACCEPT,
+            "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=delimited;q=0.7,text/plain;q=0.3"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(negotiate(&headers), Format::Protobuf);
+        headers.insert(http::header::ACCEPT, "application/vnd.google.protobuf".parse().unwrap());
+        assert_eq!(negotiate(&headers), Format::Text);
+    }
+
+    #[test]
+    fn test_encode_openmetrics() {
+        let registry = prometheus::Registry::new();
+        let counter = prometheus::IntCounterVec::new(
+            prometheus::Opts::new("requests_total", "The number of requests."),
+            &["method"],
+        )
+        .unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        counter.with_label_values(&["GET"]).inc();
+
+        let mut buffer = String::new();
+        encode_openmetrics(&registry.gather(), None, &mut buffer);
+        assert_eq!(
+            buffer,
+            "# HELP requests The number of requests.\n# TYPE requests counter\nrequests_total{method=\"GET\"} 1.0\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_json() {
+        let registry = prometheus::Registry::new();
+        let histogram = prometheus::Histogram::with_opts(
+            prometheus::HistogramOpts::new("latency_seconds", "The latencies.").buckets(vec![0.1, 1.0]),
+        )
+        .unwrap();
+        registry.register(Box::new(histogram.clone())).unwrap();
+        histogram.observe(0.5);
+
+        let json = encode_json(&registry.gather());
+        assert_eq!(json[0]["name"], "latency_seconds");
+        assert_eq!(json[0]["type"], "histogram");
+        assert_eq!(json[0]["metrics"][0]["count"], 1);
+        assert_eq!(json[0]["metrics"][0]["buckets"][1]["le"], 1.0);
+        assert_eq!(json[0]["metrics"][0]["buckets"][1]["count"], 1);
+    }
+
+    #[test]
+    fn test_etag() {
+        let registry = prometheus::Registry::new();
+        let counter = prometheus::IntCounter::new("requests_total", "The number of requests.").unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+
+        let etag = super::etag(Format::Text, &registry.gather());
+        assert_eq!(etag, super::etag(Format::Text, &registry.gather()));
+        assert_ne!(etag, super::etag(Format::OpenMetrics, &registry.gather()));
+
+        let mut headers = HeaderMap::new();
+        assert!(!etag_matches(&headers, &etag));
+        headers.insert(http::header::IF_NONE_MATCH, format!("\"other\", {}", etag).parse().unwrap());
+        assert!(etag_matches(&headers, &etag));
+
+        counter.inc();
+        assert!(!etag_matches(&headers, &super::etag(Format::Text, &registry.gather())));
+    }
+}
@@ -0,0 +1,109 @@
+//! attributes of the GraphQL operations, reported by the GraphQL handler in the response extensions
+//!
+//! ref https://opentelemetry.io/docs/specs/semconv/graphql/graphql-spans/
+
+use opentelemetry::metrics::{Counter, Meter};
+use opentelemetry::KeyValue;
+
+use crate::recorder::{RecordedResponse, ResponseRecorder};
+use crate::{HttpInstrument, HttpMetricsLayerBuilder, MetricState};
+
+/// The GraphQL operation served by a request.
+///
+/// The GraphQL handler inserts it in the response extensions, the
+/// metrics layer then records the `graphql.operation.name` and
+/// `graphql.operation.type` attributes and counts the `errors`,
+/// see [crate::HttpMetricsLayerBuilder::with_graphql_metrics].
+///
+/// ```
+/// use axum::{response::IntoResponse, Extension};
+/// use axum_otel_metrics::{GraphQlOperation, GraphQlOperationType};
+///
+/// async fn graphql() -> impl IntoResponse {
+///     // e.g. from the async-graphql request and response
+///     let operation = GraphQlOperation {
+///         name: Some("GetUser".to_string()),
+///         operation_type: GraphQlOperationType::Query,
+///         errors: 0,
+///     };
+///     (Extension(operation), "{\"data\":{}}")
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraphQlOperation {
+    /// the operation name, `None` for an anonymous operation
+    pub name: Option<String>,
+
+    /// the operation type
+    pub operation_type: GraphQlOperationType,
+
+    /// the number of GraphQL errors in the response, which is usually still a `200 OK`
+    pub errors: u64,
+}
+
+/// The type of a [GraphQlOperation].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphQlOperationType {
+    Query,
+    Mutation,
+    Subscription,
+}
+
+impl GraphQlOperationType {
+    /// the `graphql.operation.type` attribute value
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GraphQlOperationType::Query => "query",
+            GraphQlOperationType::Mutation => "mutation",
+            GraphQlOperationType::Subscription => "subscription",
+        }
+    }
+}
+
+impl GraphQlOperation {
+    /// the `graphql.operation.name` and `graphql.operation.type` attributes, anonymous operations have no name
+    pub(crate) fn attributes(&self) -> Vec<KeyValue> {
+        let mut attributes = vec![KeyValue::new("graphql.operation.type", self.operation_type.as_str())];
+        if let Some(ref name) = self.name {
+            attributes.push(KeyValue::new("graphql.operation.name", name.clone()));
+        }
+        attributes
+    }
+}
+
+/// records the operation attributes on the request metrics and counts the GraphQL errors
+pub(crate) struct GraphQlMetrics {
+    errors: Counter<u64>,
+}
+
+impl GraphQlMetrics {
+    /// `None` without [HttpMetricsLayerBuilder::with_graphql_metrics]
+    pub(crate) fn new(builder: &HttpMetricsLayerBuilder, meter: &Meter) -> Option<Self> {
+        builder.graphql_metrics.then(|| Self {
+            errors: meter
+                .u64_counter(builder.instrument_name("graphql.errors"))
+                .with_unit(builder.unit(HttpInstrument::GraphQlErrors, ""))
+                .with_description(builder.description(
+                    HttpInstrument::GraphQlErrors,
+                    "The number of errors in the GraphQL responses.",
+                ))
+                .init(),
+        })
+    }
+}
+
+impl ResponseRecorder for GraphQlMetrics {
+    fn record(&self, state: &MetricState, response: &RecordedResponse<'_>) {
+        if let Some(operation) = response.extensions.get::<GraphQlOperation>().filter(|o| o.errors > 0) {
+            let mut attributes = response.route_attributes();
+            attributes.extend(operation.attributes());
+            self.errors.add(operation.errors, &state.attributes(attributes));
+        }
+    }
+
+    fn request_attributes(&self, response: &RecordedResponse<'_>, attributes: &mut Vec<KeyValue>) {
+        if let Some(operation) = response.extensions.get::<GraphQlOperation>() {
+            attributes.extend(operation.attributes());
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! metrics of the gRPC calls, following the RPC semantic conventions
+//!
+//! ref https://opentelemetry.io/docs/specs/semconv/rpc/rpc-metrics/
+
+use std::time::Instant;
+
+use axum::http::HeaderMap;
+use opentelemetry::metrics::Histogram;
+use opentelemetry::KeyValue;
+
+/// the gRPC status of a call cancelled before its response body was done
+pub(crate) const STATUS_CANCELLED: i64 = 1;
+
+/// the gRPC status of a call whose response carries no `grpc-status`
+pub(crate) const STATUS_UNKNOWN: i64 = 2;
+
+/// the `rpc.server.duration` buckets in milliseconds, the HTTP duration buckets scaled
+pub(crate) const RPC_SERVER_DURATION_HISTOGRAM_BUCKETS: &[f64] = &[
+    0.0, 5.0, 10.0, 25.0, 50.0, 75.0, 100.0, 250.0, 500.0, 750.0, 1000.0, 2500.0, 5000.0, 7500.0, 10000.0,
+];
+
+/// whether the request is a gRPC call, i.e. its content type is `application/grpc` or `application/grpc+<codec>`
+pub(crate) fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v == "application/grpc" || v.starts_with("application/grpc+") || v.starts_with("application/grpc;"))
+}
+
+/// the service and the method of a gRPC call path, e.g. `/helloworld.Greeter/SayHello`
+pub(crate) fn parse_path(path: &str) -> Option<(String, String)> {
+    let (service, method) = path.strip_prefix('/')?.split_once('/')?;
+    if service.is_empty() || method.is_empty() || method.contains('/') {
+        return None;
+    }
+    Some((service.to_string(), method.to_string()))
+}
+
+/// the `grpc-status` of the response headers or trailers
+pub(crate) fn status(headers: &HeaderMap) -> Option<i64> {
+    headers.get("grpc-status")?.to_str().ok()?.parse().ok()
+}
+
+/// a gRPC call in flight, recorded once its status is known
+pub(crate) struct GrpcCall {
+    pub(crate) duration: Histogram<f64>,
+    /// the attributes of the call, without the status
+    pub(crate) attributes: Vec<KeyValue>,
+    pub(crate) start: Instant,
+}
+
+impl GrpcCall {
+    pub(crate) fn finish(mut self, status: i64) {
+        self.attributes.push(KeyValue::new("rpc.grpc.status_code", status));
+        let latency = self.start.elapsed().as_secs_f64() * 1000.0;
+        self.duration.record(latency, &self.attributes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            parse_path("/helloworld.Greeter/SayHello"),
+            Some(("helloworld.Greeter".to_string(), "SayHello".to_string()))
+        );
+        assert_eq!(parse_path("/helloworld.Greeter"), None);
+        assert_eq!(parse_path("/a/b/c"), None);
+    }
+
+    #[test]
+    fn test_is_grpc() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_grpc(&headers));
+        headers.insert(http::header::CONTENT_TYPE, "application/grpc+proto".parse().unwrap());
+        assert!(is_grpc(&headers));
+        headers.insert(http::header::CONTENT_TYPE, "application/grpc-web".parse().unwrap());
+        assert!(!is_grpc(&headers));
+    }
+}
@@ -0,0 +1,103 @@
+//! runtime reconfiguration of the metrics layer, e.g. to silence a noisy path during an incident without a redeploy
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+
+use crate::{PathSkipper, SampleRate};
+
+/// a value shared by the layer and its [MetricsHandle], replaced as a whole
+#[derive(Debug)]
+pub(crate) struct Swappable<T>(Arc<ArcSwap<T>>);
+
+impl<T> Clone for Swappable<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Swappable<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(value)))
+    }
+
+    /// the current value, neither locked nor cloned
+    pub(crate) fn load(&self) -> Arc<T> {
+        self.0.load_full()
+    }
+
+    pub(crate) fn store(&self, value: T) {
+        self.0.store(Arc::new(value));
+    }
+}
+
+/// the named latency thresholds of the `http.server.slow_requests` counter
+pub(crate) type SlowRequestThresholds = Vec<(String, Duration)>;
+
+/// A handle to reconfigure the metrics layer at runtime, returned by [crate::HttpMetricsLayer::handle].
+///
+/// it is cheap to clone, e.g. to keep it in the state of an admin router, and the changes apply
+/// to the requests that start after them.
+///
+/// ```rust
+/// use axum_otel_metrics::{HttpMetricsLayerBuilder, PathSkipper};
+///
+/// let handle = HttpMetricsLayerBuilder::new().build().handle();
+/// // silence a noisy path during an incident
+/// handle.set_skipper(PathSkipper::new(|path| path.starts_with("/metrics") || path == "/poll"));
+/// handle.set_sample_rate(0.1);
+/// ```
+#[derive(Clone)]
+pub struct MetricsHandle {
+    pub(crate) skipper: Swappable<PathSkipper>,
+    pub(crate) sample_rate: SampleRate,
+    pub(crate) slow_request_thresholds: Swappable<SlowRequestThresholds>,
+}
+
+impl MetricsHandle {
+    /// replace the [PathSkipper] of the layer, see [crate::HttpMetricsLayerBuilder::with_skipper]
+    pub fn set_skipper(&self, skipper: PathSkipper) {
+        self.skipper.store(skipper);
+    }
+
+    /// returns the sampling rate of the histograms
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate.get()
+    }
+
+    /// set the sampling rate of the histograms, see [crate::HttpMetricsLayerBuilder::with_sample_rate]
+    pub fn set_sample_rate(&self, rate: f64) {
+        self.sample_rate.set(rate);
+    }
+
+    /// returns the named latency thresholds of the `http.server.slow_requests` counter
+    pub fn slow_request_thresholds(&self) -> Vec<(String, Duration)> {
+        self.slow_request_thresholds.load().to_vec()
+    }
+
+    /// replace the thresholds of the `http.server.slow_requests` counter with `threshold`,
+    /// see [crate::HttpMetricsLayerBuilder::with_slow_request_threshold]
+    pub fn set_slow_request_threshold(&self, threshold: Duration) {
+        self.set_slow_request_thresholds(vec![("slow".to_string(), threshold)]);
+    }
+
+    /// replace the named thresholds of the `http.server.slow_requests` counter,
+    /// see [crate::HttpMetricsLayerBuilder::with_named_slow_request_threshold]
+    pub fn set_slow_request_thresholds(&self, thresholds: Vec<(String, Duration)>) {
+        self.slow_request_thresholds.store(thresholds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swappable() {
+        let value = Swappable::new("before".to_string());
+        let shared = value.clone();
+        shared.store("after".to_string());
+        assert_eq!(*value.load(), "after");
+    }
+}
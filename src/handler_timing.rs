@@ -0,0 +1,177 @@
+//! split of the request duration between the middleware stack and the handler
+//!
+//! the metrics middleware inserts a [HandlerTiming] extension, read by a [HandlerTimingLayer] right above the handler,
+//! everything between the two layers, e.g. authentication or decompression, is attributed to the middleware.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::http::Request;
+use futures_util::ready;
+use opentelemetry::metrics::Histogram;
+use opentelemetry::KeyValue;
+use pin_project_lite::pin_project;
+use tower::{Layer, Service};
+
+use crate::{AppMetrics, HTTP_REQ_DURATION_HISTOGRAM_BUCKETS};
+
+/// the handler duration in nanoseconds, shared through a request extension
+/// between the metrics middleware and the [HandlerTimingLayer]
+#[derive(Clone, Debug)]
+pub(crate) struct HandlerTiming(Arc<AtomicU64>);
+
+impl HandlerTiming {
+    /// the handler was not reached
+    const NONE: u64 = u64::MAX;
+
+    pub(crate) fn new() -> Self {
+        Self(Arc::new(AtomicU64::new(Self::NONE)))
+    }
+
+    fn set(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(Self::NONE - 1);
+        self.0.store(nanos, Ordering::Relaxed);
+    }
+
+    /// the time until the handler returned its response, `None` if the [HandlerTimingLayer] was not reached
+    pub(crate) fn get(&self) -> Option<Duration> {
+        match self.0.load(Ordering::Relaxed) {
+            Self::NONE => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+}
+
+/// the instruments of the middleware and handler durations
+#[derive(Clone)]
+pub(crate) struct HandlerTimingMetrics {
+    middleware_duration: Histogram<f64>,
+    handler_duration: Histogram<f64>,
+}
+
+impl HandlerTimingMetrics {
+    pub(crate) fn new(metrics: &AppMetrics) -> Self {
+        Self {
+            middleware_duration: metrics
+                .histogram("http.server.middleware.duration")
+                .with_unit("s")
+                .with_description("The time spent in the middleware stack around the handler in seconds.")
+                .with_boundaries(HTTP_REQ_DURATION_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            handler_duration: metrics
+                .histogram("http.server.handler.duration")
+                .with_unit("s")
+                .with_description("The time spent in the handler in seconds.")
+                .with_boundaries(HTTP_REQ_DURATION_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+        }
+    }
+
+    /// split the request duration `elapsed`, nothing is recorded if the handler was not reached
+    pub(crate) fn record(&self, timing: &HandlerTiming, elapsed: Duration, attributes: &[KeyValue]) {
+        if let Some(handler) = timing.get() {
+            self.handler_duration.record(handler.as_secs_f64(), attributes);
+            self.middleware_duration
+                .record(elapsed.saturating_sub(handler).as_secs_f64(), attributes);
+        }
+    }
+}
+
+/// A tower layer timing the handler, for the middleware and handler durations enabled with
+/// [crate::HttpMetricsLayerBuilder::with_handler_timing].
+///
+/// add it as the innermost layer, right above the handler:
+///
+/// ```rust,ignore
+/// let app = Router::new()
+///     .route("/", get(handler))
+///     .route_layer(HandlerTimingLayer::new())
+///     .layer(auth)
+///     .layer(metrics);
+/// ```
+///
+/// requests that did not go through the metrics layer are passed through.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HandlerTimingLayer;
+
+impl HandlerTimingLayer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<S> Layer<S> for HandlerTimingLayer {
+    type Service = HandlerTimingService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        HandlerTimingService { service }
+    }
+}
+
+/// The service of [HandlerTimingLayer].
+#[derive(Clone)]
+pub struct HandlerTimingService<S> {
+    service: S,
+}
+
+impl<S, R> Service<Request<R>> for HandlerTimingService<S>
+where
+    S: Service<Request<R>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = HandlerTimingFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<R>) -> Self::Future {
+        let timing = req.extensions().get::<HandlerTiming>().cloned();
+        HandlerTimingFuture {
+            inner: self.service.call(req),
+            start: Instant::now(),
+            timing,
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [HandlerTimingService].
+    pub struct HandlerTimingFuture<F> {
+        #[pin]
+        inner: F,
+        start: Instant,
+        timing: Option<HandlerTiming>,
+    }
+}
+
+impl<F: Future> Future for HandlerTimingFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = ready!(this.inner.poll(cx));
+        if let Some(timing) = this.timing.take() {
+            timing.set(this.start.elapsed());
+        }
+        Poll::Ready(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handler_timing() {
+        let timing = HandlerTiming::new();
+        assert_eq!(timing.get(), None);
+        timing.set(Duration::from_millis(5));
+        assert_eq!(timing.get(), Some(Duration::from_millis(5)));
+    }
+}
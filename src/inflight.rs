@@ -0,0 +1,108 @@
+//! tracking of the requests currently in flight, for the long-running requests and the per-route active requests gauges
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use opentelemetry::metrics::UpDownCounter;
+use opentelemetry::KeyValue;
+
+/// the start time of every request in flight, shared across requests
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InFlightTracker {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: AtomicU64,
+    requests: Mutex<HashMap<u64, InFlightRequest>>,
+}
+
+#[derive(Debug)]
+struct InFlightRequest {
+    method: Cow<'static, str>,
+    route: Arc<str>,
+    start: Instant,
+}
+
+impl InFlightTracker {
+    /// start tracking a request, it is tracked until the returned guard is dropped
+    pub(crate) fn track(&self, method: Cow<'static, str>, route: Arc<str>, start: Instant) -> InFlightGuard {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        self.inner
+            .requests
+            .lock()
+            .unwrap()
+            .insert(id, InFlightRequest { method, route, start });
+        InFlightGuard {
+            tracker: self.clone(),
+            id,
+        }
+    }
+
+    /// count the requests in flight for longer than `threshold`, keyed by method and route
+    pub(crate) fn longer_than(&self, threshold: Duration) -> HashMap<(String, String), u64> {
+        let now = Instant::now();
+        let mut counts = HashMap::new();
+        for req in self.inner.requests.lock().unwrap().values() {
+            if now.saturating_duration_since(req.start) > threshold {
+                *counts.entry((req.method.to_string(), req.route.to_string())).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+}
+
+/// removes the request from the tracker when the response is ready or the request is cancelled
+#[derive(Debug)]
+pub(crate) struct InFlightGuard {
+    tracker: InFlightTracker,
+    id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.tracker.inner.requests.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// counts a request in the per-route active requests until the response is ready or the request is cancelled
+pub(crate) struct RouteActiveGuard {
+    counter: UpDownCounter<i64>,
+    attributes: Vec<KeyValue>,
+}
+
+impl RouteActiveGuard {
+    pub(crate) fn new(counter: UpDownCounter<i64>, attributes: Vec<KeyValue>) -> Self {
+        counter.add(1, &attributes);
+        Self { counter, attributes }
+    }
+}
+
+impl Drop for RouteActiveGuard {
+    fn drop(&mut self) {
+        self.counter.add(-1, &self.attributes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longer_than() {
+        let tracker = InFlightTracker::default();
+        let old = Instant::now() - Duration::from_secs(60);
+        let _slow = tracker.track("GET".into(), "/export".into(), old);
+        let _fast = tracker.track("GET".into(), "/".into(), Instant::now());
+        let dropped = tracker.track("GET".into(), "/export".into(), old);
+        drop(dropped);
+
+        let counts = tracker.longer_than(Duration::from_secs(30));
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts[&("GET".to_string(), "/export".to_string())], 1);
+    }
+}
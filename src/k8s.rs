@@ -0,0 +1,71 @@
+//! Kubernetes resource detection from the downward API
+
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::resource::ResourceDetector;
+use opentelemetry_sdk::Resource;
+use opentelemetry_semantic_conventions::resource::{K8S_NAMESPACE_NAME, K8S_NODE_NAME, K8S_POD_NAME};
+
+const SERVICE_ACCOUNT_NAMESPACE_FILE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+/// Detects the `k8s.namespace.name`, `k8s.pod.name` and `k8s.node.name` resource attributes.
+///
+/// the values are read from the env vars usually populated by the downward API:
+///
+/// - `k8s.namespace.name`: `K8S_NAMESPACE_NAME`, `POD_NAMESPACE`, `INSTANCE_NAMESPACE`,
+///   then the namespace file of the mounted service account
+/// - `k8s.pod.name`: `K8S_POD_NAME`, `POD_NAME`, then `HOSTNAME` which defaults to the pod name
+/// - `k8s.node.name`: `K8S_NODE_NAME`, `NODE_NAME`
+///
+/// outside of a pod, i.e. without a namespace, nothing is detected.
+///
+/// ```yaml
+/// env:
+///   - name: K8S_NAMESPACE_NAME
+///     valueFrom:
+///       fieldRef:
+///         fieldPath: metadata.namespace
+///   - name: K8S_POD_NAME
+///     valueFrom:
+///       fieldRef:
+///         fieldPath: metadata.name
+///   - name: K8S_NODE_NAME
+///     valueFrom:
+///       fieldRef:
+///         fieldPath: spec.nodeName
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct K8sResourceDetector;
+
+impl ResourceDetector for K8sResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let Some(namespace) = first_env(&["K8S_NAMESPACE_NAME", "POD_NAMESPACE", "INSTANCE_NAMESPACE"]).or_else(|| {
+            fs::read_to_string(SERVICE_ACCOUNT_NAMESPACE_FILE)
+                .ok()
+                .map(|ns| ns.trim().to_string())
+                .filter(|ns| !ns.is_empty())
+        }) else {
+            return Resource::empty();
+        };
+
+        let mut attributes = vec![KeyValue::new(K8S_NAMESPACE_NAME, namespace)];
+        if let Some(pod) = first_env(&["K8S_POD_NAME", "POD_NAME", "HOSTNAME"]) {
+            attributes.push(KeyValue::new(K8S_POD_NAME, pod));
+        }
+        if let Some(node) = first_env(&["K8S_NODE_NAME", "NODE_NAME"]) {
+            attributes.push(KeyValue::new(K8S_NODE_NAME, node));
+        }
+        Resource::new(attributes)
+    }
+}
+
+/// returns the value of the first non-empty env var of `names`
+fn first_env(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|v| !v.is_empty())
+}
@@ -48,82 +48,750 @@
 //! }
 //! ```
 
-use axum::http::Response;
-use axum::{extract::MatchedPath, extract::State, http::Request, response::IntoResponse, routing::get, Router};
-use std::collections::HashMap;
+mod api_key;
+mod app_metrics;
+mod attribute_cache;
+mod auth;
+mod baggage;
+mod body;
+mod cache;
+mod cardinality;
+mod client;
+#[cfg(feature = "compression")]
+mod compression;
+mod compression_ratio;
+mod concurrency;
+mod exemplars;
+mod export;
+mod exposition;
+mod graphql;
+mod grpc;
+mod handle;
+mod handler_timing;
+mod inflight;
+mod redact;
+mod route_table;
+mod router;
+#[cfg(feature = "k8s")]
+mod k8s;
+#[cfg(all(feature = "process-metrics", target_os = "linux"))]
+mod process;
+mod rate_limit;
+mod recorder;
+mod responses;
+mod sampling;
+mod scrape;
+mod sse;
+mod tenant;
+mod slo;
+mod trace_context;
+#[cfg(feature = "tls-metrics")]
+mod tls;
+#[cfg(feature = "websocket")]
+mod websocket;
+
+pub use app_metrics::AppMetrics;
+pub use body::{MetricsBody, RequestBody};
+pub use cardinality::OVERFLOW_VALUE;
+pub use client::{ClientResponseFuture, HttpClientMetrics, HttpClientMetricsLayer};
+pub use compression_ratio::{UncompressedBody, UncompressedSize, UncompressedSizeFuture, UncompressedSizeLayer};
+pub use concurrency::{ConcurrencyFuture, ConcurrencyMetrics, ConcurrencyMetricsLayer};
+pub use export::ExportErrorFn;
+pub use graphql::{GraphQlOperation, GraphQlOperationType};
+pub use handle::MetricsHandle;
+pub use handler_timing::{HandlerTimingFuture, HandlerTimingLayer, HandlerTimingService};
+pub use redact::redact_pii;
+pub use router::{propagate_matched_path, RouterMetricsExt};
+#[cfg(feature = "k8s")]
+pub use k8s::K8sResourceDetector;
+pub use sampling::SampleRate;
+pub use slo::Objective;
+#[cfg(feature = "tls-metrics")]
+pub use tls::MeteredTlsAcceptor;
+#[cfg(feature = "websocket")]
+pub use websocket::{MeteredWebSocket, WebSocketMetrics};
+
+use axum::body::Bytes;
+use axum::http::{HeaderMap, Response};
+use axum::{
+    extract::ConnectInfo,
+    extract::MatchedPath,
+    extract::Path,
+    extract::State,
+    http::Request,
+    response::{AppendHeaders, IntoResponse},
+    routing::{get, MethodRouter},
+    Extension, Json, Router,
+};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::sync::Arc;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 
 use std::future::Future;
 use std::pin::Pin;
 use std::task::Poll::Ready;
 use std::task::{Context, Poll};
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use prometheus::{Encoder, ProtobufEncoder, Registry, TextEncoder};
 
-use prometheus::{Encoder, Registry, TextEncoder};
+use auth::EndpointAuth;
+use cache::ResponseCache;
+use cardinality::{BoundedSet, CardinalityLimiter};
+use exemplars::ExemplarStore;
+use export::{ExportStats, ObservedExporter};
+use exposition::Encoded;
+use body::{BodyBytes, RequestBodyBytes};
+use grpc::GrpcCall;
+use inflight::{InFlightGuard, InFlightTracker, RouteActiveGuard};
+use handle::{SlowRequestThresholds, Swappable};
+use rate_limit::RateLimiter;
+use recorder::{RecordedResponse, ResponseRecorder};
+use scrape::{ScrapeError, ScrapeGuard, ScrapeMetrics};
+use route_table::RouteTable;
+use attribute_cache::{AttributeCache, AttributeKey};
+use compression_ratio::{CompressionMetrics, UncompressedBytes};
+use handler_timing::{HandlerTiming, HandlerTimingMetrics};
+use sse::SseMetrics;
+use tenant::{TenantMetrics, TenantRegistries};
+use trace_context::RequestSpan;
 
 use opentelemetry::{Key, KeyValue, Value};
 
-use opentelemetry::metrics::{Counter, Histogram, UpDownCounter};
+use opentelemetry::metrics::{Counter, Histogram, ObservableCounter, ObservableGauge, UpDownCounter};
 
-use opentelemetry::metrics::MeterProvider;
+use opentelemetry::metrics::{Meter, MeterProvider};
 
 use opentelemetry_sdk::metrics::{
+    new_view,
     reader::{DefaultTemporalitySelector},
-    PeriodicReader, SdkMeterProvider,
+    Aggregation, Instrument, PeriodicReader, SdkMeterProvider, Stream, View,
+};
+use opentelemetry_sdk::resource::{
+    EnvResourceDetector, ResourceDetector, SdkProvidedResourceDetector, TelemetryResourceDetector,
 };
-use opentelemetry_sdk::resource::{EnvResourceDetector, SdkProvidedResourceDetector, TelemetryResourceDetector};
 use opentelemetry_semantic_conventions::resource::{SERVICE_NAME, SERVICE_NAMESPACE, SERVICE_VERSION};
 
 use opentelemetry::global;
+use opentelemetry::trace::SpanContext;
 
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::task::JoinHandle;
 use tower::{Layer, Service};
 
 use futures_util::ready;
+pub use ipnet::IpNet;
 use http_body::Body as httpBody;
 use opentelemetry_sdk::Resource;
 use pin_project_lite::pin_project; // for `Body::size_hint`
                                    // service.instance used by Tencent Cloud TKE APM only, for view application metrics by pod IP
 const SERVICE_INSTANCE: Key = Key::from_static_str("service.instance");
+// semconv 1.27.0, replaces the deprecated `deployment.environment`
+const DEPLOYMENT_ENVIRONMENT_NAME: Key = Key::from_static_str("deployment.environment.name");
+
+/// the built-in instruments, used to override their metadata on the [HttpMetricsLayerBuilder]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum HttpInstrument {
+    RequestsTotal,
+    RequestDuration,
+    RequestSize,
+    ResponseSize,
+    RequestHeaderSize,
+    RequestBodySize,
+    ResponseHeaderSize,
+    ResponseBodySize,
+    ActiveRequests,
+    RouteActiveRequests,
+    SlowRequests,
+    LongRunningRequests,
+    Apdex,
+    SloRequests,
+    SloRequestsGood,
+    BuildInfo,
+    RpcServerDuration,
+    GraphQlErrors,
+    Redirects,
+    NotFound,
+    Throttled,
+    RetryAfter,
+    RequestBodyBytes,
+    ResponseBodyBytes,
+    UpstreamDuration,
+    QueueTime,
+}
 
 /// the metrics we used in the middleware
 #[derive(Clone)]
 pub struct Metric {
-    pub requests_total: Counter<u64>,
+    /// `None` when the counter is disabled with [HttpMetricsLayerBuilder::with_requests_counter]
+    pub requests_total: Option<Counter<u64>>,
 
     // before opentelemetry 0.18.0, Histogram called ValueRecorder
     pub req_duration: Histogram<f64>,
 
-    pub req_size: Histogram<u64>,
+    /// duration histograms with route specific buckets, keyed by the `http.route` attribute
+    pub route_req_duration: Arc<HashMap<String, Histogram<f64>>>,
+
+    /// `None` when the sizes are disabled with [HttpMetricsLayerBuilder::with_size_metrics]
+    pub req_size: Option<Histogram<u64>>,
 
-    pub res_size: Histogram<u64>,
+    /// `None` when the sizes are disabled with [HttpMetricsLayerBuilder::with_size_metrics]
+    pub res_size: Option<Histogram<u64>>,
+
+    /// the request header sizes, when the header and body sizes are split
+    pub req_header_size: Option<Histogram<u64>>,
+
+    /// the request body sizes, when the header and body sizes are split
+    pub req_body_size: Option<Histogram<u64>>,
+
+    /// the response header sizes, when the header and body sizes are split
+    pub res_header_size: Option<Histogram<u64>>,
+
+    /// the response body sizes, when the header and body sizes are split
+    pub res_body_size: Option<Histogram<u64>>,
 
     pub req_active: UpDownCounter<i64>,
+
+    /// the active requests labeled by route, when enabled
+    pub route_active_requests: Option<UpDownCounter<i64>>,
+
+    /// observes the requests in flight for longer than the configured threshold
+    pub long_running_requests: Option<ObservableGauge<u64>>,
+
+    /// always 1, with the build information as attributes
+    pub build_info: Option<ObservableGauge<u64>>,
+
+    /// the start time of the process since unix epoch in seconds
+    pub process_start_time: Option<ObservableGauge<f64>>,
+
+    /// the time since the layer was built in seconds
+    pub process_uptime: Option<ObservableGauge<f64>>,
+
+    /// the duration of the gRPC calls in milliseconds, when the gRPC metrics are enabled
+    pub rpc_server_duration: Option<Histogram<f64>>,
+
+    /// the upstream durations reported by the handlers with [UpstreamDuration]
+    pub upstream_duration: Histogram<f64>,
+
+    /// the time the requests waited behind the load balancer, when the queue time is enabled
+    pub queue_time: Option<Histogram<f64>>,
+
+    /// counts the streamed request body bytes, when the throughput metrics are enabled
+    pub request_body_bytes: Option<Counter<u64>>,
+
+    /// counts the streamed response body bytes, when the throughput metrics are enabled
+    pub response_body_bytes: Option<Counter<u64>>,
+
+    /// counts the 404 responses, when the not found metrics are enabled
+    pub not_found: Option<Counter<u64>>,
+
+    /// counts the export attempts of the otlp exporter
+    pub export_attempts: Option<ObservableCounter<u64>>,
+
+    /// counts the failed exports of the otlp exporter
+    pub export_failures: Option<ObservableCounter<u64>>,
 }
 
 #[derive(Clone)]
 pub struct MetricState {
+    /// identifies the layer, so a request going through the same layer twice is recorded once
+    layer_id: u64,
+
     /// Prometheus Registry we used to gathering and exporting metrics in the export endpoint
     registry: Option<Registry>,
 
+    /// served by the `/buildinfo` admin endpoint
+    build_info: Arc<BuildInfo>,
+
+    /// the latest exemplar of every histogram series, `None` when the exemplars are disabled
+    exemplars: Option<Arc<ExemplarStore>>,
+
+    /// records the scrapes of the metrics endpoint
+    scrape_metrics: Option<ScrapeMetrics>,
+
+    /// the time and size budget of the scrapes, `None` when unlimited
+    scrape_guard: Option<ScrapeGuard>,
+
+    /// whether the metrics endpoint answers `If-None-Match` with `304 Not Modified`
+    conditional_get: bool,
+
+    /// whether the families of `prometheus::default_registry()` are served along with the ones of the registry
+    default_registry: bool,
+
+    /// the encoded payloads shared by the scrapes within the cache TTL
+    response_cache: Option<Arc<ResponseCache>>,
+
+    /// the token bucket of the metrics endpoint, `None` when unlimited
+    scrape_rate_limiter: Option<Arc<RateLimiter>>,
+
+    /// who may read the metrics endpoint
+    endpoint_auth: Arc<EndpointAuth>,
+
+    /// keeps the process instruments alive
+    #[cfg(all(feature = "process-metrics", target_os = "linux"))]
+    _process_metrics: Option<Arc<process::ProcessMetrics>>,
+
     /// hold the metrics we used in the middleware
     pub metric: Metric,
 
-    /// PathSkipper used to skip some paths for not recording metrics
-    skipper: PathSkipper,
+    /// PathSkipper used to skip some paths for not recording metrics, replaced at runtime by the [MetricsHandle]
+    skipper: Swappable<PathSkipper>,
+
+    /// RequestSkipper used to skip some requests for not recording metrics
+    request_skipper: Option<RequestSkipper>,
+
+    /// ResponseSkipper used to skip some responses for not recording metrics
+    response_skipper: Option<ResponseSkipper>,
 
     /// whether the service is running as a TLS server or not.
     /// this is used to help determine the `url.scheme` otel meter attribute.
     /// because there is no way to get the scheme from the request in http server
     /// (except for absolute uri request, but which is only used when as a proxy server).
     is_tls: bool,
+
+    /// whether to record the `client.address` attribute on the request metrics.
+    client_address: bool,
+
+    /// whether to record the `network.peer.address` and `network.peer.port` attributes on the request metrics.
+    network_peer: bool,
+
+    /// whether to split the `server.port` attribute from the `server.address` of the request metrics.
+    server_port: bool,
+
+    /// whether to record the `url.query_keys` attribute on the request metrics.
+    query_keys: bool,
+
+    /// the proxies whose forwarded headers are trusted, `None` means every peer is trusted.
+    trusted_proxies: Option<Arc<[IpNet]>>,
+
+    /// SchemeResolver used to determine the `url.scheme` attribute from forwarded headers
+    scheme_resolver: SchemeResolver,
+
+    /// whether to record the `network.protocol.version` attribute on all instruments.
+    protocol_version: bool,
+
+    /// the `baggage` entries recorded as attributes on the request metrics
+    baggage_keys: Arc<[String]>,
+
+    /// RouteExtractor used to determine the `http.route` attribute, [MatchedPath] by default
+    route_extractor: RouteExtractor,
+
+    /// how requests without a route are labeled
+    unmatched_route: UnmatchedRoute,
+
+    /// how the CORS preflight requests are recorded
+    cors_preflight: CorsPreflight,
+
+    /// how the `HEAD` requests are recorded
+    head_requests: HeadRequests,
+
+    /// how the `http.server.not_found` counter is labeled, when enabled
+    not_found_label: Option<NotFoundLabel>,
+
+    /// the path buckets recorded so far with [NotFoundLabel::PathBucket]
+    not_found_buckets: Arc<BoundedSet>,
+
+    /// the raw paths recorded so far when `unmatched_route` is [UnmatchedRoute::RawPath]
+    unmatched_paths: Arc<BoundedSet>,
+
+    /// the attribute sets of the request metrics, reused across the requests with the same method, route and status
+    attribute_cache: Arc<AttributeCache>,
+
+    /// rewrites the matched path before it becomes the `http.route` attribute
+    route_grouper: Option<Arc<RouteGrouperFn>>,
+
+    /// drops the built-in attributes the user does not want to record
+    attribute_filter: AttributeFilter,
+
+    /// scrubs the attribute values before they are recorded
+    attribute_redactor: Option<Arc<AttributeRedactorFn>>,
+
+    /// caps the number of distinct values per attribute
+    cardinality_limiter: CardinalityLimiter,
+
+    /// renames the attribute keys once the values are processed
+    attribute_naming: AttributeNaming,
+
+    /// attributes appended to every measurement
+    const_attributes: Arc<[KeyValue]>,
+
+    /// the fraction of requests whose histograms are recorded
+    sample_rate: SampleRate,
+
+    /// which responses get their histograms recorded
+    histogram_mode: HistogramMode,
+
+    /// the named latency thresholds of the `http.server.slow_requests` counter, replaced at runtime by the [MetricsHandle]
+    slow_request_thresholds: Swappable<SlowRequestThresholds>,
+
+    /// tracks the requests in flight when the long-running requests gauge is enabled
+    in_flight: Option<InFlightTracker>,
+
+    /// the Server-Sent Events instruments, when enabled
+    sse: Option<SseMetrics>,
+
+    /// the compression instruments, when enabled
+    compression: Option<CompressionMetrics>,
+
+    /// the middleware and handler duration instruments, when enabled
+    handler_timing: Option<HandlerTimingMetrics>,
+
+    /// the per-tenant registries and the extractor of the request tenant, when enabled
+    tenant_registries: Option<(TenantExtractor, Arc<TenantRegistries>)>,
+
+    /// the extractor of the `tenant.id` attribute and the tenants admitted so far, when enabled
+    tenant_attribute: Option<(TenantExtractor, Arc<BoundedSet>)>,
+
+    /// the extractor of the credential hashed into the `api_key.hash` attribute, and its salt
+    api_key_attribute: Option<(CredentialExtractor, Arc<str>)>,
+
+    /// the WebSocket instruments handed to the upgrade requests
+    #[cfg(feature = "websocket")]
+    websocket: Option<WebSocketMetrics>,
+
+    /// the instruments of the enabled features recorded from the responses, e.g. the apdex or the SLO counters
+    recorders: Arc<[Box<dyn ResponseRecorder>]>,
+}
+
+impl MetricState {
+    /// the families of `prometheus::default_registry()` to serve along with `families`,
+    /// the ones already in `families` are dropped since a family must only be exposed once
+    fn default_families(&self, families: &[prometheus::proto::MetricFamily]) -> Vec<prometheus::proto::MetricFamily> {
+        if !self.default_registry {
+            return Vec::new();
+        }
+        let names = families.iter().map(|family| family.get_name()).collect::<HashSet<_>>();
+        let mut default_families = prometheus::default_registry().gather();
+        default_families.retain(|family| !names.contains(family.get_name()));
+        default_families
+    }
+
+    /// whether any instrument records the request sizes, to not walk the request headers for nothing
+    fn records_request_size(&self) -> bool {
+        self.metric.req_size.is_some()
+            || self.metric.req_header_size.is_some()
+            || self.metric.req_body_size.is_some()
+            || self.tenant_registries.is_some()
+            || self.exemplars.is_some()
+    }
+
+    /// drop the filtered out `attributes`, scrub the others, apply the cardinality limits, rename them
+    /// and append the constant attributes
+    fn attributes(&self, mut attributes: Vec<KeyValue>) -> Vec<KeyValue> {
+        self.process_attributes(&mut attributes);
+        attributes.extend(self.const_attributes.iter().cloned());
+        attributes
+    }
+
+    /// [MetricState::attributes] without the constant attributes, for the attributes added to an already processed set
+    fn process_attributes(&self, attributes: &mut Vec<KeyValue>) {
+        self.attribute_filter.apply(attributes);
+        if let Some(ref redactor) = self.attribute_redactor {
+            redact(redactor, attributes);
+        }
+        self.cardinality_limiter.limit(attributes);
+        self.attribute_naming.apply(attributes);
+    }
+
+    /// returns the address of the client that sent `req` to the metrics endpoint
+    ///
+    /// the `Forwarded` and `X-Forwarded-For` headers are only honored when the peer is one of the
+    /// explicitly configured trusted proxies, an unknown client cannot claim an allowed address.
+    fn client_ip<T>(&self, req: &Request<T>) -> Option<IpAddr> {
+        let peer = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let trusted = match (&self.trusted_proxies, peer) {
+            (Some(proxies), Some(ip)) => proxies.iter().any(|net| net.contains(&ip)),
+            _ => false,
+        };
+        if !trusted {
+            return peer;
+        }
+        if let Some(forwarded) = header_str(req.headers(), http::header::FORWARDED.as_str()) {
+            return parse_forwarded(forwarded).r#for.and_then(|ip| ip.parse().ok());
+        }
+        if let Some(forwarded_for) = header_str(req.headers(), "X-Forwarded-For") {
+            return forwarded_for.split(',').next().and_then(|ip| ip.trim().parse().ok());
+        }
+        peer
+    }
+
+    /// the `http.request.method` attribute of the request method
+    fn method_label(&self, method: &http::Method) -> Cow<'static, str> {
+        if self.head_requests == HeadRequests::FoldIntoGet && method == http::Method::HEAD {
+            Cow::Borrowed("GET")
+        } else {
+            method_str(method)
+        }
+    }
+
+    /// returns the `http.route` label for a request that did not match any route
+    fn unmatched_route(&self, raw_path: &str) -> String {
+        match self.unmatched_route {
+            UnmatchedRoute::Label(ref label) => label.clone(),
+            UnmatchedRoute::RawPath { .. } => {
+                if self.unmatched_paths.admit(raw_path) {
+                    raw_path.to_owned()
+                } else {
+                    UNMATCHED_ROUTE_LABEL.to_owned()
+                }
+            }
+        }
+    }
+}
+
+/// Which responses get their duration and size histograms recorded.
+///
+/// The requests counter and `http.server.active_requests` are always recorded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HistogramMode {
+    /// Record the histograms for every response.
+    #[default]
+    All,
+
+    /// Only record the histograms for 4xx and 5xx responses,
+    /// and for any response slower than `slower_than` if set.
+    ErrorsOnly { slower_than: Option<Duration> },
+}
+
+impl HistogramMode {
+    fn should_record(&self, status: http::StatusCode, latency: Duration) -> bool {
+        match *self {
+            HistogramMode::All => true,
+            HistogramMode::ErrorsOnly { slower_than } => {
+                status.is_client_error() || status.is_server_error() || slower_than.is_some_and(|t| latency > t)
+            }
+        }
+    }
+}
+
+/// How the `http.server.request.duration` histogram is aggregated.
+///
+/// the native histograms are OTel exponential histograms: the otlp exporter pushes them
+/// and Prometheus ingests them as native histograms through its OTLP receiver.
+/// the prometheus exporter can not expose them, the prometheus crate has no native histogram support.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DurationHistogram {
+    /// The explicit buckets histogram.
+    #[default]
+    Classic,
+
+    /// An exponential histogram of at most `max_size` buckets, starting at scale `max_scale`,
+    /// instead of the explicit buckets histogram.
+    Native { max_size: u32, max_scale: i8 },
+
+    /// The explicit buckets histogram, plus an exponential histogram
+    /// named `http.server.request.duration.native`.
+    ClassicAndNative { max_size: u32, max_scale: i8 },
+}
+
+/// a function that maps a matched path to the `http.route` attribute, see [HttpMetricsLayerBuilder::with_route_grouper]
+pub type RouteGrouperFn = dyn for<'a> Fn(&'a str) -> Cow<'a, str> + 'static + Send + Sync;
+
+/// a function that scrubs an attribute value before it is recorded, see [HttpMetricsLayerBuilder::with_attribute_redactor]
+pub type AttributeRedactorFn = dyn for<'a> Fn(&Key, &'a str) -> Cow<'a, str> + 'static + Send + Sync;
+
+/// which of the built-in attributes are recorded
+#[derive(Clone, Debug, Default)]
+enum AttributeFilter {
+    #[default]
+    All,
+    Allow(Arc<HashSet<Key>>),
+    Deny(Arc<HashSet<Key>>),
+}
+
+impl AttributeFilter {
+    fn apply(&self, attributes: &mut Vec<KeyValue>) {
+        match self {
+            AttributeFilter::All => {}
+            AttributeFilter::Allow(keys) => attributes.retain(|kv| keys.contains(&kv.key)),
+            AttributeFilter::Deny(keys) => attributes.retain(|kv| !keys.contains(&kv.key)),
+        }
+    }
+}
+
+/// how the attribute keys are emitted, see [HttpMetricsLayerBuilder::with_attribute_naming]
+///
+/// the keys follow the semantic conventions by default, e.g. `http.request.method`,
+/// which the prometheus exporter emits as `http_request_method` on its own.
+#[derive(Clone, Debug, Default)]
+pub struct AttributeNaming {
+    underscores: bool,
+    renames: HashMap<Key, Key>,
+}
+
+impl AttributeNaming {
+    /// the semantic conventions keys, e.g. `http.request.method`
+    pub fn dotted() -> Self {
+        Self::default()
+    }
+
+    /// the dots of the keys replaced with underscores, e.g. `http_request_method`,
+    /// for the OTLP backends queried with the prometheus label names
+    pub fn underscored() -> Self {
+        Self {
+            underscores: true,
+            ..Self::default()
+        }
+    }
+
+    /// emit the attribute `from` as `to`, e.g. `http.route` as `route`, the renamed keys are emitted as is
+    pub fn with_rename(mut self, from: impl Into<Key>, to: impl Into<Key>) -> Self {
+        self.renames.insert(from.into(), to.into());
+        self
+    }
+
+    fn apply(&self, attributes: &mut [KeyValue]) {
+        for kv in attributes.iter_mut() {
+            if let Some(key) = self.renames.get(&kv.key) {
+                kv.key = key.clone();
+            } else if self.underscores && kv.key.as_str().contains('.') {
+                kv.key = Key::new(kv.key.as_str().replace('.', "_"));
+            }
+        }
+    }
+}
+
+/// replace the string values of `attributes` scrubbed by `redactor`
+fn redact(redactor: &AttributeRedactorFn, attributes: &mut [KeyValue]) {
+    for kv in attributes.iter_mut() {
+        if let Value::String(ref value) = kv.value {
+            if let Cow::Owned(redacted) = redactor(&kv.key, value.as_str()) {
+                kv.value = Value::from(redacted);
+            }
+        }
+    }
+}
+
+/// the default `http.route` label for requests that did not match any route
+pub const UNMATCHED_ROUTE_LABEL: &str = "UNMATCHED";
+
+/// the `tenant.id` label of the tenants past the limit of [HttpMetricsLayerBuilder::with_tenant_attribute]
+pub const OTHER_TENANT_LABEL: &str = "other";
+
+/// the `http.route` label of the CORS preflight requests with [CorsPreflight::Label]
+pub const CORS_PREFLIGHT_ROUTE_LABEL: &str = "CORS_PREFLIGHT";
+
+/// How the CORS preflight requests, i.e. the `OPTIONS` requests with
+/// an `Origin` and an `Access-Control-Request-Method` header, are recorded.
+///
+/// They can dominate the per-route counts and skew their latencies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CorsPreflight {
+    /// Record them under the route they target.
+    #[default]
+    Record,
+
+    /// Do not record them.
+    Skip,
+
+    /// Record them all under [CORS_PREFLIGHT_ROUTE_LABEL].
+    Label,
+}
+
+/// whether the request is a CORS preflight
+fn is_cors_preflight<T>(req: &Request<T>) -> bool {
+    req.method() == http::Method::OPTIONS
+        && req.headers().contains_key(http::header::ORIGIN)
+        && req.headers().contains_key(http::header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// How the `HEAD` requests, e.g. from uptime monitors, are recorded.
+///
+/// They double the number of series of the `GET` routes, and their
+/// latencies are not comparable to the ones of the `GET` requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HeadRequests {
+    /// Record them with `http.request.method="HEAD"`.
+    #[default]
+    Record,
+
+    /// Record them with `http.request.method="GET"`.
+    FoldIntoGet,
+
+    /// Do not record them.
+    Skip,
 }
 
+/// How the `http.server.not_found` counter is labeled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotFoundLabel {
+    /// Label by `url.path_bucket`, the first segment of the raw path, e.g. `/wp-admin`.
+    ///
+    /// At most `max_buckets` distinct buckets are recorded, any further
+    /// bucket is recorded as [OVERFLOW_VALUE].
+    PathBucket { max_buckets: usize },
+
+    /// Label by `http.referer_class`, `none` without a `Referer` header,
+    /// `internal` for a referer on the requested host, `external` otherwise.
+    RefererClass,
+}
+
+/// the first segment of a raw path, e.g. `/wp-admin` for `/wp-admin/setup.php`
+fn path_bucket(path: &str) -> &str {
+    match path.get(1..).and_then(|rest| rest.find('/')) {
+        Some(end) => &path[..end + 1],
+        None if path.is_empty() => "/",
+        None => path,
+    }
+}
+
+/// whether the referer is missing, on the requested `host` or on another host
+fn referer_class(referer: Option<&str>, host: &str) -> &'static str {
+    let Some(referer) = referer else {
+        return "none";
+    };
+    match referer.parse::<http::Uri>().ok().as_ref().and_then(|uri| uri.host()) {
+        Some(referer_host) if referer_host.trim_start_matches('[').trim_end_matches(']').eq_ignore_ascii_case(host) => {
+            "internal"
+        }
+        _ => "external",
+    }
+}
+
+/// How the `http.route` attribute is set for requests that did not
+/// match any route, e.g. 404s and requests handled by a fallback.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnmatchedRoute {
+    /// Record all unmatched requests under a fixed label.
+    Label(String),
+
+    /// Record the raw request path.
+    ///
+    /// At most `max_paths` distinct paths are recorded, any further
+    /// path is recorded as [UNMATCHED_ROUTE_LABEL] so that scanners
+    /// can not blow up the cardinality of the route label.
+    RawPath { max_paths: usize },
+}
+
+impl Default for UnmatchedRoute {
+    fn default() -> Self {
+        Self::Label(UNMATCHED_ROUTE_LABEL.to_string())
+    }
+}
+
+/// the id of the next built [HttpMetricsLayer]
+static NEXT_LAYER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// a request extension listing the layers the request went through
+#[derive(Clone, Default)]
+struct AppliedLayers(Vec<u64>);
+
 /// the service wrapper
+///
+/// its responses carry a [MetricsBody] wrapping the body of the inner service, see the CHANGELOG for the 0.10 upgrade.
 #[derive(Clone)]
 pub struct HttpMetrics<S> {
-    pub(crate) state: MetricState,
+    /// shared by the services and the response futures, so a request clones a single `Arc`
+    pub(crate) state: Arc<MetricState>,
 
     /// inner service which is wrapped by this middleware
     service: S,
@@ -132,8 +800,23 @@ pub struct HttpMetrics<S> {
 #[derive(Clone)]
 pub struct HttpMetricsLayer {
     /// the metric state, use both by the middleware handler and metrics export endpoint
-    pub(crate) state: MetricState,
+    pub(crate) state: Arc<MetricState>,
     path: String,
+
+    /// the path of the JSON metrics endpoint, `None` when disabled
+    json_path: Option<String>,
+
+    /// the path of the per-tenant metrics endpoints, when enabled
+    tenant_path: Option<String>,
+
+    /// the meter of the HTTP instruments
+    meter: Meter,
+
+    /// the provider of `meter`, flushed and shut down by the application
+    provider: SdkMeterProvider,
+
+    /// registers application instruments on `meter`
+    app_metrics: AppMetrics,
 }
 
 // TODO support custom buckets
@@ -148,6 +831,9 @@ const HTTP_REQ_DURATION_HISTOGRAM_BUCKETS: &[f64] = &[
 const KB: f64 = 1024.0;
 const MB: f64 = 1024.0 * KB;
 
+/// the number of attribute sets of the request metrics cached per layer
+const ATTRIBUTE_CACHE_SIZE: usize = 4096;
+
 const HTTP_REQ_SIZE_HISTOGRAM_BUCKETS: &[f64] = &[
     1.0 * KB,   // 1 KB
     2.0 * KB,   // 2 KB
@@ -162,600 +848,5680 @@ const HTTP_REQ_SIZE_HISTOGRAM_BUCKETS: &[f64] = &[
 ];
 
 impl HttpMetricsLayer {
-    pub fn routes<S>(&self) -> Router<S> {
-        Router::new()
-            .route(self.path.as_str(), get(Self::exporter_handler))
-            .with_state(self.state.clone())
+    /// returns the meter of the HTTP instruments.
+    ///
+    /// instruments created from it share the provider, resource and exporter of the HTTP metrics,
+    /// without going through the global meter provider.
+    pub fn meter(&self) -> Meter {
+        self.meter.clone()
     }
 
-    // TODO use a static global exporter like autometrics-rs?
-    // https://github.com/autometrics-dev/autometrics-rs/blob/d3e7bffeede43f6c77b6a992b0443c0fca34003f/autometrics/src/prometheus_exporter.rs#L10
-    pub async fn exporter_handler(state: State<MetricState>) -> impl IntoResponse {
-        // tracing::trace!("exporter_handler called");
-        match state.registry {
-            Some(ref registry) => {
-                let mut buffer = Vec::new();
-                let encoder = TextEncoder::new();
-                encoder.encode(&registry.gather(), &mut buffer).unwrap();
-                encoder.encode(&prometheus::default_registry().gather(), &mut buffer).unwrap();
-                // return metrics
-                String::from_utf8(buffer).unwrap()
-            }
-            None => "#no prometheus registry".to_string(),
-        }
+    /// returns the meter provider built for the HTTP metrics, also set as the global meter provider.
+    pub fn provider(&self) -> &SdkMeterProvider {
+        &self.provider
     }
-}
 
-/// A helper that instructs the metrics layer to ignore
-/// certain paths.
-///
-/// The [HttpMetricsLayerBuilder] uses this helper during the
-/// construction of the [HttpMetricsLayer] that will be called
-/// by Axum / Hyper / Tower when a request comes in.
-#[derive(Clone)]
-pub struct PathSkipper {
-    skip: Arc<dyn Fn(&str) -> bool + 'static + Send + Sync>,
-}
+    /// export the metrics recorded so far, e.g. with the otlp exporter before the next interval.
+    pub fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+        self.provider.force_flush()
+    }
 
-impl PathSkipper {
-    /// Returns a [PathSkipper] that skips recording metrics
-    /// for requests whose path, when passed to `fn`, returns
-    /// `true`.
+    /// flush the metrics recorded so far and shut down the provider, e.g. on graceful shutdown,
+    /// otherwise the otlp exporter loses the metrics of the last interval before the process exits.
     ///
-    /// Only static functions are accepted -- callables such
-    /// as closures that capture their surrounding context will
-    /// not work here.  For a variant that works, consult the
-    /// [PathSkipper::new_with_fn] method.
-    pub fn new(skip: fn(&str) -> bool) -> Self {
-        Self { skip: Arc::new(skip) }
+    /// the instruments stop recording once the provider is shut down.
+    pub fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+        self.provider.shutdown()
     }
 
-    /// Dynamic variant of [PathSkipper::new].
+    /// returns a future waiting for `signal`, then flushing and shutting down the provider,
+    /// to pass to `with_graceful_shutdown` in place of `signal`.
     ///
-    /// This variant requires the callable to be wrapped in an
-    /// [Arc] but, in exchange for this requirement, the caller
-    /// can use closures that capture variables from their context.
+    /// the flush and shutdown errors are reported to the global error handler.
+    /// the requests still draining once the signal fired are not exported,
+    /// call [HttpMetricsLayer::shutdown] once the server returned to export them too.
     ///
-    /// The callable argument *must be thread-safe*.  You, as
-    /// the implementor and user of this code, have that
-    /// responsibility.
-    pub fn new_with_fn(skip: Arc<dyn Fn(&str) -> bool + 'static + Send + Sync>) -> Self {
-        Self { skip: skip }
+    /// ```no_run
+    /// use axum::Router;
+    /// use axum_otel_metrics::HttpMetricsLayerBuilder;
+    ///
+    /// # async fn run(listener: tokio::net::TcpListener, signal: std::future::Ready<()>) -> std::io::Result<()> {
+    /// let metrics = HttpMetricsLayerBuilder::new().build();
+    /// let app: Router = Router::new().layer(metrics.clone());
+    /// axum::serve(listener, app)
+    ///     .with_graceful_shutdown(metrics.flush_on_shutdown(signal))
+    ///     .await
+    /// # }
+    /// ```
+    pub fn flush_on_shutdown<F>(&self, signal: F) -> impl Future<Output = ()> + Send + 'static
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let provider = self.provider.clone();
+        async move {
+            signal.await;
+            if let Err(err) = provider.force_flush() {
+                global::handle_error(err);
+            }
+            if let Err(err) = provider.shutdown() {
+                global::handle_error(err);
+            }
+        }
     }
-}
 
-impl Default for PathSkipper {
-    /// Returns a `PathSkipper` that skips any path which
-    /// starts with `/metrics` or `/favicon.ico``.
+    /// wrap `acceptor` to record the TLS handshake durations and failures, see [MeteredTlsAcceptor].
+    #[cfg(feature = "tls-metrics")]
+    pub fn tls_acceptor(&self, acceptor: tokio_rustls::TlsAcceptor) -> MeteredTlsAcceptor {
+        MeteredTlsAcceptor::new(acceptor, &self.app_metrics)
+    }
+
+    /// returns a layer recording the outbound requests of an HTTP client, see [HttpClientMetricsLayer].
     ///
-    /// This is the default implementation used when
-    /// building an HttpMetricsLayerBuilder from scratch.
-    fn default() -> Self {
-        Self::new(|s| s.starts_with("/metrics") || s.starts_with("/favicon.ico"))
+    /// the client instruments share the provider and registry of the server metrics.
+    pub fn client_layer(&self) -> HttpClientMetricsLayer {
+        HttpClientMetricsLayer::new(&self.app_metrics)
     }
-}
 
-#[derive(Clone)]
-pub struct HttpMetricsLayerBuilder {
-    service_name: Option<String>,
-    service_version: Option<String>,
-    prefix: Option<String>,
-    path: String,
-    labels: Option<HashMap<String, String>>,
-    skipper: PathSkipper,
-    is_tls: bool,
-    exporter: Option<String>,
-}
+    /// returns a layer recording the requests held back by a tower `ConcurrencyLimit` or shed by a `LoadShed`
+    /// beneath it, see [ConcurrencyMetricsLayer].
+    pub fn concurrency_layer(&self) -> ConcurrencyMetricsLayer {
+        ConcurrencyMetricsLayer::new(&self.app_metrics)
+    }
 
-impl Default for HttpMetricsLayerBuilder {
+    /// returns the prometheus registry served by the metrics endpoint, `None` with the otlp exporter.
+    ///
+    /// collectors registered on it, e.g. a database pool collector, show up on the same endpoint.
+    pub fn registry(&self) -> Option<&Registry> {
+        self.state.registry.as_ref()
+    }
+
+    /// returns a handle to register application instruments next to the HTTP metrics,
+    /// with the instrument prefix of the builder applied.
+    pub fn app_metrics(&self) -> AppMetrics {
+        self.app_metrics.clone()
+    }
+
+    /// returns the shared handle to the sampling rate, see [HttpMetricsLayerBuilder::with_sample_rate]
+    pub fn sample_rate(&self) -> SampleRate {
+        self.state.sample_rate.clone()
+    }
+
+    /// returns a handle to swap the path skipper, the sampling rate and the slow request thresholds at runtime
+    pub fn handle(&self) -> MetricsHandle {
+        MetricsHandle {
+            skipper: self.state.skipper.clone(),
+            sample_rate: self.state.sample_rate.clone(),
+            slow_request_thresholds: self.state.slow_request_thresholds.clone(),
+        }
+    }
+
+    pub fn routes<S>(&self) -> Router<S> {
+        let mut router = Router::new().route(self.path.as_str(), get(Self::exporter_request_handler));
+        if let Some(ref json_path) = self.json_path {
+            router = router.route(json_path.as_str(), get(json_exporter_handler));
+        }
+        if let Some(ref tenant_path) = self.tenant_path {
+            router = router.route(tenant_path.as_str(), get(tenant_exporter_handler));
+        }
+        router.with_state(MetricState::clone(&self.state))
+    }
+
+    /// serve the metrics endpoint on a dedicated listener bound to `addr`, e.g. `0.0.0.0:9464`,
+    /// independent of the main application router.
+    ///
+    /// the listener is bound before returning, so an address in use is reported right away,
+    /// the server then runs on a spawned task until the runtime shuts down.
+    ///
+    /// ```no_run
+    /// use axum_otel_metrics::HttpMetricsLayerBuilder;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let metrics = HttpMetricsLayerBuilder::new().build();
+    /// metrics.serve_on("0.0.0.0:9464").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn serve_on<A>(&self, addr: A) -> io::Result<JoinHandle<io::Result<()>>>
+    where
+        A: ToSocketAddrs,
+    {
+        let listener = TcpListener::bind(addr).await?;
+        let app = self.routes::<()>();
+        Ok(tokio::spawn(async move { axum::serve(listener, app).await }))
+    }
+
+    /// serve the metrics endpoint on a unix domain socket at `path`, e.g. `/run/app/metrics.sock`.
+    ///
+    /// a stale socket left at `path` by a previous run is removed before binding,
+    /// the server then runs on a spawned task until the runtime shuts down.
+    #[cfg(unix)]
+    pub async fn serve_on_uds<P>(&self, path: P) -> io::Result<JoinHandle<io::Result<()>>>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        let path = path.as_ref();
+        if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = tokio::net::UnixListener::bind(path)?;
+        Ok(tokio::spawn(serve_uds(listener, self.routes::<()>())))
+    }
+
+    /// returns a router with the operational endpoints of the service:
+    ///
+    /// - the metrics endpoint, at the path configured on the builder
+    /// - the JSON metrics endpoint, when enabled with [HttpMetricsLayerBuilder::with_json_endpoint]
+    /// - `/healthz`, a liveness endpoint always answering `200 ok`
+    /// - `/buildinfo`, the service name and version as JSON
+    ///
+    /// none of them is recorded in the HTTP metrics.
+    pub fn admin_routes<S>(&self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let build_info = self.state.build_info.clone();
+        self.unrecorded_routes()
+            .route("/healthz", get(|| async { (Extension(SkipMetrics), "ok") }))
+            .route(
+                "/buildinfo",
+                get(move || async move { (Extension(SkipMetrics), Json(build_info.to_json())) }),
+            )
+    }
+
+    /// like [HttpMetricsLayer::routes], but the responses of the metrics endpoints are never recorded
+    pub(crate) fn unrecorded_routes<S>(&self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        Router::new()
+            .route(
+                self.path.as_str(),
+                get(|state: State<MetricState>, request: Request<axum::body::Body>| async move {
+                    (Extension(SkipMetrics), Self::exporter_request_handler(state, request).await)
+                }),
+            )
+            .merge(self.json_path.as_ref().map_or_else(Router::new, |json_path| {
+                Router::new().route(
+                    json_path.as_str(),
+                    get(|state: State<MetricState>, request: Request<axum::body::Body>| async move {
+                        (Extension(SkipMetrics), json_exporter_handler(state, request).await)
+                    }),
+                )
+            }))
+            .merge(self.tenant_path.as_ref().map_or_else(Router::new, |tenant_path| {
+                Router::new().route(
+                    tenant_path.as_str(),
+                    get(
+                        |state: State<MetricState>, tenant: Path<String>, request: Request<axum::body::Body>| async move {
+                            (Extension(SkipMetrics), tenant_exporter_handler(state, tenant, request).await)
+                        },
+                    ),
+                )
+            }))
+            .with_state(MetricState::clone(&self.state))
+    }
+
+    /// returns the state of the metrics endpoint, for use with [exporter_handler].
+    pub fn metric_state(&self) -> MetricState {
+        MetricState::clone(&self.state)
+    }
+
+    /// returns the metrics endpoint as a [MethodRouter] with its state already bound,
+    /// so it can be mounted at any path of a custom router, with its own middleware stack.
+    ///
+    /// ```
+    /// use axum_otel_metrics::HttpMetricsLayerBuilder;
+    /// use axum::Router;
+    ///
+    /// let metrics = HttpMetricsLayerBuilder::new().build();
+    /// let admin = Router::<()>::new().route("/internal/metrics", metrics.exporter_route());
+    /// ```
+    pub fn exporter_route<S>(&self) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        get(Self::exporter_request_handler).with_state(MetricState::clone(&self.state))
+    }
+
+    /// The handler of the metrics endpoint, without the request.
+    ///
+    /// the metrics are served in the classic prometheus text format, uncompressed, and the scrapes are refused
+    /// when the endpoint is protected, see [HttpMetricsLayer::exporter_request_handler] which reads the request.
+    pub async fn exporter_handler(state: State<MetricState>) -> impl IntoResponse {
+        Self::exporter_request_handler(state, Request::default()).await
+    }
+
+    /// The handler of the metrics endpoint.
+    ///
+    /// the OpenMetrics text format is served when the scraper asks for `application/openmetrics-text`
+    /// in `Accept`, the delimited protobuf format for `application/vnd.google.protobuf`,
+    /// the classic prometheus text format otherwise.
+    ///
+    /// with the `compression` feature the response is compressed with gzip or deflate
+    /// when the scraper asks for it in `Accept-Encoding`.
+    ///
+    /// with [HttpMetricsLayerBuilder::with_conditional_get] the response carries an `ETag`,
+    /// and `304 Not Modified` is returned when it matches `If-None-Match`.
+    ///
+    /// with [HttpMetricsLayerBuilder::with_response_cache] the encoded payload is reused within the cache TTL.
+    ///
+    /// with [HttpMetricsLayerBuilder::with_metrics_auth_token] or [HttpMetricsLayerBuilder::with_metrics_basic_auth]
+    /// unauthorized requests get `401 Unauthorized`, with [HttpMetricsLayerBuilder::with_metrics_allowed_networks]
+    /// the clients outside of the allowed networks get `403 Forbidden`, as well as the requests refused
+    /// by the authorizer of [HttpMetricsLayerBuilder::with_metrics_authorizer].
+    ///
+    /// with [HttpMetricsLayerBuilder::with_scrape_rate_limit] the requests beyond the limit get `429 Too Many Requests`.
+    // TODO use a static global exporter like autometrics-rs?
+    // https://github.com/autometrics-dev/autometrics-rs/blob/d3e7bffeede43f6c77b6a992b0443c0fca34003f/autometrics/src/prometheus_exporter.rs#L10
+    pub async fn exporter_request_handler(
+        state: State<MetricState>,
+        request: Request<axum::body::Body>,
+    ) -> Response<axum::body::Body> {
+        // tracing::trace!("exporter_handler called");
+        if let Some(ref limiter) = state.scrape_rate_limiter {
+            if let Err(wait) = limiter.acquire() {
+                let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+                let headers = [(http::header::RETRY_AFTER, retry_after.to_string())];
+                return (http::StatusCode::TOO_MANY_REQUESTS, headers).into_response();
+            }
+        }
+        let authorized = state.endpoint_auth.authorize(&request, state.client_ip(&request));
+        if let Some(rejection) = authorized.await {
+            return rejection;
+        }
+        let headers = request.headers();
+        let format = exposition::negotiate(headers);
+        let encoded = match state.registry {
+            Some(ref registry) => match state.response_cache {
+                // the cached payload is shared by every scraper, so it is always fully encoded
+                Some(ref cache) => {
+                    cache
+                        .get_or_encode(format, || Self::encode(&state, registry, format, None))
+                        .await
+                }
+                None => Self::encode(&state, registry, format, Some(headers)),
+            },
+            None => Ok(Encoded {
+                body: Some(Bytes::from_static(b"#no prometheus registry")),
+                etag: None,
+            }),
+        };
+        let encoded = match encoded {
+            Ok(encoded) => encoded,
+            Err(err) => return scrape_error(err),
+        };
+
+        let body = match encoded.body {
+            Some(body) if !encoded.etag.as_ref().is_some_and(|tag| exposition::etag_matches(headers, tag)) => body,
+            _ => {
+                let headers = [
+                    (http::header::ETAG, encoded.etag.unwrap_or_default()),
+                    (http::header::VARY, "accept, accept-encoding".to_string()),
+                ];
+                return (http::StatusCode::NOT_MODIFIED, headers).into_response();
+            }
+        };
+
+        let content_type = [
+            (http::header::CONTENT_TYPE, format.content_type()),
+            (http::header::VARY, "accept, accept-encoding"),
+        ];
+        let etag = AppendHeaders(encoded.etag.map(|tag| (http::header::ETAG, tag)));
+        #[cfg(feature = "compression")]
+        if let Some(encoding) = compression::negotiate(headers) {
+            if let Ok(compressed) = encoding.compress(&body) {
+                let headers = [(http::header::CONTENT_ENCODING, encoding.as_str())];
+                return (content_type, etag, headers, compressed).into_response();
+            }
+        }
+        (content_type, etag, body).into_response()
+    }
+
+    /// gather the registries and encode them as `format`
+    ///
+    /// the encoding is skipped, leaving the body `None`, when the ETag matches the `If-None-Match` of `headers`.
+    ///
+    /// the families are encoded one by one, to abandon the scrape as soon as it is over the budget of the scrape guard.
+    fn encode(
+        state: &MetricState,
+        registry: &Registry,
+        format: exposition::Format,
+        headers: Option<&HeaderMap>,
+    ) -> Result<Encoded, ScrapeError> {
+        let start = Instant::now();
+        let families = registry.gather();
+        let default_families = state.default_families(&families);
+        let etag = state
+            .conditional_get
+            .then(|| exposition::etag(format, families.iter().chain(default_families.iter())));
+        if let (Some(ref tag), Some(headers)) = (&etag, headers) {
+            if exposition::etag_matches(headers, tag) {
+                if let Some(ref scrape_metrics) = state.scrape_metrics {
+                    scrape_metrics.record(start, 0, families.len() + default_families.len());
+                }
+                return Ok(Encoded { body: None, etag });
+            }
+        }
+        if let Some(ref guard) = state.scrape_guard {
+            guard.check(start, 0)?;
+        }
+        let mut buffer = Vec::new();
+        // only the families of the layer registry have exemplars
+        let layer_families = families.iter().map(|family| (family, state.exemplars.as_deref()));
+        let default_families_iter = default_families.iter().map(|family| (family, None));
+        for (family, exemplars) in layer_families.chain(default_families_iter) {
+            let family = std::slice::from_ref(family);
+            match format {
+                exposition::Format::Text => TextEncoder::new().encode(family, &mut buffer)?,
+                exposition::Format::OpenMetrics => {
+                    let mut text = String::new();
+                    exposition::encode_openmetrics(family, exemplars, &mut text);
+                    buffer.extend_from_slice(text.as_bytes());
+                }
+                exposition::Format::Protobuf => ProtobufEncoder::new().encode(family, &mut buffer)?,
+            }
+            if let Some(ref guard) = state.scrape_guard {
+                guard.check(start, buffer.len())?;
+            }
+        }
+        if format == exposition::Format::OpenMetrics {
+            buffer.extend_from_slice(b"# EOF\n");
+        }
+        if let Some(ref scrape_metrics) = state.scrape_metrics {
+            scrape_metrics.record(start, buffer.len(), families.len() + default_families.len());
+        }
+        Ok(Encoded {
+            body: Some(buffer.into()),
+            etag,
+        })
+    }
+}
+
+/// accept connections on `listener` and serve `app` on each of them,
+/// `axum::serve` only supports TCP listeners in axum 0.7
+#[cfg(unix)]
+async fn serve_uds(listener: tokio::net::UnixListener, app: Router) -> io::Result<()> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::service::TowerToHyperService;
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            // connection errors only affect this scrape
+            let _ = Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+/// A response extension that excludes the response from the HTTP metrics.
+///
+/// handlers can return it to opt out of recording, e.g. `(Extension(SkipMetrics), "ok")`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SkipMetrics;
+
+/// A request extension marking the requests received over a TLS connection,
+/// recorded with the `https` `url.scheme`.
+///
+/// for servers terminating both plain and TLS connections, the TLS acceptor loop
+/// inserts it in the requests of its connections, e.g. in the hyper service of the connection:
+/// `req.extensions_mut().insert(TlsConnection)`.
+/// servers only terminating TLS can use [HttpMetricsLayerBuilder::with_tls] instead.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TlsConnection;
+
+/// A response extension reporting the time spent waiting for an upstream service, e.g. in a proxy handler.
+///
+/// the middleware records it into the `http.server.upstream.duration` histogram, with the attributes
+/// of the request duration, so the local overhead is the difference of the two histograms.
+///
+/// ```
+/// use std::time::{Duration, Instant};
+/// use axum::{response::IntoResponse, Extension};
+/// use axum_otel_metrics::UpstreamDuration;
+///
+/// async fn proxy() -> impl IntoResponse {
+///     let start = Instant::now();
+///     // forward the request upstream
+///     (Extension(UpstreamDuration(start.elapsed())), "ok")
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UpstreamDuration(pub Duration);
+
+/// the build information served by the `/buildinfo` admin endpoint and the `build_info` gauge
+#[derive(Clone, Debug, Default)]
+struct BuildInfo {
+    service_name: Option<String>,
+    service_version: Option<String>,
+    git_sha: Option<String>,
+    rustc_version: Option<String>,
+}
+
+impl BuildInfo {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "service_name": self.service_name,
+            "service_version": self.service_version,
+            "git_sha": self.git_sha,
+            "rustc_version": self.rustc_version,
+            "axum_otel_metrics_version": env!("CARGO_PKG_VERSION"),
+        })
+    }
+
+    /// the attributes of the `build_info` gauge, unknown values are omitted
+    fn attributes(&self) -> Vec<KeyValue> {
+        let mut attributes = vec![KeyValue::new("axum_otel_metrics.version", env!("CARGO_PKG_VERSION"))];
+        let optional = [
+            ("service.name", &self.service_name),
+            ("service.version", &self.service_version),
+            ("git.sha", &self.git_sha),
+            ("rustc.version", &self.rustc_version),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                attributes.push(KeyValue::new(key, value.clone()));
+            }
+        }
+        attributes
+    }
+}
+
+/// The handler of the metrics endpoint, see [HttpMetricsLayer::metric_state].
+///
+/// ```
+/// use axum_otel_metrics::{exporter_handler, HttpMetricsLayerBuilder};
+/// use axum::{routing::get, Router};
+///
+/// let metrics = HttpMetricsLayerBuilder::new().build();
+/// let admin: Router = Router::new()
+///     .route("/internal/metrics", get(exporter_handler))
+///     .with_state(metrics.metric_state());
+/// ```
+pub async fn exporter_handler(state: State<MetricState>) -> impl IntoResponse {
+    HttpMetricsLayer::exporter_handler(state).await
+}
+
+/// The handler of the metrics endpoint reading the request, for the content negotiation, the compression,
+/// the conditional GET and the authorization, see [HttpMetricsLayer::exporter_request_handler].
+///
+/// ```
+/// use axum_otel_metrics::{exporter_request_handler, HttpMetricsLayerBuilder};
+/// use axum::{routing::get, Router};
+///
+/// let metrics = HttpMetricsLayerBuilder::new().build();
+/// let admin: Router = Router::new()
+///     .route("/internal/metrics", get(exporter_request_handler))
+///     .with_state(metrics.metric_state());
+/// ```
+pub async fn exporter_request_handler(
+    state: State<MetricState>,
+    request: Request<axum::body::Body>,
+) -> Response<axum::body::Body> {
+    HttpMetricsLayer::exporter_request_handler(state, request).await
+}
+
+/// The handler of the JSON metrics endpoint, see [HttpMetricsLayerBuilder::with_json_endpoint].
+///
+/// the metric families of the prometheus registry serialized as JSON:
+/// `[{"name", "help", "type", "metrics": [{"labels": {..}, "value"}]}]`,
+/// the histograms have `count`, `sum` and cumulative `buckets` (`le`, `count`) instead of `value`.
+///
+/// the requests are authorized like the ones of the metrics endpoint.
+pub async fn json_exporter_handler(state: State<MetricState>, request: Request<axum::body::Body>) -> Response<axum::body::Body> {
+    let authorized = state.endpoint_auth.authorize(&request, state.client_ip(&request));
+    if let Some(rejection) = authorized.await {
+        return rejection;
+    }
+    match state.registry {
+        Some(ref registry) => {
+            let mut families = registry.gather();
+            let default_families = state.default_families(&families);
+            families.extend(default_families);
+            Json(exposition::encode_json(&families)).into_response()
+        }
+        None => Json(serde_json::Value::Array(vec![])).into_response(),
+    }
+}
+
+/// The handler of the per-tenant metrics endpoints, see [HttpMetricsLayerBuilder::with_tenant_registries].
+///
+/// serves the registry of the tenant in the prometheus text format, `404 Not Found` for a tenant
+/// without recorded requests. the requests are authorized like the ones of the metrics endpoint.
+pub async fn tenant_exporter_handler(
+    state: State<MetricState>,
+    Path(tenant): Path<String>,
+    request: Request<axum::body::Body>,
+) -> Response<axum::body::Body> {
+    let authorized = state.endpoint_auth.authorize(&request, state.client_ip(&request));
+    if let Some(rejection) = authorized.await {
+        return rejection;
+    }
+    let registry = state
+        .tenant_registries
+        .as_ref()
+        .and_then(|registries| registries.registry(&tenant));
+    match registry {
+        Some(registry) => {
+            let mut buffer = vec![];
+            match TextEncoder::new().encode(&registry.gather(), &mut buffer) {
+                Ok(()) => ([(http::header::CONTENT_TYPE, exposition::TEXT_CONTENT_TYPE)], buffer).into_response(),
+                Err(err) => scrape_error(err.into()),
+            }
+        }
+        None => http::StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// the response of a failed scrape: `500 Internal Server Error` when the registry fails to encode,
+/// `503 Service Unavailable` when the scrape is over the budget of the scrape guard
+fn scrape_error(err: ScrapeError) -> Response<axum::body::Body> {
+    let status = match err {
+        ScrapeError::Encode(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+        ScrapeError::Timeout(_) | ScrapeError::TooLarge(_) => http::StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, [(http::header::CONTENT_TYPE, "text/plain; charset=utf-8")], err.to_string()).into_response()
+}
+
+/// A helper that instructs the metrics layer to ignore
+/// certain paths.
+///
+/// The [HttpMetricsLayerBuilder] uses this helper during the
+/// construction of the [HttpMetricsLayer] that will be called
+/// by Axum / Hyper / Tower when a request comes in.
+#[derive(Clone)]
+pub struct PathSkipper {
+    skip: Arc<dyn Fn(&str) -> bool + 'static + Send + Sync>,
+}
+
+impl PathSkipper {
+    /// Returns a [PathSkipper] that skips recording metrics
+    /// for requests whose path, when passed to `fn`, returns
+    /// `true`.
+    ///
+    /// Only static functions are accepted -- callables such
+    /// as closures that capture their surrounding context will
+    /// not work here.  For a variant that works, consult the
+    /// [PathSkipper::new_with_fn] method.
+    pub fn new(skip: fn(&str) -> bool) -> Self {
+        Self { skip: Arc::new(skip) }
+    }
+
+    /// Dynamic variant of [PathSkipper::new].
+    ///
+    /// This variant requires the callable to be wrapped in an
+    /// [Arc] but, in exchange for this requirement, the caller
+    /// can use closures that capture variables from their context.
+    ///
+    /// The callable argument *must be thread-safe*.  You, as
+    /// the implementor and user of this code, have that
+    /// responsibility.
+    pub fn new_with_fn(skip: Arc<dyn Fn(&str) -> bool + 'static + Send + Sync>) -> Self {
+        Self { skip: skip }
+    }
+
+    /// Returns a [PathSkipper] that records metrics *only* for
+    /// paths starting with one of `prefixes`, every other path
+    /// is skipped.
+    ///
+    /// ```
+    /// use axum_otel_metrics::PathSkipper;
+    ///
+    /// let skipper = PathSkipper::allow_only(["/api/"]);
+    /// ```
+    pub fn allow_only<I, P>(prefixes: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        let prefixes: Vec<String> = prefixes.into_iter().map(Into::into).collect();
+        Self::new_with_fn(Arc::new(move |path: &str| {
+            !prefixes.iter().any(|prefix| path.starts_with(prefix.as_str()))
+        }))
+    }
+
+    /// Returns a [PathSkipper] that skips any path matching
+    /// the regular expression `pattern`.
+    ///
+    /// ```
+    /// use axum_otel_metrics::PathSkipper;
+    ///
+    /// let skipper = PathSkipper::from_regex(r"^/internal/.*").unwrap();
+    /// ```
+    pub fn from_regex(pattern: &str) -> Result<Self, regex::Error> {
+        Self::from_regex_set([pattern])
+    }
+
+    /// Returns a [PathSkipper] that skips any path matching
+    /// at least one of `patterns`.
+    ///
+    /// The patterns are compiled once into a [regex::RegexSet]
+    /// so a path is matched against all of them in a single pass.
+    pub fn from_regex_set<I, P>(patterns: I) -> Result<Self, regex::Error>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<str>,
+    {
+        let set = regex::RegexSet::new(patterns)?;
+        Ok(Self::new_with_fn(Arc::new(move |path: &str| set.is_match(path))))
+    }
+
+    /// Returns a [PathSkipper] that skips any path matching
+    /// at least one of the glob `patterns`.
+    ///
+    /// `*` and `?` never match a `/`, use `**` to match across
+    /// path segments.  The patterns are compiled once into a
+    /// [globset::GlobSet].
+    ///
+    /// ```
+    /// use axum_otel_metrics::PathSkipper;
+    ///
+    /// let skipper = PathSkipper::from_globs(["/static/**", "/health*"]).unwrap();
+    /// ```
+    pub fn from_globs<I, P>(patterns: I) -> Result<Self, globset::Error>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<str>,
+    {
+        let mut builder = globset::GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(globset::GlobBuilder::new(pattern.as_ref()).literal_separator(true).build()?);
+        }
+        let set = builder.build()?;
+        Ok(Self::new_with_fn(Arc::new(move |path: &str| set.is_match(path))))
+    }
+
+    /// Returns a [PathSkipper] that skips exactly the paths
+    /// this one records, turning a skip-list into an allow-list.
+    pub fn invert(self) -> Self {
+        Self::new_with_fn(Arc::new(move |path: &str| !(self.skip)(path)))
+    }
+}
+
+impl Default for PathSkipper {
+    /// Returns a `PathSkipper` that skips any path which
+    /// starts with `/metrics` or `/favicon.ico``.
+    ///
+    /// This is the default implementation used when
+    /// building an HttpMetricsLayerBuilder from scratch.
     fn default() -> Self {
+        Self::new(|s| s.starts_with("/metrics") || s.starts_with("/favicon.ico"))
+    }
+}
+
+/// The parts of a request a [RequestSkipper] can look at.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct RequestInfo<'a> {
+    pub method: &'a http::Method,
+    pub uri: &'a http::Uri,
+    pub version: http::Version,
+    pub headers: &'a HeaderMap,
+    pub extensions: &'a http::Extensions,
+    /// the matched path, or the unmatched route label if no route matched
+    ///
+    /// it is the route of the router the layer is added to, not the one refined by [crate::propagate_matched_path].
+    pub route: &'a str,
+}
+
+/// A helper that instructs the metrics layer to ignore
+/// certain requests based on more than their path.
+///
+/// Unlike [PathSkipper], the callable receives the method,
+/// headers and extensions of the request, which allows skipping
+/// e.g. `OPTIONS` preflights, requests carrying an internal
+/// header, or gRPC-web traffic.  A request is skipped if either
+/// the [PathSkipper] or the [RequestSkipper] says so.
+#[derive(Clone)]
+pub struct RequestSkipper {
+    skip: Arc<dyn Fn(&RequestInfo<'_>) -> bool + 'static + Send + Sync>,
+}
+
+impl RequestSkipper {
+    /// Returns a [RequestSkipper] that skips recording metrics
+    /// for requests for which `fn` returns `true`.
+    ///
+    /// Like [PathSkipper::new], only static functions are
+    /// accepted.  For closures, consult the
+    /// [RequestSkipper::new_with_fn] method.
+    ///
+    /// ```
+    /// use axum_otel_metrics::RequestSkipper;
+    ///
+    /// let skipper = RequestSkipper::new(|req| req.headers.contains_key("x-internal-probe"));
+    /// ```
+    pub fn new(skip: fn(&RequestInfo<'_>) -> bool) -> Self {
+        Self { skip: Arc::new(skip) }
+    }
+
+    /// Dynamic variant of [RequestSkipper::new].
+    ///
+    /// The callable argument *must be thread-safe*.
+    pub fn new_with_fn(skip: Arc<dyn Fn(&RequestInfo<'_>) -> bool + 'static + Send + Sync>) -> Self {
+        Self { skip }
+    }
+}
+
+/// A helper that instructs the metrics layer to ignore
+/// certain responses.
+///
+/// The callable is evaluated once the response is available,
+/// so it can look at the status code and the response headers,
+/// e.g. to drop the 404s generated by internet scanners.
+#[derive(Clone)]
+pub struct ResponseSkipper {
+    skip: Arc<dyn Fn(http::StatusCode, &HeaderMap) -> bool + 'static + Send + Sync>,
+}
+
+impl ResponseSkipper {
+    /// Returns a [ResponseSkipper] that skips recording metrics
+    /// for responses for which `fn` returns `true`.
+    ///
+    /// Like [PathSkipper::new], only static functions are
+    /// accepted.  For closures, consult the
+    /// [ResponseSkipper::new_with_fn] method.
+    pub fn new(skip: fn(http::StatusCode, &HeaderMap) -> bool) -> Self {
+        Self { skip: Arc::new(skip) }
+    }
+
+    /// Dynamic variant of [ResponseSkipper::new].
+    ///
+    /// The callable argument *must be thread-safe*.
+    pub fn new_with_fn(skip: Arc<dyn Fn(http::StatusCode, &HeaderMap) -> bool + 'static + Send + Sync>) -> Self {
+        Self { skip }
+    }
+
+    /// Returns a [ResponseSkipper] that skips any response
+    /// with one of the status codes in `statuses`.
+    ///
+    /// ```
+    /// use axum_otel_metrics::ResponseSkipper;
+    /// use axum::http::StatusCode;
+    ///
+    /// let skipper = ResponseSkipper::status([StatusCode::NOT_FOUND]);
+    /// ```
+    pub fn status<I>(statuses: I) -> Self
+    where
+        I: IntoIterator<Item = http::StatusCode>,
+    {
+        let statuses: Vec<http::StatusCode> = statuses.into_iter().collect();
+        Self::new_with_fn(Arc::new(move |status, _| statuses.contains(&status)))
+    }
+}
+
+/// A helper that resolves the `url.scheme` attribute from the
+/// request headers.
+///
+/// The resolver is only consulted when the layer is not configured
+/// as a TLS server and the peer is a trusted proxy.  When it returns
+/// `None` the scheme falls back to `http`.
+#[derive(Clone)]
+pub struct SchemeResolver {
+    resolve: Arc<dyn Fn(&HeaderMap) -> Option<String> + 'static + Send + Sync>,
+}
+
+impl SchemeResolver {
+    /// Returns a [SchemeResolver] that resolves the scheme
+    /// by passing the request headers to `fn`.
+    ///
+    /// Like [PathSkipper::new], only static functions are
+    /// accepted.  For closures, consult the
+    /// [SchemeResolver::new_with_fn] method.
+    pub fn new(resolve: fn(&HeaderMap) -> Option<String>) -> Self {
         Self {
-            service_name: None,
-            service_version: None,
-            prefix: None,
-            path: "/metrics".to_string(),
-            labels: None,
-            skipper: PathSkipper::default(),
-            is_tls: false,
-            exporter: Some("prometheus".to_string()),
+            resolve: Arc::new(resolve),
+        }
+    }
+
+    /// Dynamic variant of [SchemeResolver::new].
+    ///
+    /// The callable argument *must be thread-safe*.
+    pub fn new_with_fn(resolve: Arc<dyn Fn(&HeaderMap) -> Option<String> + 'static + Send + Sync>) -> Self {
+        Self { resolve }
+    }
+}
+
+impl Default for SchemeResolver {
+    /// Returns a `SchemeResolver` that looks at, in order, the
+    /// `proto` parameter of the `Forwarded` header,
+    /// `X-Forwarded-Proto`, `X-Forwarded-Protocol`,
+    /// `X-Forwarded-Ssl: on` and `X-Url-Scheme`.
+    fn default() -> Self {
+        Self::new(|headers| {
+            if let Some(proto) = header_str(headers, http::header::FORWARDED.as_str())
+                .map(parse_forwarded)
+                .and_then(|fwd| fwd.proto)
+            {
+                return Some(proto);
+            }
+            if let Some(scheme) = header_str(headers, "X-Forwarded-Proto") {
+                return Some(scheme.to_string());
+            } else if let Some(scheme) = header_str(headers, "X-Forwarded-Protocol") {
+                return Some(scheme.to_string());
+            }
+            if header_str(headers, "X-Forwarded-Ssl").is_some_and(|v| v.eq_ignore_ascii_case("on")) {
+                return Some("https".to_string());
+            }
+            header_str(headers, "X-Url-Scheme").map(str::to_string)
+        })
+    }
+}
+
+/// A helper that extracts the route template of a request,
+/// recorded as the `http.route` attribute.
+///
+/// The default extractor reads the [MatchedPath] set by the axum
+/// router.  A custom extractor lets the layer wrap plain hyper or
+/// tower services, which have no [MatchedPath], and still record
+/// low cardinality routes.  When it returns `None` the request is
+/// labeled as configured by [UnmatchedRoute].
+#[derive(Clone)]
+pub struct RouteExtractor {
+    extract: Arc<dyn Fn(&http::Uri, &http::Extensions) -> Option<String> + 'static + Send + Sync>,
+}
+
+impl RouteExtractor {
+    /// Returns a [RouteExtractor] that extracts the route
+    /// by passing the request uri and extensions to `fn`.
+    ///
+    /// Like [PathSkipper::new], only static functions are
+    /// accepted.  For closures, consult the
+    /// [RouteExtractor::new_with_fn] method.
+    ///
+    /// ```
+    /// use axum_otel_metrics::RouteExtractor;
+    ///
+    /// let extractor = RouteExtractor::new(|uri, _| match uri.path() {
+    ///     path if path.starts_with("/users/") => Some("/users/{id}".to_string()),
+    ///     _ => None,
+    /// });
+    /// ```
+    pub fn new(extract: fn(&http::Uri, &http::Extensions) -> Option<String>) -> Self {
+        Self {
+            extract: Arc::new(extract),
+        }
+    }
+
+    /// Dynamic variant of [RouteExtractor::new].
+    ///
+    /// The callable argument *must be thread-safe*.
+    pub fn new_with_fn(extract: Arc<dyn Fn(&http::Uri, &http::Extensions) -> Option<String> + 'static + Send + Sync>) -> Self {
+        Self { extract }
+    }
+
+    /// Returns a [RouteExtractor] that reads the [MatchedPath]
+    /// of the axum router, and matches the raw path of the
+    /// requests without one against the route `templates`,
+    /// e.g. the ones served by a fallback or a proxied prefix.
+    ///
+    /// The templates accept both the `:id` and `{id}` parameters,
+    /// and a trailing `*rest` or `{*rest}` catch-all.  The match
+    /// with the most literal segments wins.
+    ///
+    /// ```
+    /// use axum_otel_metrics::RouteExtractor;
+    ///
+    /// let extractor = RouteExtractor::with_templates(["/legacy/users/:id", "/proxy/*rest"]);
+    /// ```
+    pub fn with_templates<T: Into<String>>(templates: impl IntoIterator<Item = T>) -> Self {
+        let table = RouteTable::new(templates.into_iter().map(Into::into));
+        Self::new_with_fn(Arc::new(move |uri, extensions| {
+            extensions
+                .get::<MatchedPath>()
+                .map(|path| path.as_str().to_owned())
+                .or_else(|| table.find(uri.path()).map(str::to_owned))
+        }))
+    }
+}
+
+impl Default for RouteExtractor {
+    /// Returns a `RouteExtractor` that reads the [MatchedPath]
+    /// of the axum router.
+    fn default() -> Self {
+        Self::new(|_, extensions| extensions.get::<MatchedPath>().map(|path| path.as_str().to_owned()))
+    }
+}
+
+/// A helper that extracts the tenant of a request, for the
+/// per-tenant metrics of [HttpMetricsLayerBuilder::with_tenant_registries].
+///
+/// The extractor gets the request headers and extensions, e.g.
+/// a tenant header or the claims set by an authentication layer.
+/// When it returns `None` the request is only recorded in the main
+/// registry.
+#[derive(Clone)]
+pub struct TenantExtractor {
+    extract: Arc<dyn Fn(&HeaderMap, &http::Extensions) -> Option<String> + 'static + Send + Sync>,
+}
+
+impl TenantExtractor {
+    /// Returns a [TenantExtractor] that extracts the tenant
+    /// by passing the request headers and extensions to `fn`.
+    ///
+    /// Like [PathSkipper::new], only static functions are
+    /// accepted.  For closures, consult the
+    /// [TenantExtractor::new_with_fn] method.
+    pub fn new(extract: fn(&HeaderMap, &http::Extensions) -> Option<String>) -> Self {
+        Self {
+            extract: Arc::new(extract),
+        }
+    }
+
+    /// Dynamic variant of [TenantExtractor::new].
+    ///
+    /// The callable argument *must be thread-safe*.
+    pub fn new_with_fn(extract: Arc<dyn Fn(&HeaderMap, &http::Extensions) -> Option<String> + 'static + Send + Sync>) -> Self {
+        Self { extract }
+    }
+
+    /// Returns a [TenantExtractor] that reads the tenant from
+    /// the header `name`, e.g. `X-Tenant-Id`.
+    pub fn from_header(name: &str) -> Self {
+        let name = name.to_string();
+        Self::new_with_fn(Arc::new(move |headers, _| {
+            header_str(headers, &name).filter(|v| !v.is_empty()).map(str::to_string)
+        }))
+    }
+}
+
+/// A helper that extracts the credential identifier of a request,
+/// e.g. its API key, for [HttpMetricsLayerBuilder::with_api_key_attribute].
+///
+/// The identifier is hashed before it is recorded, so the
+/// extractor can return the raw secret.
+#[derive(Clone)]
+pub struct CredentialExtractor {
+    extract: Arc<dyn Fn(&HeaderMap, &http::Extensions) -> Option<String> + 'static + Send + Sync>,
+}
+
+impl CredentialExtractor {
+    /// Returns a [CredentialExtractor] that extracts the credential
+    /// by passing the request headers and extensions to `fn`.
+    ///
+    /// Like [PathSkipper::new], only static functions are
+    /// accepted.  For closures, consult the
+    /// [CredentialExtractor::new_with_fn] method.
+    pub fn new(extract: fn(&HeaderMap, &http::Extensions) -> Option<String>) -> Self {
+        Self {
+            extract: Arc::new(extract),
+        }
+    }
+
+    /// Dynamic variant of [CredentialExtractor::new].
+    ///
+    /// The callable argument *must be thread-safe*.
+    pub fn new_with_fn(extract: Arc<dyn Fn(&HeaderMap, &http::Extensions) -> Option<String> + 'static + Send + Sync>) -> Self {
+        Self { extract }
+    }
+
+    /// Returns a [CredentialExtractor] that reads the credential
+    /// from the header `name`, e.g. `X-Api-Key`.
+    pub fn from_header(name: &str) -> Self {
+        let name = name.to_string();
+        Self::new_with_fn(Arc::new(move |headers, _| {
+            header_str(headers, &name).filter(|v| !v.is_empty()).map(str::to_string)
+        }))
+    }
+
+    /// Returns a [CredentialExtractor] that reads the bearer
+    /// token of the `Authorization` header.
+    pub fn bearer_token() -> Self {
+        Self::new(|headers, _| {
+            header_str(headers, http::header::AUTHORIZATION.as_str())
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .map(|token| token.trim().to_string())
+                .filter(|token| !token.is_empty())
+        })
+    }
+}
+
+/// lets the builder stay [Clone] while handing boxed detectors to [Resource::from_detectors]
+struct SharedResourceDetector(Arc<dyn ResourceDetector + Send + Sync>);
+
+impl ResourceDetector for SharedResourceDetector {
+    fn detect(&self, timeout: Duration) -> Resource {
+        self.0.detect(timeout)
+    }
+}
+
+#[derive(Clone)]
+pub struct HttpMetricsLayerBuilder {
+    service_name: Option<String>,
+    service_version: Option<String>,
+    deployment_environment: Option<String>,
+    prefix: Option<String>,
+    path: String,
+    labels: Option<HashMap<String, String>>,
+    target_info: bool,
+    scope_info: bool,
+    skipper: PathSkipper,
+    request_skipper: Option<RequestSkipper>,
+    response_skipper: Option<ResponseSkipper>,
+    is_tls: bool,
+    client_address: bool,
+    network_peer: bool,
+    server_port: bool,
+    query_keys: bool,
+    trusted_proxies: Option<Arc<[IpNet]>>,
+    scheme_resolver: SchemeResolver,
+    protocol_version: bool,
+    baggage_keys: Vec<String>,
+    route_extractor: RouteExtractor,
+    unmatched_route: UnmatchedRoute,
+    cors_preflight: CorsPreflight,
+    head_requests: HeadRequests,
+    redirect_metrics: bool,
+    throttling_metrics: bool,
+    not_found_label: Option<NotFoundLabel>,
+    route_grouper: Option<Arc<RouteGrouperFn>>,
+    attribute_filter: AttributeFilter,
+    attribute_redactor: Option<Arc<AttributeRedactorFn>>,
+    attribute_naming: AttributeNaming,
+    cardinality_limits: HashMap<Key, usize>,
+    duration_buckets: Option<Vec<f64>>,
+    route_buckets: HashMap<String, Vec<f64>>,
+    sample_rate: f64,
+    histogram_mode: HistogramMode,
+    duration_histogram: DurationHistogram,
+    slow_request_thresholds: Vec<(String, Duration)>,
+    long_running_threshold: Option<Duration>,
+    route_active_requests: bool,
+    apdex_target: Option<Duration>,
+    objectives: Vec<Objective>,
+    requests_counter_name: Option<String>,
+    descriptions: HashMap<HttpInstrument, String>,
+    units: HashMap<HttpInstrument, String>,
+    instrument_prefix: Option<String>,
+    const_attributes: Vec<KeyValue>,
+    resource: Option<Resource>,
+    resource_attributes: Vec<KeyValue>,
+    resource_detectors: Vec<Arc<dyn ResourceDetector + Send + Sync>>,
+    default_resource_detectors: bool,
+    resource_detection_timeout: Duration,
+    scope_name: Option<String>,
+    scope_version: Option<Option<String>>,
+    schema_url: Option<Option<String>>,
+    git_sha: Option<String>,
+    rustc_version: Option<String>,
+    build_info_metric: bool,
+    uptime_metrics: bool,
+    grpc_metrics: bool,
+    graphql_metrics: bool,
+    sse_metrics: bool,
+    compression_metrics: bool,
+    handler_timing: bool,
+    queue_time: bool,
+    throughput_metrics: bool,
+    size_metrics: bool,
+    split_sizes: bool,
+    tenant_registries: Option<(TenantExtractor, usize)>,
+    tenant_attribute: Option<(TenantExtractor, usize)>,
+    api_key_attribute: Option<(CredentialExtractor, String)>,
+    #[cfg(feature = "websocket")]
+    websocket_metrics: bool,
+    scrape_metrics: bool,
+    scrape_timeout: Option<Duration>,
+    max_scrape_size: Option<usize>,
+    conditional_get: bool,
+    default_registry: bool,
+    response_cache_ttl: Option<Duration>,
+    scrape_rate_limit: Option<u32>,
+    endpoint_auth: EndpointAuth,
+    json_endpoint: bool,
+    exemplars: bool,
+    #[cfg(all(feature = "process-metrics", target_os = "linux"))]
+    process_metrics: bool,
+    export_error_handler: Option<Arc<ExportErrorFn>>,
+    exporter: Option<String>,
+}
+
+impl Default for HttpMetricsLayerBuilder {
+    fn default() -> Self {
+        Self {
+            service_name: None,
+            service_version: None,
+            deployment_environment: None,
+            prefix: None,
+            path: "/metrics".to_string(),
+            labels: None,
+            target_info: true,
+            scope_info: true,
+            skipper: PathSkipper::default(),
+            request_skipper: None,
+            response_skipper: None,
+            is_tls: false,
+            client_address: false,
+            network_peer: false,
+            server_port: false,
+            query_keys: false,
+            trusted_proxies: None,
+            scheme_resolver: SchemeResolver::default(),
+            protocol_version: false,
+            baggage_keys: vec![],
+            route_extractor: RouteExtractor::default(),
+            unmatched_route: UnmatchedRoute::default(),
+            cors_preflight: CorsPreflight::default(),
+            head_requests: HeadRequests::default(),
+            redirect_metrics: false,
+            throttling_metrics: false,
+            not_found_label: None,
+            route_grouper: None,
+            attribute_filter: AttributeFilter::All,
+            attribute_redactor: None,
+            attribute_naming: AttributeNaming::default(),
+            cardinality_limits: HashMap::new(),
+            duration_buckets: None,
+            route_buckets: HashMap::new(),
+            sample_rate: 1.0,
+            histogram_mode: HistogramMode::default(),
+            duration_histogram: DurationHistogram::default(),
+            slow_request_thresholds: vec![],
+            long_running_threshold: None,
+            route_active_requests: false,
+            apdex_target: None,
+            objectives: vec![],
+            requests_counter_name: Some("requests".to_string()),
+            descriptions: HashMap::new(),
+            units: HashMap::new(),
+            instrument_prefix: None,
+            const_attributes: vec![],
+            resource: None,
+            resource_attributes: vec![],
+            resource_detectors: vec![],
+            default_resource_detectors: true,
+            resource_detection_timeout: Duration::from_secs(6),
+            scope_name: None,
+            scope_version: None,
+            schema_url: None,
+            git_sha: None,
+            rustc_version: None,
+            build_info_metric: false,
+            uptime_metrics: false,
+            grpc_metrics: false,
+            graphql_metrics: false,
+            sse_metrics: false,
+            compression_metrics: false,
+            handler_timing: false,
+            queue_time: false,
+            throughput_metrics: false,
+            size_metrics: true,
+            split_sizes: false,
+            tenant_registries: None,
+            tenant_attribute: None,
+            api_key_attribute: None,
+            #[cfg(feature = "websocket")]
+            websocket_metrics: false,
+            scrape_metrics: false,
+            scrape_timeout: None,
+            max_scrape_size: None,
+            conditional_get: false,
+            default_registry: true,
+            response_cache_ttl: None,
+            scrape_rate_limit: None,
+            endpoint_auth: EndpointAuth::default(),
+            json_endpoint: false,
+            exemplars: false,
+            #[cfg(all(feature = "process-metrics", target_os = "linux"))]
+            process_metrics: false,
+            export_error_handler: None,
+            exporter: Some("prometheus".to_string()),
+        }
+    }
+}
+
+impl HttpMetricsLayerBuilder {
+    pub fn new() -> Self {
+        HttpMetricsLayerBuilder::default()
+    }
+
+    /// a builder configured from the environment, for the containerized deployments tuning the metrics without code changes:
+    ///
+    /// - `AXUM_OTEL_METRICS_EXPORTER`: `prometheus` (the default) or `otlp`, see [HttpMetricsLayerBuilder::with_exporter]
+    /// - `AXUM_OTEL_METRICS_PATH`: the path of the metrics endpoint, `/metrics` by default, its requests are skipped
+    /// - `AXUM_OTEL_METRICS_PREFIX`: the prefix of the prometheus metric names, see [HttpMetricsLayerBuilder::with_prefix]
+    /// - `AXUM_OTEL_METRICS_SKIP_PATHS`: comma separated glob patterns of the paths to skip,
+    ///   on top of the ones of the default [PathSkipper], e.g. `/health*,/static/**`
+    /// - `AXUM_OTEL_METRICS_DURATION_BUCKETS`: comma separated request duration buckets in seconds,
+    ///   e.g. `0.01,0.1,1,10`
+    /// - `AXUM_OTEL_METRICS_CONST_LABELS`: comma separated `key=value` attributes of every measurement,
+    ///   e.g. `region=eu-west-1,cluster=blue`, see [HttpMetricsLayerBuilder::with_const_attributes]
+    ///
+    /// the unset variables keep the defaults, the invalid ones are reported to the global error handler and ignored.
+    /// the other options can still be set on the returned builder.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    /// [HttpMetricsLayerBuilder::from_env] with the variables read from `lookup`
+    fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let invalid = |name: &str, value: &str, reason: &dyn std::fmt::Display| {
+            global::handle_error(opentelemetry::metrics::MetricsError::Config(format!(
+                "invalid {name} {value:?}: {reason}"
+            )));
+        };
+        let var = |name: &str| lookup(name).map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+        let mut builder = Self::new();
+
+        if let Some(exporter) = var("AXUM_OTEL_METRICS_EXPORTER") {
+            match exporter.as_str() {
+                "prometheus" | "otlp" => builder = builder.with_exporter(exporter),
+                _ => invalid("AXUM_OTEL_METRICS_EXPORTER", &exporter, &"expected `prometheus` or `otlp`"),
+            }
+        }
+        let path = var("AXUM_OTEL_METRICS_PATH");
+        if let Some(path) = &path {
+            builder = builder.with_path(path.clone());
+        }
+        if let Some(prefix) = var("AXUM_OTEL_METRICS_PREFIX") {
+            builder = builder.with_prefix(prefix);
+        }
+        let globs = var("AXUM_OTEL_METRICS_SKIP_PATHS").and_then(|patterns| {
+            PathSkipper::from_globs(patterns.split(',').map(str::trim).filter(|p| !p.is_empty()))
+                .map_err(|err| invalid("AXUM_OTEL_METRICS_SKIP_PATHS", &patterns, &err))
+                .ok()
+        });
+        if path.is_some() || globs.is_some() {
+            // the scrapes of the configured metrics endpoint are skipped like the ones of `/metrics`
+            let default = PathSkipper::default();
+            builder = builder.with_skipper(PathSkipper::new_with_fn(Arc::new(move |p: &str| {
+                (default.skip)(p) || path.as_deref() == Some(p) || globs.as_ref().is_some_and(|globs| (globs.skip)(p))
+            })));
+        }
+        if let Some(buckets) = var("AXUM_OTEL_METRICS_DURATION_BUCKETS") {
+            match buckets.split(',').map(|b| b.trim().parse::<f64>()).collect::<Result<Vec<_>, _>>() {
+                Ok(parsed) if parsed.windows(2).all(|w| w[0] < w[1]) => builder = builder.with_duration_buckets(parsed),
+                Ok(_) => invalid("AXUM_OTEL_METRICS_DURATION_BUCKETS", &buckets, &"the buckets must be increasing"),
+                Err(err) => invalid("AXUM_OTEL_METRICS_DURATION_BUCKETS", &buckets, &err),
+            }
+        }
+        if let Some(labels) = var("AXUM_OTEL_METRICS_CONST_LABELS") {
+            let parsed = labels
+                .split(',')
+                .filter(|label| !label.trim().is_empty())
+                .map(|label| {
+                    let (key, value) = label.split_once('=')?;
+                    let key = key.trim();
+                    (!key.is_empty()).then(|| KeyValue::new(key.to_string(), value.trim().to_string()))
+                })
+                .collect::<Option<Vec<_>>>();
+            match parsed {
+                Some(attributes) => builder = builder.with_const_attributes(attributes),
+                None => invalid("AXUM_OTEL_METRICS_CONST_LABELS", &labels, &"expected comma separated `key=value`"),
+            }
+        }
+        builder
+    }
+
+    pub fn with_service_name(mut self, service_name: String) -> Self {
+        self.service_name = Some(service_name);
+        self
+    }
+
+    pub fn with_service_version(mut self, service_version: String) -> Self {
+        self.service_version = Some(service_version);
+        self
+    }
+
+    /// set the `deployment.environment.name` resource attribute, e.g. `staging` or `production`.
+    pub fn with_deployment_environment(mut self, environment: String) -> Self {
+        self.deployment_environment = Some(environment);
+        self
+    }
+
+    pub fn with_prefix(mut self, prefix: String) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
+
+    pub fn with_path(mut self, path: String) -> Self {
+        self.path = path;
+        self
+    }
+
+    /// set labels attached to every exported metric.
+    ///
+    /// for the prometheus exporter they are const labels of the registry,
+    /// for the otlp exporter they are merged into the OTel [Resource] as resource attributes.
+    pub fn with_labels(mut self, labels: HashMap<String, String>) -> Self {
+        self.labels = Some(labels);
+        self
+    }
+
+    /// whether the prometheus exporter exports the resource attributes as the `target_info` gauge, `true` by default.
+    pub fn with_target_info(mut self, enabled: bool) -> Self {
+        self.target_info = enabled;
+        self
+    }
+
+    /// whether the prometheus exporter adds the `otel_scope_name` and `otel_scope_version` labels to every metric
+    /// and exports the `otel_scope_info` gauge, `true` by default.
+    pub fn with_scope_info(mut self, enabled: bool) -> Self {
+        self.scope_info = enabled;
+        self
+    }
+
+    pub fn with_skipper(mut self, skipper: PathSkipper) -> Self {
+        self.skipper = skipper;
+        self
+    }
+
+    /// whether the service is running as a TLS server, recorded as the `https` `url.scheme` of every request.
+    ///
+    /// servers terminating both schemes can instead insert a [TlsConnection] extension
+    /// in the requests of their TLS connections.
+    pub fn with_tls(mut self, enabled: bool) -> Self {
+        self.is_tls = enabled;
+        self
+    }
+
+    /// record the `client.address` attribute on the request metrics.
+    ///
+    /// the address is taken from the `for` parameter of the RFC 7239 `Forwarded` header,
+    /// then the first entry of `X-Forwarded-For`, then the peer address from [axum::extract::ConnectInfo].
+    /// this is disabled by default since client addresses are usually high cardinality.
+    pub fn with_client_address(mut self, enabled: bool) -> Self {
+        self.client_address = enabled;
+        self
+    }
+
+    /// record the `network.peer.address` and `network.peer.port` attributes on the request metrics,
+    /// the socket address of the direct peer from [axum::extract::ConnectInfo], e.g. a sidecar proxy.
+    ///
+    /// unlike `client.address`, the forwarded headers are ignored.
+    /// this is disabled by default since peer ports are high cardinality.
+    pub fn with_network_peer(mut self, enabled: bool) -> Self {
+        self.network_peer = enabled;
+        self
+    }
+
+    /// record the port of the `Host` header as the `server.port` attribute of the request metrics,
+    /// the default one of the `url.scheme` if the header has none, and only the host as the `server.address`.
+    ///
+    /// this is disabled by default, the `server.address` then keeps the port of the `Host` header, e.g. `host:8080`.
+    pub fn with_server_port(mut self, enabled: bool) -> Self {
+        self.server_port = enabled;
+        self
+    }
+
+    /// record the `url.query_keys` attribute on the request metrics, the sorted and deduplicated names
+    /// of the query parameters without their values, e.g. `limit,offset`.
+    ///
+    /// requests without a query do not get the attribute.
+    pub fn with_query_keys(mut self, enabled: bool) -> Self {
+        self.query_keys = enabled;
+        self
+    }
+
+    /// only honor the `Forwarded` and `X-Forwarded-*` headers when the peer address is in one of `proxies`.
+    ///
+    /// by default all peers are trusted: any client reaching the app directly can then set the `url.scheme`,
+    /// `server.address` and `client.address` of its requests with these headers, so set the proxies
+    /// whenever the app is reachable without going through them.
+    ///
+    /// the peer address is read from [axum::extract::ConnectInfo], so the app must be served with
+    /// `into_make_service_with_connect_info::<SocketAddr>()`, otherwise no peer is trusted.
+    /// for untrusted peers `url.scheme` falls back to the TLS setting and `server.address` to the `Host` header.
+    pub fn with_trusted_proxies(mut self, proxies: Vec<IpNet>) -> Self {
+        self.trusted_proxies = Some(proxies.into());
+        self
+    }
+
+    pub fn with_scheme_resolver(mut self, scheme_resolver: SchemeResolver) -> Self {
+        self.scheme_resolver = scheme_resolver;
+        self
+    }
+
+    /// record the `network.protocol.version` attribute (`1.0`, `1.1`, `2` or `3`) on all instruments.
+    pub fn with_protocol_version(mut self, enabled: bool) -> Self {
+        self.protocol_version = enabled;
+        self
+    }
+
+    /// record the W3C `baggage` entries named in `keys` as attributes of the request metrics,
+    /// e.g. `tenant` or `plan` propagated by the upstream services.
+    ///
+    /// requests without the entry do not get the attribute, consider [HttpMetricsLayerBuilder::with_cardinality_limit]
+    /// since the values are client supplied.
+    pub fn with_baggage_attributes(mut self, keys: Vec<String>) -> Self {
+        self.baggage_keys = keys;
+        self
+    }
+
+    /// set how the route template of a request is extracted, see [RouteExtractor].
+    ///
+    /// needed to record the `http.route` attribute of plain hyper or tower services, without the axum router.
+    pub fn with_route_extractor(mut self, route_extractor: RouteExtractor) -> Self {
+        self.route_extractor = route_extractor;
+        self
+    }
+
+    /// set how the `http.route` attribute is recorded for requests that did not match any route.
+    ///
+    /// defaults to [UnmatchedRoute::Label] with [UNMATCHED_ROUTE_LABEL].
+    pub fn with_unmatched_route(mut self, unmatched_route: UnmatchedRoute) -> Self {
+        self.unmatched_route = unmatched_route;
+        self
+    }
+
+    /// set how the CORS preflight requests are recorded, see [CorsPreflight].
+    ///
+    /// defaults to [CorsPreflight::Record], like any other request.
+    pub fn with_cors_preflight(mut self, cors_preflight: CorsPreflight) -> Self {
+        self.cors_preflight = cors_preflight;
+        self
+    }
+
+    /// set how the `HEAD` requests are recorded, see [HeadRequests].
+    ///
+    /// defaults to [HeadRequests::Record], with their own `http.request.method` attribute.
+    pub fn with_head_requests(mut self, head_requests: HeadRequests) -> Self {
+        self.head_requests = head_requests;
+        self
+    }
+
+    /// rewrite the matched path before it is recorded as the `http.route` attribute,
+    /// e.g. to collapse `/api/v1/users/:id` and `/api/v2/users/:id` into `/api/*/users/:id`.
+    ///
+    /// the [PathSkipper] still sees the original matched path.
+    pub fn with_route_grouper<F>(mut self, grouper: F) -> Self
+    where
+        F: for<'a> Fn(&'a str) -> Cow<'a, str> + 'static + Send + Sync,
+    {
+        self.route_grouper = Some(Arc::new(grouper));
+        self
+    }
+
+    /// never record the built-in attributes `keys`, e.g. `server.address` when every pod sees a different
+    /// `Host` header, or `url.scheme`, to reduce the number of series without configuring views.
+    ///
+    /// the constant attributes are always recorded. replaces [HttpMetricsLayerBuilder::with_attribute_allowlist].
+    pub fn with_attribute_denylist<K: Into<Key>>(mut self, keys: impl IntoIterator<Item = K>) -> Self {
+        self.attribute_filter = AttributeFilter::Deny(Arc::new(keys.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    /// only record the built-in attributes `keys`, e.g. `http.request.method`, `http.route` and
+    /// `http.response.status_code`.
+    ///
+    /// the constant attributes are always recorded. replaces [HttpMetricsLayerBuilder::with_attribute_denylist].
+    pub fn with_attribute_allowlist<K: Into<Key>>(mut self, keys: impl IntoIterator<Item = K>) -> Self {
+        self.attribute_filter = AttributeFilter::Allow(Arc::new(keys.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    /// scrub every attribute value before it is recorded, e.g. the emails, UUIDs or tokens
+    /// of a raw path recorded for the unmatched routes, see [redact_pii] for a ready-made redactor.
+    ///
+    /// the redactor runs before the cardinality limits, so the scrubbed values share their series.
+    pub fn with_attribute_redactor<F>(mut self, redactor: F) -> Self
+    where
+        F: for<'a> Fn(&Key, &'a str) -> Cow<'a, str> + 'static + Send + Sync,
+    {
+        self.attribute_redactor = Some(Arc::new(redactor));
+        self
+    }
+
+    /// control how the attribute keys are emitted, e.g. [AttributeNaming::underscored] for an OTLP backend
+    /// queried with the prometheus label names, or a table of renames with [AttributeNaming::with_rename].
+    ///
+    /// the naming applies to the exporter of the layer, the prometheus exporter turns any remaining dot into
+    /// an underscore. the filters, redactor and cardinality limits still use the semantic conventions keys.
+    pub fn with_attribute_naming(mut self, naming: AttributeNaming) -> Self {
+        self.attribute_naming = naming;
+        self
+    }
+
+    /// record at most `max` distinct values of the attribute `key`, e.g. `http.route` or `server.address`.
+    ///
+    /// once the limit is reached, any new value is recorded as [OVERFLOW_VALUE].
+    /// the limit is shared across all requests and instruments of the layer.
+    pub fn with_cardinality_limit(mut self, key: impl Into<Key>, max: usize) -> Self {
+        self.cardinality_limits.insert(key.into(), max);
+        self
+    }
+
+    /// use `buckets` (in seconds) instead of the default buckets for the request duration of `route`.
+    ///
+    /// `route` is matched against the `http.route` attribute, i.e. after the route grouper is applied.
+    /// each route gets its own `http.server.request.duration` instrument under a dedicated
+    /// instrumentation scope, so a slow route does not force huge buckets onto every other route.
+    pub fn with_route_buckets(mut self, route: String, buckets: Vec<f64>) -> Self {
+        self.route_buckets.insert(route, buckets);
+        self
+    }
+
+    /// use `buckets` (in seconds) instead of the default buckets for the request duration of every route
+    /// without buckets of its own.
+    pub fn with_duration_buckets(mut self, buckets: Vec<f64>) -> Self {
+        self.duration_buckets = Some(buckets);
+        self
+    }
+
+    pub fn with_request_skipper(mut self, request_skipper: RequestSkipper) -> Self {
+        self.request_skipper = Some(request_skipper);
+        self
+    }
+
+    pub fn with_response_skipper(mut self, response_skipper: ResponseSkipper) -> Self {
+        self.response_skipper = Some(response_skipper);
+        self
+    }
+
+    /// only record the duration and size histograms for a deterministic fraction `rate` of the requests,
+    /// the requests counter is always incremented.
+    ///
+    /// the rate can be adjusted at runtime through [HttpMetricsLayer::sample_rate].
+    pub fn with_sample_rate(mut self, rate: f64) -> Self {
+        self.sample_rate = rate;
+        self
+    }
+
+    /// set which responses get their duration and size histograms recorded, e.g. errors and slow requests only.
+    pub fn with_histogram_mode(mut self, histogram_mode: HistogramMode) -> Self {
+        self.histogram_mode = histogram_mode;
+        self
+    }
+
+    /// set how the `http.server.request.duration` histogram is aggregated, see [DurationHistogram].
+    pub fn with_duration_histogram(mut self, duration_histogram: DurationHistogram) -> Self {
+        self.duration_histogram = duration_histogram;
+        self
+    }
+
+    /// count the requests slower than `threshold` in the `http.server.slow_requests` counter,
+    /// with the attribute `threshold="slow"`.
+    pub fn with_slow_request_threshold(self, threshold: Duration) -> Self {
+        self.with_named_slow_request_threshold("slow".to_string(), threshold)
+    }
+
+    /// like [HttpMetricsLayerBuilder::with_slow_request_threshold], but with the attribute `threshold=name`.
+    ///
+    /// can be called multiple times, e.g. for a `warn` and a `critical` threshold,
+    /// a request is counted once for every threshold it exceeds.
+    pub fn with_named_slow_request_threshold(mut self, name: String, threshold: Duration) -> Self {
+        self.slow_request_thresholds.retain(|(n, _)| *n != name);
+        self.slow_request_thresholds.push((name, threshold));
+        self
+    }
+
+    /// observe the number of requests in flight for longer than `threshold` in the
+    /// `http.server.long_running_requests` gauge, labeled by method and route.
+    ///
+    /// unlike `http.server.active_requests`, this needs to track the start time of every
+    /// active request, which costs a lock per request.
+    pub fn with_long_running_threshold(mut self, threshold: Duration) -> Self {
+        self.long_running_threshold = Some(threshold);
+        self
+    }
+
+    /// count the active requests in the `http.server.route.active_requests` gauge, labeled by method and route,
+    /// to see which endpoints hold the concurrency during an incident.
+    ///
+    /// `http.server.active_requests` only carries the method and scheme, as per the semantic conventions.
+    pub fn with_route_active_requests(mut self, enabled: bool) -> Self {
+        self.route_active_requests = enabled;
+        self
+    }
+
+    /// count the requests by apdex satisfaction with target latency `target` in the `http.server.apdex` counter,
+    /// with the attribute `apdex.result` set to `satisfied`, `tolerating` or `frustrated`.
+    ///
+    /// the apdex score of a route is then `(satisfied + tolerating / 2) / total`.
+    pub fn with_apdex_target(mut self, target: Duration) -> Self {
+        self.apdex_target = Some(target);
+        self
+    }
+
+    /// count the requests matching `objective` in the `slo.requests` counter, and the ones that met it
+    /// in the `slo.requests.good` counter, both with the attribute `slo.name`.
+    ///
+    /// can be called multiple times to declare several objectives.
+    pub fn with_slo(mut self, objective: Objective) -> Self {
+        self.objectives.push(objective);
+        self
+    }
+
+    /// set the instrument name of the requests counter, `requests` by default (exported as `requests_total`).
+    ///
+    /// e.g. `http.server.requests` is exported as `http_server_requests_total`.
+    pub fn with_requests_counter_name(mut self, name: String) -> Self {
+        self.requests_counter_name = Some(name);
+        self
+    }
+
+    /// whether to record the requests counter at all, `true` by default.
+    ///
+    /// the count of the `http.server.request.duration` histogram carries the same information,
+    /// so the counter can be dropped to save series.
+    pub fn with_requests_counter(mut self, enabled: bool) -> Self {
+        match (enabled, self.requests_counter_name.is_some()) {
+            (true, false) => self.requests_counter_name = Some("requests".to_string()),
+            (false, _) => self.requests_counter_name = None,
+            _ => {}
+        }
+        self
+    }
+
+    /// override the description of `instrument`, shown in the `# HELP` line and the OTLP metadata.
+    pub fn with_description(mut self, instrument: HttpInstrument, description: String) -> Self {
+        self.descriptions.insert(instrument, description);
+        self
+    }
+
+    /// override the unit of `instrument`.
+    ///
+    /// the unit is metadata only, the recorded values are not converted,
+    /// e.g. the request duration is always recorded in seconds.
+    pub fn with_unit(mut self, instrument: HttpInstrument, unit: String) -> Self {
+        self.units.insert(instrument, unit);
+        self
+    }
+
+    /// prefix the name of every instrument with `prefix.`, e.g. `myapp.http.server.request.duration`.
+    ///
+    /// unlike [HttpMetricsLayerBuilder::with_prefix], which only applies to the prometheus registry,
+    /// the prefix is part of the instrument name so it works the same for every exporter.
+    pub fn with_instrument_prefix(mut self, prefix: String) -> Self {
+        self.instrument_prefix = Some(prefix);
+        self
+    }
+
+    /// append `attributes` to the attributes of every recorded measurement, e.g. `region` or `cluster`.
+    ///
+    /// unlike [HttpMetricsLayerBuilder::with_labels], which only become const labels of the prometheus registry,
+    /// these attributes reach every exporter.
+    pub fn with_const_attributes(mut self, attributes: Vec<KeyValue>) -> Self {
+        self.const_attributes = attributes;
+        self
+    }
+
+    /// merge `resource` into the detected resource, its attributes take precedence over the detected ones.
+    pub fn with_resource(mut self, resource: Resource) -> Self {
+        self.resource = Some(resource);
+        self
+    }
+
+    /// add `attributes` to the resource, e.g. `cloud.region` or `k8s.pod.name`.
+    ///
+    /// they take precedence over the detected resource and [HttpMetricsLayerBuilder::with_resource],
+    /// but not over the service name, version and labels set on the builder.
+    pub fn with_resource_attributes(mut self, attributes: Vec<KeyValue>) -> Self {
+        self.resource_attributes.extend(attributes);
+        self
+    }
+
+    /// run `detectors` after the default resource detectors, e.g. k8s, EC2 or GCP detectors.
+    ///
+    /// the attributes of later detectors take precedence over earlier ones.
+    pub fn with_resource_detectors(mut self, detectors: Vec<Box<dyn ResourceDetector + Send + Sync>>) -> Self {
+        self.resource_detectors.extend(detectors.into_iter().map(Arc::from));
+        self
+    }
+
+    /// detect the `k8s.namespace.name`, `k8s.pod.name` and `k8s.node.name` resource attributes,
+    /// see [K8sResourceDetector].
+    ///
+    /// this is the semconv replacement of the `INSTANCE_NAMESPACE` / `INSTANCE_IP` env vars.
+    #[cfg(feature = "k8s")]
+    pub fn with_k8s_resource_detector(mut self) -> Self {
+        self.resource_detectors.push(Arc::new(K8sResourceDetector));
+        self
+    }
+
+    /// whether to run the default resource detectors (sdk provided, `OTEL_RESOURCE_ATTRIBUTES` and telemetry sdk),
+    /// `true` by default.
+    ///
+    /// with the defaults disabled and no other detector, resource detection is skipped entirely.
+    pub fn with_default_resource_detectors(mut self, enabled: bool) -> Self {
+        self.default_resource_detectors = enabled;
+        self
+    }
+
+    /// set the timeout passed to the resource detectors, 6 seconds by default.
+    pub fn with_resource_detection_timeout(mut self, timeout: Duration) -> Self {
+        self.resource_detection_timeout = timeout;
+        self
+    }
+
+    /// set the instrumentation scope of the meter, by default the name and version of this crate.
+    ///
+    /// pass `None` as `version` to omit the scope version.
+    pub fn with_meter_scope(mut self, name: String, version: Option<String>) -> Self {
+        self.scope_name = Some(name);
+        self.scope_version = Some(version);
+        self
+    }
+
+    /// set the schema url of the meter, `https://opentelemetry.io/schema/1.0.0` by default.
+    ///
+    /// pass `None` to omit the schema url.
+    pub fn with_schema_url(mut self, schema_url: Option<String>) -> Self {
+        self.schema_url = Some(schema_url);
+        self
+    }
+
+    /// set the git commit the service was built from, e.g. `env!("GIT_SHA")` set by a build script.
+    pub fn with_git_sha(mut self, git_sha: String) -> Self {
+        self.git_sha = Some(git_sha);
+        self
+    }
+
+    /// set the rustc version the service was built with, e.g. `env!("RUSTC_VERSION")` set by a build script.
+    pub fn with_rustc_version(mut self, rustc_version: String) -> Self {
+        self.rustc_version = Some(rustc_version);
+        self
+    }
+
+    /// export the `build_info` gauge, always 1 with the service name and version, git sha and rustc version
+    /// as attributes, so dashboards can join on it.
+    pub fn with_build_info_metric(mut self, enabled: bool) -> Self {
+        self.build_info_metric = enabled;
+        self
+    }
+
+    /// export the `process.start_time` and `process.uptime` gauges,
+    /// `process_start_time_seconds` and `process_uptime_seconds` with the prometheus exporter,
+    /// so restarts can be detected.
+    ///
+    /// both are taken when the layer is built, which is close enough to the process start for most services.
+    /// when the process metrics are enabled too, their `process.start_time` read from procfs is exported instead.
+    pub fn with_uptime_metrics(mut self, enabled: bool) -> Self {
+        self.uptime_metrics = enabled;
+        self
+    }
+
+    /// record the gRPC calls, recognized by their `application/grpc` content type, in the `rpc.server.duration`
+    /// histogram (milliseconds) of the RPC semantic conventions instead of the HTTP instruments,
+    /// with the `rpc.system`, `rpc.service`, `rpc.method` and `rpc.grpc.status_code` attributes.
+    ///
+    /// the status is read from the `grpc-status` trailer, so the call is recorded once the response body is done.
+    pub fn with_grpc_metrics(mut self, enabled: bool) -> Self {
+        self.grpc_metrics = enabled;
+        self
+    }
+
+    /// record the `graphql.operation.name` and `graphql.operation.type` attributes of the requests
+    /// whose response carries a [GraphQlOperation] extension, and count their GraphQL errors
+    /// in the `graphql.errors` counter.
+    ///
+    /// the operation names are chosen by the clients, consider [HttpMetricsLayerBuilder::with_cardinality_limit].
+    pub fn with_graphql_metrics(mut self, enabled: bool) -> Self {
+        self.graphql_metrics = enabled;
+        self
+    }
+
+    /// count the 3xx responses in the `http.server.redirects` counter,
+    /// labeled by method, route and status code.
+    pub fn with_redirect_metrics(mut self, enabled: bool) -> Self {
+        self.redirect_metrics = enabled;
+        self
+    }
+
+    /// count the 429 responses in the `http.server.throttled` counter labeled by method and route,
+    /// and record the delays of the `Retry-After` response headers, e.g. of 429 or 503 responses,
+    /// in the `http.server.retry_after` histogram (seconds) labeled by method, route and status code.
+    ///
+    /// this covers the responses of a rate limiter beneath this layer as well as the ones passed on from upstreams.
+    pub fn with_throttling_metrics(mut self, enabled: bool) -> Self {
+        self.throttling_metrics = enabled;
+        self
+    }
+
+    /// count the 404 responses in the `http.server.not_found` counter, labeled by method
+    /// and either the bucket of the raw path or the class of the referer, see [NotFoundLabel].
+    ///
+    /// this separates the noise of scanners and dead links from the error rate of the routes.
+    pub fn with_not_found_metrics(mut self, label: NotFoundLabel) -> Self {
+        self.not_found_label = Some(label);
+        self
+    }
+
+    /// count the request and response body bytes as they are streamed, in the
+    /// `http.server.request.body` and `http.server.response.body` counters labeled by method and route,
+    /// to graph the throughput with `rate()`.
+    pub fn with_throughput_metrics(mut self, enabled: bool) -> Self {
+        self.throughput_metrics = enabled;
+        self
+    }
+
+    /// whether to record the `http.server.request.size` and `http.server.response.size` histograms, `true` by default.
+    ///
+    /// the request headers are only walked to size them when a request size is recorded.
+    pub fn with_size_metrics(mut self, enabled: bool) -> Self {
+        self.size_metrics = enabled;
+        self
+    }
+
+    /// record the header and body parts of the request and response sizes in the
+    /// `http.server.request.header.size`, `http.server.request.body.size`,
+    /// `http.server.response.header.size` and `http.server.response.body.size` histograms,
+    /// next to the combined `http.server.request.size` and `http.server.response.size`.
+    ///
+    /// jumbo cookies show up in the header sizes, jumbo payloads in the body sizes.
+    pub fn with_split_sizes(mut self, enabled: bool) -> Self {
+        self.split_sizes = enabled;
+        self
+    }
+
+    /// record the requests of each tenant, as identified by `extractor`, in a registry of its own
+    /// served at `<metrics path>/:tenant`, e.g. `/metrics/acme`, so each customer of a hosted platform
+    /// can scrape its isolated HTTP metrics.
+    ///
+    /// at most `max_tenants` registries are created, the later tenants are only recorded in the main registry.
+    /// the tenant registries hold the request counter, duration and size instruments.
+    pub fn with_tenant_registries(mut self, extractor: TenantExtractor, max_tenants: usize) -> Self {
+        self.tenant_registries = Some((extractor, max_tenants));
+        self
+    }
+
+    /// record the tenant of each request, as identified by `extractor`, as the `tenant.id` attribute
+    /// of the active requests and the request metrics.
+    ///
+    /// the first `max_tenants` tenants get their own series, the long tail is recorded as [OTHER_TENANT_LABEL].
+    /// requests without a tenant do not get the attribute.
+    pub fn with_tenant_attribute(mut self, extractor: TenantExtractor, max_tenants: usize) -> Self {
+        self.tenant_attribute = Some((extractor, max_tenants));
+        self
+    }
+
+    /// record the credential of each request, as identified by `extractor`, as the `api_key.hash` attribute
+    /// of the request metrics: the first 64 bits of the SHA-256 of `salt` and the credential, in hex.
+    ///
+    /// the key never shows up in the labels, and the same key gets the same label across restarts
+    /// and replicas sharing the salt. keep the salt secret, consider [HttpMetricsLayerBuilder::with_cardinality_limit].
+    pub fn with_api_key_attribute(mut self, extractor: CredentialExtractor, salt: impl Into<String>) -> Self {
+        self.api_key_attribute = Some((extractor, salt.into()));
+        self
+    }
+
+    /// record the `text/event-stream` responses as Server-Sent Events streams:
+    /// the `sse.server.active_streams` count, the `sse.server.stream.duration` until the end of the body
+    /// and the `sse.server.events` sent.
+    ///
+    /// the HTTP request duration of a stream only covers the time until its response headers.
+    pub fn with_sse_metrics(mut self, enabled: bool) -> Self {
+        self.sse_metrics = enabled;
+        self
+    }
+
+    /// record the responses compressed by a layer beneath this one, e.g. the `CompressionLayer` of tower-http:
+    /// the `http.server.response.body.uncompressed_size` and `http.server.response.body.compressed_size`
+    /// histograms, and their ratio in the `http.server.response.compression` histogram,
+    /// labeled by method, route and `http.response.content_encoding`.
+    ///
+    /// the uncompressed bytes are counted by an [UncompressedSizeLayer] beneath the compression layer,
+    /// the responses are recorded once their body is sent to its end.
+    pub fn with_compression_metrics(mut self, enabled: bool) -> Self {
+        self.compression_metrics = enabled;
+        self
+    }
+
+    /// split the request duration into the `http.server.middleware.duration` and `http.server.handler.duration`
+    /// histograms, with the labels of the request duration, to tell the latency of the middleware stack,
+    /// e.g. authentication or decompression, from the latency of the handler itself.
+    ///
+    /// the handler is timed by a [HandlerTimingLayer] right above it,
+    /// the requests that did not reach it, e.g. rejected by the authentication, are not split.
+    pub fn with_handler_timing(mut self, enabled: bool) -> Self {
+        self.handler_timing = enabled;
+        self
+    }
+
+    /// record the time the requests waited between the load balancer and the application in the
+    /// `http.server.queue_time` histogram labeled by method and route, from the timestamp of the
+    /// `X-Request-Start` or `X-Queue-Start` header set by the load balancer.
+    ///
+    /// the timestamp is in seconds, milliseconds or microseconds since unix epoch, optionally prefixed by `t=`,
+    /// e.g. `t=1700000000.123` as set by nginx with `proxy_set_header X-Request-Start "t=${msec}";`.
+    /// a growing queue time shows the saturation before the application latency does.
+    pub fn with_queue_time(mut self, enabled: bool) -> Self {
+        self.queue_time = enabled;
+        self
+    }
+
+    /// hand a [WebSocketMetrics] extension to the WebSocket upgrade requests,
+    /// to record the connections and messages of the upgraded sockets.
+    ///
+    /// without it the upgrade requests are only recorded as `101 Switching Protocols` responses.
+    #[cfg(feature = "websocket")]
+    pub fn with_websocket_metrics(mut self, enabled: bool) -> Self {
+        self.websocket_metrics = enabled;
+        self
+    }
+
+    /// export the self-metrics of the prometheus metrics endpoint: the scrape duration, the payload size,
+    /// the number of metric families and the time of the last scrape, e.g. `metrics_scrape_duration_seconds`.
+    ///
+    /// the measurements of a scrape are exported by the next one.
+    pub fn with_scrape_metrics(mut self, enabled: bool) -> Self {
+        self.scrape_metrics = enabled;
+        self
+    }
+
+    /// abandon the scrapes of the metrics endpoint taking longer than `timeout` to gather and encode,
+    /// with `503 Service Unavailable`, unlimited by default.
+    ///
+    /// the abandoned scrapes are counted by the `metrics.scrape.rejected` counter with `reason` set to `timeout`.
+    pub fn with_scrape_timeout(mut self, timeout: Duration) -> Self {
+        self.scrape_timeout = Some(timeout);
+        self
+    }
+
+    /// abandon the scrapes of the metrics endpoint whose encoded payload is larger than `max_size` bytes,
+    /// with `503 Service Unavailable`, unlimited by default.
+    ///
+    /// the abandoned scrapes are counted by the `metrics.scrape.rejected` counter with `reason` set to `size`.
+    pub fn with_max_scrape_size(mut self, max_size: usize) -> Self {
+        self.max_scrape_size = Some(max_size);
+        self
+    }
+
+    /// send an `ETag` with the metrics endpoint response, a hash of the gathered metric families,
+    /// and answer `304 Not Modified` when it matches the `If-None-Match` header of the scraper.
+    ///
+    /// an unchanged registry then costs neither the encoding nor the transfer of the payload.
+    pub fn with_conditional_get(mut self, enabled: bool) -> Self {
+        self.conditional_get = enabled;
+        self
+    }
+
+    /// whether the metrics endpoints also serve the families of `prometheus::default_registry()`, `true` by default.
+    ///
+    /// the families registered in both registries are only served once, from the registry of the layer.
+    pub fn with_default_registry(mut self, enabled: bool) -> Self {
+        self.default_registry = enabled;
+        self
+    }
+
+    /// cache the encoded metrics endpoint response for `ttl`, e.g. 1 second,
+    /// so several scrapers hitting the endpoint at once share one gather and encode pass.
+    ///
+    /// concurrent scrapes of an expired payload wait for the one encoding it instead of encoding it again.
+    pub fn with_response_cache(mut self, ttl: Duration) -> Self {
+        self.response_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// answer the metrics endpoint requests beyond `per_second` per second with `429 Too Many Requests`,
+    /// e.g. the ones of crawlers hitting an exposed endpoint, unlimited by default.
+    ///
+    /// the limit is a token bucket of the metrics endpoint alone, allowing bursts of up to `per_second` requests.
+    pub fn with_scrape_rate_limit(mut self, per_second: u32) -> Self {
+        self.scrape_rate_limit = Some(per_second);
+        self
+    }
+
+    /// reject the metrics endpoint requests without the `Authorization: Bearer <token>` header
+    /// with `401 Unauthorized`, so the endpoint can be served on the public listener.
+    ///
+    /// the token is compared in constant time. configure the scraper with `authorization` / `bearer_token`.
+    pub fn with_metrics_auth_token(mut self, token: String) -> Self {
+        self.endpoint_auth.bearer_token = Some(token);
+        self
+    }
+
+    /// reject the metrics endpoint requests from clients outside of `networks` with `403 Forbidden`,
+    /// e.g. to only let the in-cluster scraper read the metrics.
+    ///
+    /// the client address is the peer address from [axum::extract::ConnectInfo], so the app must be served with
+    /// `into_make_service_with_connect_info::<SocketAddr>()`, otherwise every request is rejected.
+    /// the `Forwarded` and `X-Forwarded-For` headers are only honored for the peers set with
+    /// [HttpMetricsLayerBuilder::with_trusted_proxies].
+    pub fn with_metrics_allowed_networks(mut self, networks: Vec<IpNet>) -> Self {
+        self.endpoint_auth.allowed_networks = Some(networks.into());
+        self
+    }
+
+    /// consult `authorizer` before serving the metrics, the requests it refuses get `403 Forbidden`,
+    /// e.g. to validate a signed token against an auth service.
+    ///
+    /// it runs after the other access rules of the builder. the returned future must not borrow the request,
+    /// copy what it needs out of it first:
+    ///
+    /// ```
+    /// use axum_otel_metrics::HttpMetricsLayerBuilder;
+    ///
+    /// let metrics = HttpMetricsLayerBuilder::new()
+    ///     .with_metrics_authorizer(|req| {
+    ///         let token = req.headers().get("x-scrape-token").cloned();
+    ///         async move { token.is_some_and(|token| token == "secret") }
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn with_metrics_authorizer<F, Fut>(mut self, authorizer: F) -> Self
+    where
+        F: Fn(&Request<axum::body::Body>) -> Fut + 'static + Send + Sync,
+        Fut: Future<Output = bool> + 'static + Send,
+    {
+        self.endpoint_auth.authorizer = Some(Arc::new(move |req: &Request<axum::body::Body>| {
+            Box::pin(authorizer(req)) as futures_util::future::BoxFuture<'static, bool>
+        }));
+        self
+    }
+
+    /// reject the metrics endpoint requests without the HTTP basic auth `username` and `password`
+    /// with `401 Unauthorized`, matching the `basic_auth` of the Prometheus scrape config.
+    ///
+    /// the credentials are compared in constant time.
+    pub fn with_metrics_basic_auth(mut self, username: String, password: String) -> Self {
+        self.endpoint_auth.basic_credentials = Some(format!("{}:{}", username, password));
+        self
+    }
+
+    /// serve the metric families as JSON next to the metrics endpoint, at its path with a `.json` suffix,
+    /// e.g. `/metrics.json`, for lightweight dashboards and integration tests. see [json_exporter_handler].
+    pub fn with_json_endpoint(mut self, enabled: bool) -> Self {
+        self.json_endpoint = enabled;
+        self
+    }
+
+    /// attach the sampled trace of the request as an exemplar to the duration and size histograms,
+    /// served with the OpenMetrics format of the metrics endpoint.
+    ///
+    /// the trace context is extracted from the request headers with the global text map propagator,
+    /// falling back to the current context. with the `tracing` feature the current `tracing` span is used first.
+    /// the latest exemplar of every series is kept.
+    pub fn with_exemplars(mut self, enabled: bool) -> Self {
+        self.exemplars = enabled;
+        self
+    }
+
+    /// export the process metrics: cpu time, resident and virtual memory, open fds, threads and start time,
+    /// e.g. `process_cpu_seconds_total` and `process_resident_memory_bytes` with the prometheus exporter.
+    ///
+    /// they are registered on the same meter as the HTTP metrics, so they reach every exporter.
+    #[cfg(all(feature = "process-metrics", target_os = "linux"))]
+    pub fn with_process_metrics(mut self, enabled: bool) -> Self {
+        self.process_metrics = enabled;
+        self
+    }
+
+    /// call `handler` with the error of every failed export of the otlp exporter,
+    /// e.g. to log it or to flip a health check, the failures are counted by `metrics.export.failures` too.
+    pub fn with_export_error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&opentelemetry::metrics::MetricsError) + 'static + Send + Sync,
+    {
+        self.export_error_handler = Some(Arc::new(handler));
+        self
+    }
+
+    pub fn with_exporter(mut self, exporter: String) -> Self {
+        self.exporter = Some(exporter);
+        self
+    }
+
+    /// the resource attributes set by the builder options, on top of the detected ones
+    fn explicit_resource_attributes(&self) -> Vec<KeyValue> {
+        // the explicit builder options below take precedence over the user supplied resource attributes
+        let mut resource = self.resource_attributes.clone();
+
+        // legacy env vars used by Tencent Cloud TKE APM,
+        // prefer the semconv attributes of `K8sResourceDetector` (feature `k8s`) for new deployments
+        let ns = env::var("INSTANCE_NAMESPACE").unwrap_or_default();
+        if !ns.is_empty() {
+            resource.push(KeyValue::new(SERVICE_NAMESPACE, ns.clone()));
+        }
+
+        let instance_ip = env::var("INSTANCE_IP").unwrap_or_default();
+        if !instance_ip.is_empty() {
+            resource.push(KeyValue::new(SERVICE_INSTANCE, instance_ip));
+        }
+
+        if let Some(service_name) = self.service_name.clone() {
+            // `foo.ns`
+            if !ns.is_empty() && !service_name.starts_with(format!("{}.", &ns).as_str()) {
+                resource.push(KeyValue::new(SERVICE_NAME, format!("{}.{}", service_name, &ns)));
+            } else {
+                resource.push(KeyValue::new(SERVICE_NAME, service_name));
+            }
+        }
+        if let Some(service_version) = self.service_version.clone() {
+            resource.push(KeyValue::new(SERVICE_VERSION, service_version));
+        }
+        if let Some(environment) = self.deployment_environment.clone() {
+            resource.push(KeyValue::new(DEPLOYMENT_ENVIRONMENT_NAME, environment));
+        }
+
+        // the labels only become const labels of the prometheus registry,
+        // add them to the resource for the otlp exporter so it does not silently drop them,
+        // but not for the prometheus one which would export them twice, also in `target_info`
+        if self.exporter.as_deref() == Some("otlp") {
+            if let Some(labels) = self.labels.clone() {
+                resource.extend(labels.into_iter().map(|(k, v)| KeyValue::new(k, v)));
+            }
+        }
+        resource
+    }
+
+    pub fn build(self) -> HttpMetricsLayer {
+        let resource = self.explicit_resource_attributes();
+
+        let mut detectors: Vec<Box<dyn ResourceDetector>> = vec![];
+        if self.default_resource_detectors {
+            // set service.name from env OTEL_SERVICE_NAME > env OTEL_RESOURCE_ATTRIBUTES > option_env! CARGO_BIN_NAME > unknown_service
+            detectors.push(Box::new(SdkProvidedResourceDetector));
+            // detect res from env OTEL_RESOURCE_ATTRIBUTES (resources string like key1=value1,key2=value2,...)
+            detectors.push(Box::new(EnvResourceDetector::new()));
+            // set telemetry.sdk.{name, language, version}
+            detectors.push(Box::new(TelemetryResourceDetector));
+        }
+        detectors.extend(
+            self.resource_detectors
+                .iter()
+                .map(|d| Box::new(SharedResourceDetector(d.clone())) as Box<dyn ResourceDetector>),
+        );
+
+        let res = if detectors.is_empty() {
+            Resource::empty()
+        } else {
+            Resource::from_detectors(self.resource_detection_timeout, detectors)
+        };
+
+        let res = match self.resource {
+            Some(ref user_res) => res.merge(user_res),
+            None => res,
+        };
+
+        let res = if !resource.is_empty() {
+            res.merge(&mut Resource::new(resource))
+        } else {
+            res
+        };
+
+        let mut registry = None;
+        let mut export_stats = None;
+        let mut builder = SdkMeterProvider::builder().with_resource(res);
+
+        // exporter
+
+        if self.exporter == Some("otlp".to_string()) {
+            let stats = Arc::new(ExportStats::default());
+            builder = builder.with_reader(self.build_otlp(stats.clone()));
+            export_stats = Some(stats);
+        } else {
+            let (reg, exporter) = self.build_prometheus();
+            registry = Some(reg);
+            builder = builder.with_reader(exporter);
+        }
+
+        for view in self.duration_histogram_views() {
+            builder = builder.with_view(view);
+        }
+
+        let provider = builder.build();
+
+        // init the global meter provider
+        global::set_meter_provider(provider.clone());
+        // this must called after the global meter provider has ben initialized
+        // let meter = global::meter("axum-app");
+        // let meter = provider.meter("axum-app");
+        // the meter scope must be `&'static str`, the configured values are only leaked once per layer
+        let scope_name: &'static str = match self.scope_name {
+            Some(ref name) => Box::leak(name.clone().into_boxed_str()),
+            None => env!("CARGO_PKG_NAME"),
+        };
+        let scope_version: Option<&'static str> = match self.scope_version {
+            Some(Some(ref version)) => Some(Box::leak(version.clone().into_boxed_str())),
+            Some(None) => None,
+            None => Some(env!("CARGO_PKG_VERSION")),
+        };
+        let schema_url: Option<&'static str> = match self.schema_url {
+            Some(Some(ref url)) => Some(Box::leak(url.clone().into_boxed_str())),
+            Some(None) => None,
+            None => Some("https://opentelemetry.io/schema/1.0.0"),
+        };
+        let meter = provider.versioned_meter(scope_name, scope_version, schema_url, None);
+
+        // requests_total
+        let requests_total = self.requests_counter_name.clone().map(|name| {
+            meter
+                .u64_counter(self.instrument_name(&name))
+                .with_unit(self.unit(HttpInstrument::RequestsTotal, ""))
+                .with_description(self.description(
+                    HttpInstrument::RequestsTotal,
+                    "How many HTTP requests processed, partitioned by status code and HTTP method.",
+                ))
+                .init()
+        });
+
+        // request_duration_seconds
+        let req_duration = meter
+            .f64_histogram(self.instrument_name("http.server.request.duration"))
+            .with_unit(self.unit(HttpInstrument::RequestDuration, "s"))
+            .with_description(self.description(HttpInstrument::RequestDuration, "The HTTP request latencies in seconds."))
+            .with_boundaries(
+                self.duration_buckets
+                    .clone()
+                    .unwrap_or_else(|| HTTP_REQ_DURATION_HISTOGRAM_BUCKETS.to_vec()),
+            )
+            .init();
+
+        // the instrument identity does not include the boundaries,
+        // so the route specific histograms must live in their own meter scope
+        let route_req_duration = self
+            .route_buckets
+            .iter()
+            .map(|(route, buckets)| {
+                // the meter name must be `&'static str`, the routes are only leaked once per layer
+                let scope: &'static str = Box::leak(format!("{}/route{}", scope_name, route).into_boxed_str());
+                let histogram = provider
+                    .versioned_meter(scope, scope_version, schema_url, None)
+                    .f64_histogram(self.instrument_name("http.server.request.duration"))
+                    .with_unit(self.unit(HttpInstrument::RequestDuration, "s"))
+                    .with_description(self.description(HttpInstrument::RequestDuration, "The HTTP request latencies in seconds."))
+                    .with_boundaries(buckets.clone())
+                    .init();
+                (route.clone(), histogram)
+            })
+            .collect::<HashMap<_, _>>();
+
+        // request_size_bytes
+        let req_size = self.size_metrics.then(|| {
+            meter
+                .u64_histogram(self.instrument_name("http.server.request.size"))
+                .with_unit(self.unit(HttpInstrument::RequestSize, "By"))
+                .with_description(self.description(HttpInstrument::RequestSize, "The HTTP request sizes in bytes."))
+                .with_boundaries(HTTP_REQ_SIZE_HISTOGRAM_BUCKETS.to_vec())
+                .init()
+        });
+
+        let res_size = self.size_metrics.then(|| {
+            meter
+                .u64_histogram(self.instrument_name("http.server.response.size"))
+                .with_unit(self.unit(HttpInstrument::ResponseSize, "By"))
+                .with_description(self.description(HttpInstrument::ResponseSize, "The HTTP response sizes in bytes."))
+                .with_boundaries(HTTP_REQ_SIZE_HISTOGRAM_BUCKETS.to_vec())
+                .init()
+        });
+
+        let size_histogram = |name: &str, instrument: HttpInstrument, description: &str| {
+            meter
+                .u64_histogram(self.instrument_name(name))
+                .with_unit(self.unit(instrument, "By"))
+                .with_description(self.description(instrument, description))
+                .with_boundaries(HTTP_REQ_SIZE_HISTOGRAM_BUCKETS.to_vec())
+                .init()
+        };
+        let (req_header_size, req_body_size, res_header_size, res_body_size) = if self.split_sizes {
+            (
+                Some(size_histogram(
+                    "http.server.request.header.size",
+                    HttpInstrument::RequestHeaderSize,
+                    "The HTTP request header sizes in bytes.",
+                )),
+                Some(size_histogram(
+                    "http.server.request.body.size",
+                    HttpInstrument::RequestBodySize,
+                    "The HTTP request body sizes in bytes.",
+                )),
+                Some(size_histogram(
+                    "http.server.response.header.size",
+                    HttpInstrument::ResponseHeaderSize,
+                    "The HTTP response header sizes in bytes.",
+                )),
+                Some(size_histogram(
+                    "http.server.response.body.size",
+                    HttpInstrument::ResponseBodySize,
+                    "The HTTP response body sizes in bytes.",
+                )),
+            )
+        } else {
+            (None, None, None, None)
+        };
+
+        // no u64_up_down_counter because up_down_counter maybe < 0 since it allow negative values
+        let req_active = meter
+            .i64_up_down_counter(self.instrument_name("http.server.active_requests"))
+            .with_unit(self.unit(HttpInstrument::ActiveRequests, ""))
+            .with_description(self.description(HttpInstrument::ActiveRequests, "The number of active HTTP requests."))
+            .init();
+
+        let route_active_requests = self.route_active_requests.then(|| {
+            meter
+                .i64_up_down_counter(self.instrument_name("http.server.route.active_requests"))
+                .with_unit(self.unit(HttpInstrument::RouteActiveRequests, ""))
+                .with_description(self.description(
+                    HttpInstrument::RouteActiveRequests,
+                    "The number of active HTTP requests per route.",
+                ))
+                .init()
+        });
+
+        let cardinality_limiter = CardinalityLimiter::new(self.cardinality_limits.clone());
+        let mut const_attributes = self.const_attributes.clone();
+        self.attribute_naming.apply(&mut const_attributes);
+        let const_attributes: Arc<[KeyValue]> = const_attributes.into();
+
+        let in_flight = self.long_running_threshold.map(|_| InFlightTracker::default());
+        let long_running_requests = match (self.long_running_threshold, in_flight.clone()) {
+            (Some(threshold), Some(tracker)) => {
+                let limiter = cardinality_limiter.clone();
+                let filter = self.attribute_filter.clone();
+                let redactor = self.attribute_redactor.clone();
+                let naming = self.attribute_naming.clone();
+                let const_attributes = const_attributes.clone();
+                Some(
+                    meter
+                        .u64_observable_gauge(self.instrument_name("http.server.long_running_requests"))
+                        .with_unit(self.unit(HttpInstrument::LongRunningRequests, ""))
+                        .with_description(self.description(
+                            HttpInstrument::LongRunningRequests,
+                            "The number of HTTP requests in flight for longer than the configured threshold.",
+                        ))
+                        .with_callback(move |observer| {
+                            for ((method, route), count) in tracker.longer_than(threshold) {
+                                let mut labels = vec![
+                                    KeyValue::new("http.request.method", method),
+                                    KeyValue::new("http.route", route),
+                                ];
+                                filter.apply(&mut labels);
+                                if let Some(ref redactor) = redactor {
+                                    redact(redactor, &mut labels);
+                                }
+                                limiter.limit(&mut labels);
+                                naming.apply(&mut labels);
+                                labels.extend(const_attributes.iter().cloned());
+                                observer.observe(count, &labels);
+                            }
+                        })
+                        .init(),
+                )
+            }
+            _ => None,
+        };
+
+        let build_info = BuildInfo {
+            service_name: self.service_name.clone(),
+            service_version: self.service_version.clone(),
+            git_sha: self.git_sha.clone(),
+            rustc_version: self.rustc_version.clone(),
+        };
+
+        let build_info_gauge = if self.build_info_metric {
+            let mut attributes = build_info.attributes();
+            self.attribute_naming.apply(&mut attributes);
+            attributes.extend(const_attributes.iter().cloned());
+            Some(
+                meter
+                    .u64_observable_gauge(self.instrument_name("build_info"))
+                    .with_unit(self.unit(HttpInstrument::BuildInfo, ""))
+                    .with_description(self.description(
+                        HttpInstrument::BuildInfo,
+                        "Always 1, with the build information of the service as attributes.",
+                    ))
+                    .with_callback(move |observer| observer.observe(1, &attributes))
+                    .init(),
+            )
+        } else {
+            None
+        };
+
+        #[cfg(all(feature = "process-metrics", target_os = "linux"))]
+        let process_metrics = self.process_metrics.then(|| {
+            Arc::new(process::ProcessMetrics::register(
+                &meter,
+                |name| self.instrument_name(name),
+                const_attributes.to_vec(),
+            ))
+        });
+
+        #[cfg(all(feature = "process-metrics", target_os = "linux"))]
+        let start_time_registered = process_metrics.is_some();
+        #[cfg(not(all(feature = "process-metrics", target_os = "linux")))]
+        let start_time_registered = false;
+
+        let (process_start_time, process_uptime) = if self.uptime_metrics {
+            let started = Instant::now();
+            let start_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            let attributes = const_attributes.clone();
+            let process_start_time = (!start_time_registered).then(|| {
+                meter
+                    .f64_observable_gauge(self.instrument_name("process.start_time"))
+                    .with_unit("s")
+                    .with_description("Start time of the process since unix epoch in seconds.")
+                    .with_callback(move |observer| observer.observe(start_time, &attributes))
+                    .init()
+            });
+            let attributes = const_attributes.clone();
+            let process_uptime = meter
+                .f64_observable_gauge(self.instrument_name("process.uptime"))
+                .with_unit("s")
+                .with_description("The time the process has been running in seconds.")
+                .with_callback(move |observer| observer.observe(started.elapsed().as_secs_f64(), &attributes))
+                .init();
+            (process_start_time, Some(process_uptime))
+        } else {
+            (None, None)
+        };
+
+        let exemplars = (self.exemplars && registry.is_some()).then(|| {
+            Arc::new(ExemplarStore::new(
+                &self.instrument_name("http.server.request.duration"),
+                &self.instrument_name("http.server.request.size"),
+                &self.instrument_name("http.server.response.size"),
+            ))
+        });
+
+        let scrape_metrics = (self.scrape_metrics && registry.is_some()).then(|| {
+            ScrapeMetrics::register(&meter, |name| self.instrument_name(name), const_attributes.clone())
+        });
+
+        let scrape_guard = (registry.is_some() && (self.scrape_timeout.is_some() || self.max_scrape_size.is_some())).then(|| {
+            ScrapeGuard::register(
+                &meter,
+                |name| self.instrument_name(name),
+                const_attributes.clone(),
+                self.scrape_timeout,
+                self.max_scrape_size,
+            )
+        });
+
+        let (export_attempts, export_failures) = match export_stats {
+            Some(stats) => {
+                let (attempts_stats, attributes) = (stats.clone(), const_attributes.clone());
+                let export_attempts = meter
+                    .u64_observable_counter(self.instrument_name("metrics.export.attempts"))
+                    .with_description("The number of export attempts of the otlp exporter.")
+                    .with_callback(move |observer| observer.observe(attempts_stats.attempts(), &attributes))
+                    .init();
+                let attributes = const_attributes.clone();
+                let export_failures = meter
+                    .u64_observable_counter(self.instrument_name("metrics.export.failures"))
+                    .with_description("The number of failed exports of the otlp exporter.")
+                    .with_callback(move |observer| observer.observe(stats.failures(), &attributes))
+                    .init();
+                (Some(export_attempts), Some(export_failures))
+            }
+            None => (None, None),
+        };
+
+        let rpc_server_duration = self.grpc_metrics.then(|| {
+            meter
+                .f64_histogram(self.instrument_name("rpc.server.duration"))
+                .with_unit(self.unit(HttpInstrument::RpcServerDuration, "ms"))
+                .with_description(self.description(
+                    HttpInstrument::RpcServerDuration,
+                    "The duration of the inbound gRPC calls in milliseconds.",
+                ))
+                .with_boundaries(grpc::RPC_SERVER_DURATION_HISTOGRAM_BUCKETS.to_vec())
+                .init()
+        });
+
+        let upstream_duration = meter
+            .f64_histogram(self.instrument_name("http.server.upstream.duration"))
+            .with_unit(self.unit(HttpInstrument::UpstreamDuration, "s"))
+            .with_description(self.description(
+                HttpInstrument::UpstreamDuration,
+                "The time spent waiting for the upstream services in seconds.",
+            ))
+            .with_boundaries(HTTP_REQ_DURATION_HISTOGRAM_BUCKETS.to_vec())
+            .init();
+
+        let queue_time = self.queue_time.then(|| {
+            meter
+                .f64_histogram(self.instrument_name("http.server.queue_time"))
+                .with_unit(self.unit(HttpInstrument::QueueTime, "s"))
+                .with_description(self.description(
+                    HttpInstrument::QueueTime,
+                    "The time the HTTP requests waited behind the load balancer in seconds.",
+                ))
+                .with_boundaries(HTTP_REQ_DURATION_HISTOGRAM_BUCKETS.to_vec())
+                .init()
+        });
+
+        let (request_body_bytes, response_body_bytes) = if self.throughput_metrics {
+            (
+                Some(
+                    meter
+                        .u64_counter(self.instrument_name("http.server.request.body"))
+                        .with_unit(self.unit(HttpInstrument::RequestBodyBytes, "By"))
+                        .with_description(self.description(
+                            HttpInstrument::RequestBodyBytes,
+                            "The number of HTTP request body bytes received.",
+                        ))
+                        .init(),
+                ),
+                Some(
+                    meter
+                        .u64_counter(self.instrument_name("http.server.response.body"))
+                        .with_unit(self.unit(HttpInstrument::ResponseBodyBytes, "By"))
+                        .with_description(self.description(
+                            HttpInstrument::ResponseBodyBytes,
+                            "The number of HTTP response body bytes sent.",
+                        ))
+                        .init(),
+                ),
+            )
+        } else {
+            (None, None)
+        };
+
+        let not_found = self.not_found_label.map(|_| {
+            meter
+                .u64_counter(self.instrument_name("http.server.not_found"))
+                .with_unit(self.unit(HttpInstrument::NotFound, ""))
+                .with_description(self.description(
+                    HttpInstrument::NotFound,
+                    "The number of HTTP not found responses.",
+                ))
+                .init()
+        });
+
+        let app_metrics = AppMetrics::new(meter.clone(), self.instrument_prefix.clone());
+
+        let slow_request_thresholds = Swappable::new(self.slow_request_thresholds.clone());
+        let recorders = recorder::recorders(&self, &meter, &slow_request_thresholds);
+
+        let meter_state = MetricState {
+            layer_id: NEXT_LAYER_ID.fetch_add(1, Ordering::Relaxed),
+            registry,
+            build_info: Arc::new(build_info),
+            exemplars,
+            scrape_metrics,
+            scrape_guard,
+            conditional_get: self.conditional_get,
+            default_registry: self.default_registry,
+            response_cache: self.response_cache_ttl.map(|ttl| Arc::new(ResponseCache::new(ttl))),
+            scrape_rate_limiter: self.scrape_rate_limit.map(|per_second| Arc::new(RateLimiter::new(per_second))),
+            endpoint_auth: Arc::new(self.endpoint_auth),
+            #[cfg(all(feature = "process-metrics", target_os = "linux"))]
+            _process_metrics: process_metrics,
+            metric: Metric {
+                requests_total,
+                req_duration,
+                route_req_duration: Arc::new(route_req_duration),
+                req_size,
+                res_size,
+                req_header_size,
+                req_body_size,
+                res_header_size,
+                res_body_size,
+                req_active,
+                route_active_requests,
+                long_running_requests,
+                build_info: build_info_gauge,
+                process_start_time,
+                process_uptime,
+                rpc_server_duration,
+                upstream_duration,
+                queue_time,
+                request_body_bytes,
+                response_body_bytes,
+                not_found,
+                export_attempts,
+                export_failures,
+            },
+            skipper: Swappable::new(self.skipper),
+            request_skipper: self.request_skipper,
+            response_skipper: self.response_skipper,
+            is_tls: self.is_tls,
+            client_address: self.client_address,
+            network_peer: self.network_peer,
+            server_port: self.server_port,
+            query_keys: self.query_keys,
+            trusted_proxies: self.trusted_proxies,
+            scheme_resolver: self.scheme_resolver,
+            protocol_version: self.protocol_version,
+            baggage_keys: self.baggage_keys.into(),
+            attribute_cache: Arc::new(AttributeCache::new(ATTRIBUTE_CACHE_SIZE)),
+            unmatched_paths: Arc::new(BoundedSet::new(match self.unmatched_route {
+                UnmatchedRoute::RawPath { max_paths } => max_paths,
+                UnmatchedRoute::Label(_) => 0,
+            })),
+            route_extractor: self.route_extractor,
+            unmatched_route: self.unmatched_route,
+            cors_preflight: self.cors_preflight,
+            head_requests: self.head_requests,
+            not_found_label: self.not_found_label,
+            not_found_buckets: Arc::new(BoundedSet::new(match self.not_found_label {
+                Some(NotFoundLabel::PathBucket { max_buckets }) => max_buckets,
+                _ => 0,
+            })),
+            route_grouper: self.route_grouper,
+            attribute_filter: self.attribute_filter,
+            attribute_redactor: self.attribute_redactor,
+            cardinality_limiter,
+            attribute_naming: self.attribute_naming,
+            const_attributes,
+            sample_rate: SampleRate::new(self.sample_rate),
+            histogram_mode: self.histogram_mode,
+            slow_request_thresholds,
+            in_flight,
+            sse: self.sse_metrics.then(|| SseMetrics::new(&app_metrics)),
+            compression: self.compression_metrics.then(|| CompressionMetrics::new(&app_metrics)),
+            handler_timing: self.handler_timing.then(|| HandlerTimingMetrics::new(&app_metrics)),
+            tenant_registries: self.tenant_registries.clone().map(|(extractor, max_tenants)| {
+                (
+                    extractor,
+                    Arc::new(TenantRegistries::new(max_tenants, self.instrument_prefix.clone())),
+                )
+            }),
+            tenant_attribute: self
+                .tenant_attribute
+                .clone()
+                .map(|(extractor, max_tenants)| (extractor, Arc::new(BoundedSet::new(max_tenants)))),
+            api_key_attribute: self
+                .api_key_attribute
+                .clone()
+                .map(|(extractor, salt)| (extractor, salt.into())),
+            #[cfg(feature = "websocket")]
+            websocket: self.websocket_metrics.then(|| WebSocketMetrics::new(&app_metrics)),
+            recorders: recorders.into(),
+        };
+
+        HttpMetricsLayer {
+            state: Arc::new(meter_state),
+            json_path: self.json_endpoint.then(|| format!("{}.json", self.path)),
+            tenant_path: self
+                .tenant_registries
+                .as_ref()
+                .map(|_| format!("{}/:tenant", self.path.trim_end_matches('/'))),
+            path: self.path,
+            app_metrics,
+            meter,
+            provider,
+        }
+    }
+
+    fn instrument_name(&self, name: &str) -> String {
+        match self.instrument_prefix {
+            Some(ref prefix) => format!("{}.{}", prefix, name),
+            None => name.to_string(),
+        }
+    }
+
+    fn description(&self, instrument: HttpInstrument, default: &str) -> String {
+        self.descriptions
+            .get(&instrument)
+            .cloned()
+            .unwrap_or_else(|| default.to_string())
+    }
+
+    fn unit(&self, instrument: HttpInstrument, default: &str) -> String {
+        self.units.get(&instrument).cloned().unwrap_or_else(|| default.to_string())
+    }
+
+    /// the views aggregating the duration histogram as configured by [DurationHistogram]
+    fn duration_histogram_views(&self) -> Vec<Box<dyn View>> {
+        let name = self.instrument_name("http.server.request.duration");
+        let exponential = |max_size, max_scale| Aggregation::Base2ExponentialHistogram {
+            max_size,
+            max_scale,
+            record_min_max: true,
+        };
+        let views = match self.duration_histogram {
+            DurationHistogram::Classic => vec![],
+            DurationHistogram::Native { max_size, max_scale } => vec![new_view(
+                Instrument::new().name(name),
+                Stream::new().aggregation(exponential(max_size, max_scale)),
+            )],
+            DurationHistogram::ClassicAndNative { max_size, max_scale } => vec![
+                // once a view matches, the instrument is only aggregated as the views say,
+                // an empty stream keeps the explicit buckets histogram
+                new_view(Instrument::new().name(name.clone()), Stream::new()),
+                new_view(
+                    Instrument::new().name(name.clone()),
+                    Stream::new()
+                        .name(format!("{}.native", name))
+                        .aggregation(exponential(max_size, max_scale)),
+                ),
+            ],
+        };
+        views
+            .into_iter()
+            .map(|view| view.expect("create duration histogram view"))
+            .collect()
+    }
+
+    fn build_prometheus(&self) -> (Registry, impl opentelemetry_sdk::metrics::reader::MetricReader) {
+        let registry = if let Some(prefix) = self.prefix.clone() {
+            Registry::new_custom(Some(prefix), self.labels.clone()).expect("create prometheus registry")
+        } else {
+            Registry::new()
+        };
+        // init prometheus exporter
+        let mut exporter = opentelemetry_prometheus::exporter().with_registry(registry.clone());
+        if !self.target_info {
+            exporter = exporter.without_target_info();
+        }
+        if !self.scope_info {
+            exporter = exporter.without_scope_info();
+        }
+        (registry, exporter.build().unwrap())
+    }
+
+    /// init otlp metrics exporter
+    /// read from env var:
+    /// OTEL_EXPORTER_OTLP_METRICS_ENDPOINT, OTEL_EXPORTER_OTLP_METRICS_HEADERS,OTEL_EXPORTER_OTLP_METRICS_TIMEOUT
+    /// ref https://github.com/tokio-rs/tracing-opentelemetry/blob/5e3354ec24debcfbf856bfd1eb7022459dca1e6a/examples/opentelemetry-otlp.rs#L32
+    fn build_otlp(&self, stats: Arc<ExportStats>) -> impl opentelemetry_sdk::metrics::reader::MetricReader {
+        let protocol = match env::var("OTEL_EXPORTER_OTLP_METRICS_PROTOCOL")
+            .ok()
+            .or(env::var("OTEL_EXPORTER_OTLP_PROTOCOL").ok())
+        {
+            Some(val) => val,
+            None => "http/protobuf".to_string(),
+        };
+
+        let exporter = if protocol.starts_with("http") {
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .build_metrics_exporter(
+                    Box::new(DefaultTemporalitySelector::new()),
+                )
+                .unwrap()
+        } else {
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .build_metrics_exporter(
+                    Box::new(DefaultTemporalitySelector::new()),
+                )
+                .unwrap()
+        };
+
+        let exporter = ObservedExporter::new(exporter, stats, self.export_error_handler.clone());
+
+        let reader = PeriodicReader::builder(exporter, opentelemetry_sdk::runtime::Tokio)
+            .with_interval(std::time::Duration::from_secs(30))
+            .build();
+        reader
+    }
+}
+
+impl<S> Layer<S> for HttpMetricsLayer {
+    type Service = HttpMetrics<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        HttpMetrics {
+            state: self.state.clone(),
+            service,
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`HttpMetrics`] Service.
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        start: Instant,
+        state: Arc<MetricState>,
+        skip: bool,
+        duplicate: bool,
+        grpc: Option<(String, String)>,
+        path: Arc<str>,
+        refine_route: bool,
+        request_body_bytes: Option<RequestBodyBytes>,
+        method: Cow<'static, str>,
+        active_labels: Vec<KeyValue>,
+        host: Arc<str>,
+        server_port: Option<u16>,
+        client_address: Option<String>,
+        network_peer: Option<SocketAddr>,
+        query_keys: Option<String>,
+        not_found: Option<KeyValue>,
+        uncompressed: Option<UncompressedBytes>,
+        handler_time: Option<HandlerTiming>,
+        protocol_version: Option<&'static str>,
+        baggage: Vec<KeyValue>,
+        req_header_size: u64,
+        req_body_size: u64,
+        in_flight: Option<InFlightGuard>,
+        route_active: Option<RouteActiveGuard>,
+        tenant: Option<Arc<TenantMetrics>>,
+        tenant_id: Option<String>,
+        api_key_hash: Option<String>,
+        span_context: Option<SpanContext>,
+        span: Option<RequestSpan>,
+    }
+}
+
+impl<S, R, ResBody> Service<Request<R>> for HttpMetrics<S>
+where
+    S: Service<Request<RequestBody<R>>, Response = Response<ResBody>>,
+    ResBody: httpBody,
+{
+    type Response = Response<MetricsBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<R>) -> Self::Future {
+        // the same layer applied twice, e.g. with `with_metrics` and `.layer(metrics)`, only records the outer pass
+        let duplicate = match req.extensions_mut().get_mut::<AppliedLayers>() {
+            Some(AppliedLayers(ids)) if ids.contains(&self.state.layer_id) => true,
+            Some(AppliedLayers(ids)) => {
+                ids.push(self.state.layer_id);
+                false
+            }
+            None => {
+                req.extensions_mut().insert(AppliedLayers(vec![self.state.layer_id]));
+                false
+            }
+        };
+
+        let peer_addr = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr);
+        let peer = peer_addr.map(|addr| addr.ip());
+        let trust_forwarded = match self.state.trusted_proxies {
+            Some(ref proxies) => peer.is_some_and(|ip| proxies.iter().any(|net| net.contains(&ip))),
+            None => true,
+        };
+
+        let forwarded = if trust_forwarded {
+            req.headers()
+                .get(http::header::FORWARDED)
+                .and_then(|v| v.to_str().ok())
+                .map(parse_forwarded)
+                .unwrap_or_default()
+        } else {
+            ForwardedElement::default()
+        };
+
+        let url_scheme: Cow<'static, str> = if self.state.is_tls || req.extensions().get::<TlsConnection>().is_some() {
+            Cow::Borrowed("https")
+        } else if !trust_forwarded {
+            Cow::Borrowed("http")
+        } else {
+            (self.state.scheme_resolver.resolve)(req.headers()).map_or(Cow::Borrowed("http"), Cow::Owned)
+        };
+        let protocol_version = if self.state.protocol_version {
+            protocol_version(req.version())
+        } else {
+            None
+        };
+
+        let tenant_id = self.state.tenant_attribute.as_ref().and_then(|(extractor, admitted)| {
+            (extractor.extract)(req.headers(), req.extensions()).map(|tenant| {
+                if admitted.admit(&tenant) {
+                    tenant
+                } else {
+                    OTHER_TENANT_LABEL.to_string()
+                }
+            })
+        });
+
+        let api_key_hash = self
+            .state
+            .api_key_attribute
+            .as_ref()
+            .and_then(|(extractor, salt)| (extractor.extract)(req.headers(), req.extensions()).map(|key| api_key::hash(salt, &key)));
+
+        // ref https://github.com/open-telemetry/semantic-conventions/blob/main/docs/http/http-metrics.md#metric-httpserveractive_requests
+        // http.request.method and url.scheme is required
+        let mut active_labels = vec![
+            KeyValue::new("http.request.method", self.state.method_label(req.method())),
+            KeyValue::new("url.scheme", url_scheme.clone()),
+        ];
+        if let Some(version) = protocol_version {
+            active_labels.push(KeyValue::new("network.protocol.version", version));
+        }
+        if let Some(ref tenant_id) = tenant_id {
+            active_labels.push(KeyValue::new("tenant.id", tenant_id.clone()));
+        }
+        let active_labels = self.state.attributes(active_labels);
+        if !duplicate {
+            self.state.metric.req_active.add(1, &active_labels);
+        }
+        let start = Instant::now();
+        let method = self.state.method_label(req.method());
+        let extracted = (self.state.route_extractor.extract)(req.uri(), req.extensions());
+        // only the route of the router's own `MatchedPath` can be refined by a nested router
+        let from_matched_path = extracted.as_deref().is_some_and(|route| {
+            req.extensions()
+                .get::<MatchedPath>()
+                .is_some_and(|matched| matched.as_str() == route)
+        });
+        let path: Arc<str> = match extracted {
+            Some(route) => normalize_route(&route).into(),
+            None => self.state.unmatched_route(req.uri().path()).into(),
+        };
+        let skip = duplicate
+            || (self.state.head_requests == HeadRequests::Skip && req.method() == http::Method::HEAD)
+            || (self.state.skipper.load().skip)(&path)
+            || self.state.request_skipper.as_ref().is_some_and(|skipper| {
+                (skipper.skip)(&RequestInfo {
+                    method: req.method(),
+                    uri: req.uri(),
+                    version: req.version(),
+                    headers: req.headers(),
+                    extensions: req.extensions(),
+                    route: &path,
+                })
+            });
+        // the route is only copied when the grouper rewrites it
+        let grouped: Option<Arc<str>> = match self.state.route_grouper {
+            Some(ref grouper) => match grouper(&path) {
+                Cow::Borrowed(grouped) if grouped == &*path => None,
+                grouped => Some(grouped.into()),
+            },
+            None => None,
+        };
+        let path = grouped.unwrap_or(path);
+        let preflight = self.state.cors_preflight != CorsPreflight::Record && is_cors_preflight(&req);
+        let skip = skip || (preflight && self.state.cors_preflight == CorsPreflight::Skip);
+        let path = if preflight {
+            Arc::from(CORS_PREFLIGHT_ROUTE_LABEL)
+        } else {
+            path
+        };
+        let refine_route = from_matched_path && !preflight && !skip;
+
+        let host = forwarded
+            .host
+            .as_deref()
+            .or_else(|| header_str(req.headers(), http::header::HOST.as_str()))
+            .or_else(|| req.uri().authority().map(|authority| authority.as_str()))
+            .unwrap_or("unknown");
+        let (bare_host, port) = split_host_port(host);
+        let server_port = port
+            .or(match &*url_scheme {
+                "http" => Some(80),
+                "https" => Some(443),
+                _ => None,
+            })
+            .filter(|_| self.state.server_port);
+        let host: Arc<str> = if self.state.server_port { bare_host } else { host }.into();
+
+        let network_peer = peer_addr.filter(|_| self.state.network_peer);
+
+        let query_keys = req.uri().query().filter(|_| self.state.query_keys).and_then(query_keys);
+
+        let not_found = self.state.not_found_label.filter(|_| !skip).map(|label| match label {
+            NotFoundLabel::PathBucket { .. } => KeyValue::new("url.path_bucket", path_bucket(req.uri().path()).to_string()),
+            NotFoundLabel::RefererClass => KeyValue::new(
+                "http.referer_class",
+                referer_class(header_str(req.headers(), "Referer"), bare_host),
+            ),
+        });
+
+        let client_address = if self.state.client_address {
+            forwarded
+                .r#for
+                .clone()
+                .or_else(|| {
+                    header_str(req.headers(), "X-Forwarded-For")
+                        .filter(|_| trust_forwarded)
+                        .and_then(|v| v.split(',').next())
+                        .map(|v| v.trim().to_string())
+                })
+                .or_else(|| peer.map(|ip| ip.to_string()))
+        } else {
+            None
+        };
+
+        #[cfg(feature = "websocket")]
+        if let Some(ref websocket) = self.state.websocket {
+            if !skip && websocket::is_upgrade(req.headers()) {
+                let attributes = self.state.attributes(vec![KeyValue::new("http.route", path.clone())]);
+                req.extensions_mut().insert(websocket.with_attributes(attributes));
+            }
+        }
+
+        let uncompressed = self.state.compression.as_ref().filter(|_| !skip).map(|_| {
+            let bytes = UncompressedBytes::default();
+            req.extensions_mut().insert(bytes.clone());
+            bytes
+        });
+
+        let handler_time = self.state.handler_timing.as_ref().filter(|_| !skip).map(|_| {
+            let timing = HandlerTiming::new();
+            req.extensions_mut().insert(timing.clone());
+            timing
+        });
+
+        // the skipped requests are not recorded, so their headers are not walked
+        let (req_header_size, req_body_size) = if !skip && self.state.records_request_size() {
+            (compute_approximate_request_header_size(&req), request_body_size(&req))
+        } else {
+            (0, 0)
+        };
+
+        let grpc = self
+            .state
+            .metric
+            .rpc_server_duration
+            .as_ref()
+            .filter(|_| grpc::is_grpc(req.headers()))
+            .and_then(|_| grpc::parse_path(req.uri().path()));
+
+        let baggage = if self.state.baggage_keys.is_empty() {
+            Vec::new()
+        } else {
+            baggage::extract(req.headers(), &self.state.baggage_keys)
+        };
+
+        // the span is only looked up for the exemplars
+        let span = self.state.exemplars.as_ref().map(|_| trace_context::current_span());
+        let span_context = span
+            .as_ref()
+            .map(|span| trace_context::span_context(req.headers(), span));
+
+        if let Some(queue_time) = self.state.metric.queue_time.as_ref().filter(|_| !skip) {
+            let queue_start = header_str(req.headers(), "X-Request-Start")
+                .or_else(|| header_str(req.headers(), "X-Queue-Start"))
+                .and_then(parse_queue_start);
+            // a start in the future is the clock skew between the load balancer and us
+            if let Some(waited) = queue_start.and_then(|start| SystemTime::now().duration_since(start).ok()) {
+                let attributes = self.state.attributes(vec![
+                    KeyValue::new("http.request.method", method.clone()),
+                    KeyValue::new("http.route", path.clone()),
+                ]);
+                queue_time.record(waited.as_secs_f64(), &attributes);
+            }
+        }
+
+        let route_active = self
+            .state
+            .metric
+            .route_active_requests
+            .as_ref()
+            .filter(|_| !skip)
+            .map(|counter| {
+                let attributes = self.state.attributes(vec![
+                    KeyValue::new("http.request.method", method.clone()),
+                    KeyValue::new("http.route", path.clone()),
+                ]);
+                RouteActiveGuard::new(counter.clone(), attributes)
+            });
+
+        let in_flight = self
+            .state
+            .in_flight
+            .as_ref()
+            .filter(|_| !skip)
+            .map(|tracker| tracker.track(method.clone(), path.clone(), start));
+
+        let request_body_bytes = self
+            .state
+            .metric
+            .request_body_bytes
+            .as_ref()
+            .filter(|_| !skip && grpc.is_none())
+            .map(|_| RequestBodyBytes::default());
+        let req = req.map(|body| RequestBody::new(body, request_body_bytes.clone()));
+
+        let tenant = self
+            .state
+            .tenant_registries
+            .as_ref()
+            .filter(|_| !skip)
+            .and_then(|(extractor, registries)| {
+                (extractor.extract)(req.headers(), req.extensions()).and_then(|tenant| registries.get_or_create(&tenant))
+            });
+
+        // for scheme, see github.com/labstack/echo/v4@v4.11.1/context.go
+        // we can not use req.uri().scheme() since for non-absolute uri, it is always None
+
+        ResponseFuture {
+            inner: self.service.call(req),
+            start,
+            method,
+            skip,
+            duplicate,
+            grpc,
+            path,
+            refine_route,
+            request_body_bytes,
+            host,
+            server_port,
+            client_address,
+            network_peer,
+            query_keys,
+            not_found,
+            uncompressed,
+            handler_time,
+            protocol_version,
+            baggage,
+            req_header_size: req_header_size as u64,
+            req_body_size: req_body_size as u64,
+            in_flight,
+            route_active,
+            tenant,
+            tenant_id,
+            api_key_hash,
+            span_context,
+            span,
+            state: self.state.clone(),
+            active_labels,
+        }
+    }
+}
+
+/// the catch-all parameter of the routes added by `Router::nest` and `Router::nest_service`
+const NEST_TAIL_PARAM: &str = "__private__axum_nest_tail_param";
+
+/// normalize a route template to the axum 0.7 syntax, whatever the axum version or the extractor:
+/// `{id}` becomes `:id`, `{*rest}` becomes `*rest`, and the private catch-all of a nest prefix becomes `*`
+fn normalize_route(route: &str) -> Cow<'_, str> {
+    if !route.contains(['{', '}']) && !route.contains(NEST_TAIL_PARAM) {
+        return Cow::Borrowed(route);
+    }
+    let segments: Vec<String> = route
+        .split('/')
+        .map(|segment| {
+            let param = segment.strip_prefix('{').and_then(|s| s.strip_suffix('}'));
+            let segment = match param {
+                Some(name) if name.starts_with('*') => name.to_string(),
+                Some(name) => format!(":{}", name),
+                None => segment.to_string(),
+            };
+            if segment == format!("*{}", NEST_TAIL_PARAM) {
+                "*".to_string()
+            } else {
+                segment
+            }
+        })
+        .collect();
+    Cow::Owned(segments.join("/"))
+}
+
+/// the sorted and deduplicated parameter names of a query string, `None` if it has none
+fn query_keys(query: &str) -> Option<String> {
+    let mut keys: Vec<&str> = query
+        .split('&')
+        .map(|pair| pair.split_once('=').map_or(pair, |(key, _)| key))
+        .filter(|key| !key.is_empty())
+        .collect();
+    if keys.is_empty() {
+        return None;
+    }
+    keys.sort_unstable();
+    keys.dedup();
+    Some(keys.join(","))
+}
+
+/// the name of `method`, only allocated for the extension methods
+fn method_str(method: &http::Method) -> Cow<'static, str> {
+    use http::Method;
+
+    let name = match *method {
+        Method::GET => "GET",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        Method::CONNECT => "CONNECT",
+        Method::PATCH => "PATCH",
+        Method::TRACE => "TRACE",
+        _ => return Cow::Owned(method.as_str().to_string()),
+    };
+    Cow::Borrowed(name)
+}
+
+/// the decimal representation of a status code, interned on first use
+fn status_str(status: u16) -> &'static str {
+    static STATUS_CODES: OnceLock<Box<[&'static str]>> = OnceLock::new();
+    let codes = STATUS_CODES.get_or_init(|| {
+        (100..1000u16)
+            .map(|code| &*Box::leak(code.to_string().into_boxed_str()))
+            .collect()
+    });
+    // the status codes of `http` are three digits
+    codes[usize::from(status.clamp(100, 999) - 100)]
+}
+
+/// the time the load balancer received the request, from a `X-Request-Start` header value
+/// in seconds, milliseconds or microseconds since unix epoch, told apart by their magnitude
+fn parse_queue_start(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let timestamp: f64 = value.strip_prefix("t=").unwrap_or(value).parse().ok()?;
+    let seconds = if timestamp > 1e15 {
+        timestamp / 1e6
+    } else if timestamp > 1e12 {
+        timestamp / 1e3
+    } else {
+        timestamp
+    };
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return None;
+    }
+    UNIX_EPOCH.checked_add(Duration::from_secs_f64(seconds))
+}
+
+/// split a `Host` header value into the `server.address` and the `server.port`,
+/// the brackets of an IPv6 address are removed
+fn split_host_port(host: &str) -> (&str, Option<u16>) {
+    if let Some(rest) = host.strip_prefix('[') {
+        return match rest.split_once(']') {
+            Some((address, port)) => (address, port.strip_prefix(':').and_then(|p| p.parse().ok())),
+            None => (host, None),
+        };
+    }
+    match host.split_once(':') {
+        // a bare IPv6 address has several colons and no port
+        Some((address, port)) if !port.contains(':') => (address, port.parse().ok()),
+        _ => (host, None),
+    }
+}
+
+/// returns the value of the header `name` if it is present and valid visible ASCII
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// the forwarding information from the first element of an RFC 7239 `Forwarded` header,
+/// which is the one added by the proxy closest to the client.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct ForwardedElement {
+    proto: Option<String>,
+    host: Option<String>,
+    r#for: Option<String>,
+}
+
+/// parse the `Forwarded` header value, e.g. `for=192.0.2.60;proto=http;by=203.0.113.43`
+///
+/// ref https://www.rfc-editor.org/rfc/rfc7239#section-4
+fn parse_forwarded(value: &str) -> ForwardedElement {
+    let mut element = ForwardedElement::default();
+    let first = split_unquoted(value, ',').next().unwrap_or_default();
+    for pair in split_unquoted(first, ';') {
+        let Some((name, val)) = pair.split_once('=') else {
+            continue;
+        };
+        let val = unquote(val.trim());
+        if val.is_empty() {
+            continue;
+        }
+        match name.trim().to_ascii_lowercase().as_str() {
+            "proto" => element.proto = Some(val.to_ascii_lowercase()),
+            "host" => element.host = Some(val.into_owned()),
+            "for" => element.r#for = Some(strip_node_port(&val)),
+            _ => {}
+        }
+    }
+    element
+}
+
+/// split `value` on `delimiter`, except inside the quoted strings
+fn split_unquoted(value: &str, delimiter: char) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    let mut escaped = false;
+    value.split(move |c: char| {
+        if escaped {
+            escaped = false;
+            return false;
+        }
+        match c {
+            '\\' if quoted => {
+                escaped = true;
+                false
+            }
+            '"' => {
+                quoted = !quoted;
+                false
+            }
+            c => c == delimiter && !quoted,
+        }
+    })
+}
+
+/// the value of a `Forwarded` parameter, without the quotes and the escapes of a quoted string
+fn unquote(value: &str) -> Cow<'_, str> {
+    let Some(quoted) = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) else {
+        return Cow::Borrowed(value);
+    };
+    if !quoted.contains('\\') {
+        return Cow::Borrowed(quoted);
+    }
+    let mut unescaped = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+/// strip the optional port from a `Forwarded` node, e.g. `"[2001:db8::1]:4711"` becomes `2001:db8::1`
+fn strip_node_port(node: &str) -> String {
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest).to_string();
+    }
+    match node.split_once(':') {
+        // a bare IPv6 address has more than one colon and no port
+        Some((addr, port)) if !port.contains(':') => addr.to_string(),
+        _ => node.to_string(),
+    }
+}
+
+/// map the request's HTTP version to the semconv `network.protocol.version` value
+fn protocol_version(version: http::Version) -> Option<&'static str> {
+    match version {
+        http::Version::HTTP_09 => Some("0.9"),
+        http::Version::HTTP_10 => Some("1.0"),
+        http::Version::HTTP_11 => Some("1.1"),
+        http::Version::HTTP_2 => Some("2"),
+        http::Version::HTTP_3 => Some("3"),
+        _ => None,
+    }
+}
+
+/// the approximate size of the request line and headers
+///
+/// the implimentation refs [labstack/echo-contrib 's prometheus middleware](https://github.com/labstack/echo-contrib/blob/db8911a1af7abb6bdafbd999adada548fd9c0849/echoprometheus/prometheus.go#L329)
+fn compute_approximate_request_header_size<T>(req: &Request<T>) -> usize {
+    let mut s = 0;
+    s += req.uri().path().len();
+    s += req.method().as_str().len();
+    s += headers_size(req.headers());
+    s += req.uri().host().map(|h| h.len()).unwrap_or(0);
+    s
+}
+
+/// the request body size announced by the `Content-Length` header
+fn request_body_size<T>(req: &Request<T>) -> usize {
+    req.headers()
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0)
+}
+
+/// the size of the header names and values
+fn headers_size(headers: &HeaderMap) -> usize {
+    headers.iter().map(|(k, v)| k.as_str().len() + v.as_bytes().len()).sum()
+}
+
+impl<F, B: httpBody, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<MetricsBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = ready!(this.inner.poll(cx))?;
+        this.in_flight.take();
+        this.route_active.take();
+
+        if *this.duplicate {
+            return Poll::Ready(Ok(response.map(MetricsBody::new)));
+        }
+
+        // the recording hooks, e.g. the response skipper, run in the request span when the exemplars looked it up
+        #[cfg(feature = "tracing")]
+        let _entered = this.span.as_ref().map(|span| span.enter());
+        #[cfg(not(feature = "tracing"))]
+        let _ = this.span;
+
+        // the labels the request was added with in `call`
+        this.state.metric.req_active.add(-1, this.active_labels);
+
+        if *this.skip || response.extensions().get::<SkipMetrics>().is_some() {
+            return Poll::Ready(Ok(response.map(MetricsBody::new)));
+        }
+
+        if let Some(ref skipper) = this.state.response_skipper {
+            if (skipper.skip)(response.status(), response.headers()) {
+                return Poll::Ready(Ok(response.map(MetricsBody::new)));
+            }
+        }
+
+        // the deepest route template, propagated by a nested router, wins over the one seen on the way in
+        if let Some(matched) = response.extensions().get::<MatchedPath>().filter(|_| *this.refine_route) {
+            let route = normalize_route(matched.as_str());
+            // the request skipper already ran on the route seen on the way in, the request is gone by now
+            if (this.state.skipper.load().skip)(&route) {
+                return Poll::Ready(Ok(response.map(MetricsBody::new)));
+            }
+            *this.path = match this.state.route_grouper {
+                Some(ref grouper) => grouper(&route).into(),
+                None => route.into(),
+            };
+        }
+
+        if let (Some(bytes), Some(ref counter)) = (this.request_body_bytes.take(), &this.state.metric.request_body_bytes) {
+            let attributes = this.state.attributes(vec![
+                KeyValue::new("http.request.method", this.method.clone()),
+                KeyValue::new("http.route", this.path.clone()),
+            ]);
+            bytes.count(BodyBytes::new(counter.clone(), attributes));
+        }
+
+        // the gRPC calls are recorded by the response body, once the `grpc-status` trailer is sent
+        if let (Some((service, method)), Some(ref duration)) = (this.grpc.take(), &this.state.metric.rpc_server_duration) {
+            let call = GrpcCall {
+                duration: duration.clone(),
+                attributes: this.state.attributes(vec![
+                    KeyValue::new("rpc.system", "grpc"),
+                    KeyValue::new("rpc.service", service),
+                    KeyValue::new("rpc.method", method),
+                ]),
+                start: *this.start,
+            };
+            // a trailers-only response carries the status in its headers
+            if let Some(status) = grpc::status(response.headers()) {
+                call.finish(status);
+                return Poll::Ready(Ok(response.map(MetricsBody::new)));
+            }
+            return Poll::Ready(Ok(response.map(|body| MetricsBody::grpc(body, call))));
+        }
+
+        let body_bytes = this.state.metric.response_body_bytes.as_ref().map(|counter| {
+            let attributes = this.state.attributes(vec![
+                KeyValue::new("http.request.method", this.method.clone()),
+                KeyValue::new("http.route", this.path.clone()),
+            ]);
+            BodyBytes::new(counter.clone(), attributes)
+        });
+
+        let sse = this
+            .state
+            .sse
+            .as_ref()
+            .filter(|_| sse::is_event_stream(response.headers()))
+            .map(|sse| {
+                let attributes = this.state.attributes(vec![
+                    KeyValue::new("http.request.method", this.method.clone()),
+                    KeyValue::new("http.route", this.path.clone()),
+                ]);
+                sse.start(attributes, *this.start)
+            });
+
+        let compressed = this
+            .state
+            .compression
+            .as_ref()
+            .zip(this.uncompressed.take())
+            .and_then(|(compression, uncompressed)| {
+                let encoding = compression_ratio::content_encoding(response.headers())?;
+                let attributes = this.state.attributes(vec![
+                    KeyValue::new("http.request.method", this.method.clone()),
+                    KeyValue::new("http.route", this.path.clone()),
+                    KeyValue::new("http.response.content_encoding", encoding.to_ascii_lowercase()),
+                ]);
+                Some(compression.start(uncompressed, attributes))
+            });
+
+        let elapsed = this.start.elapsed();
+        let latency = elapsed.as_secs_f64();
+        let status = response.status().as_u16();
+
+        let res_size = response.body().size_hint().upper().unwrap_or(0);
+
+        let recorded = RecordedResponse {
+            method: this.method,
+            route: this.path,
+            status: response.status(),
+            headers: response.headers(),
+            extensions: response.extensions(),
+            elapsed,
+        };
+        let cache_key = AttributeKey {
+            method: &**this.method,
+            route: &**this.path,
+            status,
+            protocol_version: *this.protocol_version,
+        };
+        let route_labels = match this.state.attribute_cache.get(&cache_key) {
+            Some(labels) => labels,
+            None => {
+                let mut labels = vec![
+                    KeyValue {
+                        key: Key::from("http.request.method"),
+                        value: Value::from(this.method.clone()),
+                    },
+                    KeyValue::new("http.route", this.path.clone()),
+                    KeyValue::new("http.response.status_code", status_str(status)),
+                ];
+                if let Some(version) = *this.protocol_version {
+                    labels.push(KeyValue::new("network.protocol.version", version));
+                }
+                let labels: Arc<[KeyValue]> = this.state.attributes(labels).into();
+                this.state.attribute_cache.insert(&cache_key, labels.clone());
+                labels
+            }
+        };
+        // the attributes taken from the request are processed on their own, they would make every cached set unique
+        let mut request_labels = vec![
+            // server.address: Name of the local HTTP server that received the request.
+            // Determined by using the first of the following that applies
+            //
+            // 1. The primary server name of the matched virtual host. MUST only include host identifier.
+            // 2. Host identifier of the request target if it's sent in absolute-form.
+            // 3. Host identifier of the Host header
+            KeyValue::new("server.address", this.host.clone()),
+        ];
+        if let Some(port) = *this.server_port {
+            request_labels.push(KeyValue::new("server.port", port as i64));
+        }
+        if let Some(client_address) = this.client_address.take() {
+            request_labels.push(KeyValue::new("client.address", client_address));
+        }
+        if let Some(peer) = this.network_peer.take() {
+            request_labels.push(KeyValue::new("network.peer.address", peer.ip().to_string()));
+            request_labels.push(KeyValue::new("network.peer.port", peer.port() as i64));
+        }
+        if let Some(query_keys) = this.query_keys.take() {
+            request_labels.push(KeyValue::new("url.query_keys", query_keys));
+        }
+        if let Some(tenant_id) = this.tenant_id.take() {
+            request_labels.push(KeyValue::new("tenant.id", tenant_id));
+        }
+        if let Some(api_key_hash) = this.api_key_hash.take() {
+            request_labels.push(KeyValue::new("api_key.hash", api_key_hash));
+        }
+        request_labels.append(this.baggage);
+        for recorder in this.state.recorders.iter() {
+            recorder.request_attributes(&recorded, &mut request_labels);
+        }
+        this.state.process_attributes(&mut request_labels);
+        let mut labels = Vec::with_capacity(route_labels.len() + request_labels.len());
+        labels.extend(route_labels.iter().cloned());
+        labels.append(&mut request_labels);
+
+        if let Some(ref requests_total) = this.state.metric.requests_total {
+            requests_total.add(1, &labels);
+        }
+
+        if let (Some(ref not_found), Some(mut label)) = (&this.state.metric.not_found, this.not_found.take()) {
+            if response.status() == http::StatusCode::NOT_FOUND {
+                if label.key.as_str() == "url.path_bucket" && !this.state.not_found_buckets.admit(label.value.as_str().as_ref()) {
+                    label = KeyValue::new("url.path_bucket", OVERFLOW_VALUE);
+                }
+                let not_found_labels =
+                    this.state.attributes(vec![KeyValue::new("http.request.method", this.method.clone()), label]);
+                not_found.add(1, &not_found_labels);
+            }
+        }
+
+        for recorder in this.state.recorders.iter() {
+            recorder.record(this.state, &recorded);
+        }
+
+        if !this.state.histogram_mode.should_record(response.status(), elapsed) {
+            return Ready(Ok(response.map(|body| MetricsBody::recorded(body, sse, body_bytes, compressed))));
+        }
+
+        if !this.state.sample_rate.sample() {
+            return Ready(Ok(response.map(|body| MetricsBody::recorded(body, sse, body_bytes, compressed))));
+        }
+
+        let req_size = *this.req_header_size + *this.req_body_size;
+        if let Some(ref req_size_histogram) = this.state.metric.req_size {
+            req_size_histogram.record(req_size, &labels);
+        }
+        if let Some(ref res_size_histogram) = this.state.metric.res_size {
+            res_size_histogram.record(res_size, &labels);
+        }
+
+        if let Some(ref req_header_size) = this.state.metric.req_header_size {
+            req_header_size.record(*this.req_header_size, &labels);
+        }
+        if let Some(ref req_body_size) = this.state.metric.req_body_size {
+            req_body_size.record(*this.req_body_size, &labels);
+        }
+        if let Some(ref res_header_size) = this.state.metric.res_header_size {
+            res_header_size.record(headers_size(response.headers()) as u64, &labels);
+        }
+        if let Some(ref res_body_size) = this.state.metric.res_body_size {
+            res_body_size.record(res_size, &labels);
+        }
+
+        match this.state.metric.route_req_duration.get(&**this.path) {
+            Some(histogram) => histogram.record(latency, &labels),
+            None => this.state.metric.req_duration.record(latency, &labels),
+        }
+
+        if let Some(tenant) = this.tenant.take() {
+            tenant.record(&labels, latency, req_size, res_size);
+        }
+
+        if let Some(UpstreamDuration(upstream)) = response.extensions().get::<UpstreamDuration>() {
+            this.state.metric.upstream_duration.record(upstream.as_secs_f64(), &labels);
+        }
+
+        if let (Some(ref handler_timing), Some(ref timing)) = (&this.state.handler_timing, &this.handler_time) {
+            handler_timing.record(timing, elapsed, &labels);
+        }
+
+        if let (Some(ref exemplars), Some(ref span_context)) = (&this.state.exemplars, &this.span_context) {
+            exemplars.duration.record(&labels, latency, span_context);
+            exemplars.request_size.record(&labels, req_size as f64, span_context);
+            exemplars.response_size.record(&labels, res_size as f64, span_context);
+        }
+
+        Ready(Ok(response.map(|body| MetricsBody::recorded(body, sse, body_bytes, compressed))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::HttpMetricsLayerBuilder;
+    use axum::extract::State;
+    use axum::routing::get;
+    use axum::Router;
+    use opentelemetry::{global, Context, KeyValue};
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+    use prometheus::{Encoder, Registry, TextEncoder};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_prometheus_exporter() {
+        let _cx = Context::current();
+
+        let registry = Registry::new();
+
+        // init prometheus exporter
+        let exporter = opentelemetry_prometheus::exporter()
+            .with_registry(registry.clone())
+            .build()
+            .unwrap();
+
+        let provider = SdkMeterProvider::builder().with_reader(exporter).build();
+
+        // init the global meter provider
+        global::set_meter_provider(provider.clone());
+
+        let meter = global::meter("my-app");
+
+        // Use two instruments
+        let counter = meter.u64_counter("a.counter").with_description("Counts things").init();
+        let recorder = meter.u64_histogram("a.histogram").with_description("Records values").init();
+
+        counter.add(100, &[KeyValue::new("key", "value")]);
+        recorder.record(100, &[KeyValue::new("key", "value")]);
+
+        // Encode data as text or protobuf
+        let encoder = TextEncoder::new();
+        let metric_families = registry.gather();
+        let mut result = Vec::new();
+        encoder.encode(&metric_families, &mut result).expect("encode failed");
+        println!("{}", String::from_utf8(result).unwrap());
+    }
+
+    #[test]
+    fn test_builder() {
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let _app = Router::new()
+            // export metrics at `/metrics` endpoint
+            .merge(metrics.routes::<()>())
+            .route("/", get(handler))
+            .route("/hello", get(handler))
+            .route("/world", get(handler))
+            // add the metrics middleware
+            .layer(metrics);
+
+        async fn handler() -> &'static str {
+            "<h1>Hello, World!</h1>"
+        }
+    }
+
+    #[test]
+    fn test_builder_with_state_router() {
+        #[derive(Clone)]
+        struct AppState {}
+
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let _app: Router<AppState> = Router::new()
+            // export metrics at `/metrics` endpoint
+            .merge(metrics.routes::<AppState>())
+            .route("/", get(handler))
+            .route("/hello", get(handler))
+            .route("/world", get(handler))
+            // add the metrics middleware
+            .layer(metrics)
+            .with_state(AppState {});
+
+        async fn handler(_state: State<AppState>) -> &'static str {
+            "<h1>Hello, World!</h1>"
+        }
+    }
+
+    #[test]
+    fn test_builder_with_arced_skipper() {
+        #[derive(Clone)]
+        struct AppState {}
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_skipper(crate::PathSkipper::new_with_fn(Arc::new(|_: &str| true)))
+            .build();
+        let _app: Router<AppState> = Router::new()
+            // export metrics at `/metrics` endpoint
+            .merge(metrics.routes::<AppState>())
+            .route("/", get(handler))
+            // add the metrics middleware
+            .layer(metrics)
+            .with_state(AppState {});
+
+        async fn handler(_state: State<AppState>) -> &'static str {
+            "<h1>Hello, World!</h1>"
+        }
+    }
+
+    #[test]
+    fn test_parse_forwarded() {
+        let fwd = crate::parse_forwarded(r#"for="[2001:db8:cafe::17]:4711";proto=HTTPS;host=example.com, for=192.0.2.43"#);
+        assert_eq!(fwd.r#for.as_deref(), Some("2001:db8:cafe::17"));
+        assert_eq!(fwd.proto.as_deref(), Some("https"));
+        assert_eq!(fwd.host.as_deref(), Some("example.com"));
+
+        let fwd = crate::parse_forwarded("for=192.0.2.60:8080;by=203.0.113.43");
+        assert_eq!(fwd.r#for.as_deref(), Some("192.0.2.60"));
+        assert_eq!(fwd.proto, None);
+        assert_eq!(fwd.host, None);
+
+        // the delimiters inside the quoted strings do not split the elements nor the pairs
+        let fwd = crate::parse_forwarded(r#"for="[2001:db8::1]";host="a,b;c";proto=https, for=198.51.100.17;proto=http"#);
+        assert_eq!(fwd.r#for.as_deref(), Some("2001:db8::1"));
+        assert_eq!(fwd.host.as_deref(), Some("a,b;c"));
+        assert_eq!(fwd.proto.as_deref(), Some("https"));
+
+        let fwd = crate::parse_forwarded(r#"host="ex\"ample.com";for=unknown"#);
+        assert_eq!(fwd.host.as_deref(), Some(r#"ex"ample.com"#));
+        assert_eq!(fwd.r#for.as_deref(), Some("unknown"));
+    }
+
+    #[test]
+    fn test_parse_forwarded_elements() {
+        // only the first element, added by the proxy closest to the client, is read
+        let fwd = crate::parse_forwarded(r#"for=192.0.2.43, for="[2001:db8:cafe::17]:4711";proto=https;host=example.com"#);
+        assert_eq!(fwd.r#for.as_deref(), Some("192.0.2.43"));
+        assert_eq!(fwd.proto, None);
+        assert_eq!(fwd.host, None);
+
+        let fwd = crate::parse_forwarded(r#"for="[2001:db8:cafe::17]:4711", for=192.0.2.43;proto=https"#);
+        assert_eq!(fwd.r#for.as_deref(), Some("2001:db8:cafe::17"));
+        assert_eq!(fwd.proto, None);
+    }
+
+    #[test]
+    fn test_builder_with_trusted_proxies() {
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()])
+            .with_client_address(true)
+            .build();
+        let _app = Router::new()
+            .merge(metrics.routes::<()>())
+            .route("/", get(handler))
+            .layer(metrics);
+
+        async fn handler() -> &'static str {
+            "<h1>Hello, World!</h1>"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trusted_proxies() {
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()])
+            .with_client_address(true)
+            .build();
+        let mut families = Vec::new();
+        for peer in ["10.0.0.1:4000", "203.0.113.7:4000"] {
+            let mut request = axum::http::Request::builder()
+                .uri("/users")
+                .header("x-forwarded-proto", "https")
+                .header("x-forwarded-for", "198.51.100.17")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+            families = gather(&metrics, request).await;
+        }
+
+        let values = |family: &str, label: &str| {
+            let family = families.iter().find(|f| f.get_name() == family).unwrap();
+            let mut values = family
+                .get_metric()
+                .iter()
+                .flat_map(|m| m.get_label())
+                .filter(|l| l.get_name() == label)
+                .map(|l| l.get_value().to_string())
+                .collect::<Vec<_>>();
+            values.sort();
+            values
+        };
+        // the forwarded headers of the trusted proxy are honored, the spoofed ones of the other peer are ignored
+        assert_eq!(values("http_server_active_requests", "url_scheme"), ["http", "https"]);
+        assert_eq!(values("requests_total", "client_address"), ["198.51.100.17", "203.0.113.7"]);
+    }
+
+    #[test]
+    fn test_default_scheme_resolver() {
+        let resolver = crate::SchemeResolver::default();
+        let mut headers = axum::http::HeaderMap::new();
+        assert_eq!((resolver.resolve)(&headers), None);
+
+        headers.insert("X-Forwarded-Ssl", "on".parse().unwrap());
+        assert_eq!((resolver.resolve)(&headers).as_deref(), Some("https"));
+
+        headers.insert("Forwarded", "for=192.0.2.60;proto=http".parse().unwrap());
+        assert_eq!((resolver.resolve)(&headers).as_deref(), Some("http"));
+    }
+
+    #[tokio::test]
+    async fn test_protocol_version() {
+        let request = |version| {
+            axum::http::Request::builder()
+                .uri("/users")
+                .version(version)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        let versions = |families: &[prometheus::proto::MetricFamily]| {
+            let family = families
+                .iter()
+                .find(|f| f.get_name() == "http_server_request_duration_seconds")
+                .unwrap();
+            let mut versions = family
+                .get_metric()
+                .iter()
+                .flat_map(|m| m.get_label())
+                .filter(|l| l.get_name() == "network_protocol_version")
+                .map(|l| l.get_value().to_string())
+                .collect::<Vec<_>>();
+            versions.sort();
+            versions
+        };
+
+        let metrics = HttpMetricsLayerBuilder::new().with_protocol_version(true).build();
+        gather(&metrics, request(axum::http::Version::HTTP_11)).await;
+        let families = gather(&metrics, request(axum::http::Version::HTTP_2)).await;
+        assert_eq!(versions(&families), ["1.1", "2"]);
+
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let families = gather(&metrics, request(axum::http::Version::HTTP_2)).await;
+        assert!(versions(&families).is_empty());
+    }
+
+    #[test]
+    fn test_unmatched_route_raw_path_limit() {
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_unmatched_route(crate::UnmatchedRoute::RawPath { max_paths: 2 })
+            .build();
+        let state = &metrics.state;
+        assert_eq!(state.unmatched_route("/a"), "/a");
+        assert_eq!(state.unmatched_route("/b"), "/b");
+        assert_eq!(state.unmatched_route("/c"), crate::UNMATCHED_ROUTE_LABEL);
+        assert_eq!(state.unmatched_route("/a"), "/a");
+    }
+
+    #[test]
+    fn test_builder_with_route_grouper() {
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_route_grouper(|route| match route.strip_prefix("/api/v1/").or(route.strip_prefix("/api/v2/")) {
+                Some(rest) => format!("/api/*/{rest}").into(),
+                None => route.into(),
+            })
+            .build();
+        let grouper = metrics.state.route_grouper.as_ref().unwrap();
+        assert_eq!(grouper("/api/v2/users/:id"), "/api/*/users/:id");
+        assert_eq!(grouper("/hello"), "/hello");
+    }
+
+    #[test]
+    fn test_builder_with_route_buckets() {
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_route_buckets("/export".to_string(), vec![1.0, 10.0, 30.0, 60.0, 120.0])
+            .build();
+        assert!(metrics.state.metric.route_req_duration.contains_key("/export"));
+        assert!(!metrics.state.metric.route_req_duration.contains_key("/"));
+    }
+
+    #[test]
+    fn test_allow_only_skipper() {
+        let skipper = crate::PathSkipper::allow_only(["/api/", "/rpc"]);
+        assert!(!(skipper.skip)("/api/users/:id"));
+        assert!(!(skipper.skip)("/rpc"));
+        assert!((skipper.skip)("/static/app.js"));
+        assert!((skipper.skip)("/metrics"));
+
+        let skipper = crate::PathSkipper::default().invert();
+        assert!(!(skipper.skip)("/metrics"));
+        assert!((skipper.skip)("/hello"));
+    }
+
+    #[test]
+    fn test_regex_skipper() {
+        let skipper = crate::PathSkipper::from_regex_set([r"^/internal/.*", r"\.(png|css|js)$"]).unwrap();
+        assert!((skipper.skip)("/internal/debug"));
+        assert!((skipper.skip)("/static/app.js"));
+        assert!(!(skipper.skip)("/api/users"));
+
+        assert!(crate::PathSkipper::from_regex("(").is_err());
+    }
+
+    #[test]
+    fn test_glob_skipper() {
+        let skipper = crate::PathSkipper::from_globs(["/static/**", "/health*"]).unwrap();
+        assert!((skipper.skip)("/static/css/app.css"));
+        assert!((skipper.skip)("/healthz"));
+        assert!(!(skipper.skip)("/health/live"));
+        assert!(!(skipper.skip)("/api/users"));
+    }
+
+    #[test]
+    fn test_builder_with_request_skipper() {
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_request_skipper(crate::RequestSkipper::new(|req| req.method == axum::http::Method::OPTIONS))
+            .build();
+        let _app = Router::new()
+            .merge(metrics.routes::<()>())
+            .route("/", get(handler))
+            .layer(metrics);
+
+        async fn handler() -> &'static str {
+            "<h1>Hello, World!</h1>"
+        }
+    }
+
+    #[test]
+    fn test_status_response_skipper() {
+        use axum::http::{HeaderMap, StatusCode};
+
+        let skipper = crate::ResponseSkipper::status([StatusCode::NOT_FOUND, StatusCode::METHOD_NOT_ALLOWED]);
+        assert!((skipper.skip)(StatusCode::NOT_FOUND, &HeaderMap::new()));
+        assert!(!(skipper.skip)(StatusCode::OK, &HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_errors_only_histogram_mode() {
+        use crate::HistogramMode;
+        use axum::http::StatusCode;
+        use std::time::Duration;
+
+        let mode = HistogramMode::ErrorsOnly {
+            slower_than: Some(Duration::from_millis(500)),
+        };
+        assert!(!mode.should_record(StatusCode::OK, Duration::from_millis(10)));
+        assert!(mode.should_record(StatusCode::OK, Duration::from_secs(1)));
+        assert!(mode.should_record(StatusCode::NOT_FOUND, Duration::from_millis(10)));
+        assert!(mode.should_record(StatusCode::BAD_GATEWAY, Duration::from_millis(10)));
+        assert!(HistogramMode::All.should_record(StatusCode::OK, Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_threshold() {
+        use std::time::Duration;
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_slow_request_threshold(Duration::from_millis(1))
+            .build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            // a blocking sleep, the handler only has to outlast the threshold
+            std::thread::sleep(Duration::from_millis(10));
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        }));
+        let request = axum::http::Request::builder().uri("/slow").body(axum::body::Body::empty()).unwrap();
+        service.oneshot(request).await.unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        let slow = families
+            .iter()
+            .find(|f| f.get_name() == "http_server_slow_requests_total")
+            .unwrap();
+        let metric = &slow.get_metric()[0];
+        assert_eq!(metric.get_counter().get_value(), 1.0);
+        assert!(metric
+            .get_label()
+            .iter()
+            .any(|l| l.get_name() == "threshold" && l.get_value() == "slow"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_threshold_not_exceeded() {
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_slow_request_threshold(std::time::Duration::from_secs(60))
+            .build();
+        let request = axum::http::Request::builder().uri("/fast").body(axum::body::Body::empty()).unwrap();
+        let families = gather(&metrics, request).await;
+        assert!(families.iter().any(|f| f.get_name() == "http_server_request_duration_seconds"));
+        assert!(!families
+            .iter()
+            .filter(|f| f.get_name() == "http_server_slow_requests_total")
+            .any(|f| !f.get_metric().is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_exporter_content_negotiation() {
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let request = axum::http::Request::builder().uri("/users").body(axum::body::Body::empty()).unwrap();
+        let _ = gather(&metrics, request).await;
+
+        let scrape = |accept: &'static str| {
+            let metrics = metrics.clone();
+            async move {
+                let request = axum::http::Request::builder()
+                    .header(axum::http::header::ACCEPT, accept)
+                    .body(axum::body::Body::empty())
+                    .unwrap();
+                let response = crate::exporter_request_handler(State(metrics.metric_state()), request).await;
+                let content_type = response.headers().get(axum::http::header::CONTENT_TYPE).unwrap().clone();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (content_type, body)
+            }
+        };
+
+        let (content_type, body) = scrape("application/openmetrics-text;version=1.0.0,text/plain;q=0.5").await;
+        assert_eq!(content_type, "application/openmetrics-text; version=1.0.0; charset=utf-8");
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("# TYPE requests counter\n"));
+        assert!(body.ends_with("# EOF\n"));
+
+        let (content_type, body) =
+            scrape("application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=delimited").await;
+        assert_eq!(
+            content_type,
+            "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited"
+        );
+        // length delimited binary messages, not text
+        assert!(!body.is_empty() && !body.starts_with(b"#"));
+
+        // the unsupported formats fall back to the classic text format
+        let (content_type, body) = scrape("application/json").await;
+        assert_eq!(content_type, "text/plain; version=0.0.4; charset=utf-8");
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("# TYPE requests_total counter\n"));
+        assert!(!body.contains("# EOF"));
+    }
+
+    #[tokio::test]
+    async fn test_requests_counter_name() {
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_requests_counter_name("http.server.requests".to_string())
+            .build();
+        let request = axum::http::Request::builder().uri("/users").body(axum::body::Body::empty()).unwrap();
+        let families = gather(&metrics, request).await;
+        assert!(families.iter().any(|f| f.get_name() == "http_server_requests_total"));
+        assert!(!families.iter().any(|f| f.get_name() == "requests_total"));
+    }
+
+    #[tokio::test]
+    async fn test_without_requests_counter() {
+        let metrics = HttpMetricsLayerBuilder::new().with_requests_counter(false).build();
+        let request = axum::http::Request::builder().uri("/users").body(axum::body::Body::empty()).unwrap();
+        let families = gather(&metrics, request).await;
+        assert!(families.iter().any(|f| f.get_name() == "http_server_request_duration_seconds"));
+        assert!(!families.iter().any(|f| f.get_name() == "requests_total"));
+    }
+
+    #[test]
+    fn test_instrument_prefix() {
+        let builder = HttpMetricsLayerBuilder::new();
+        assert_eq!(builder.instrument_name("http.server.request.size"), "http.server.request.size");
+
+        let builder = builder.with_instrument_prefix("myapp".to_string());
+        assert_eq!(builder.instrument_name("http.server.request.size"), "myapp.http.server.request.size");
+    }
+
+    #[tokio::test]
+    async fn test_const_attributes() {
+        use tower::ServiceExt;
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_const_attributes(vec![KeyValue::new("region", "eu-west-1"), KeyValue::new("cluster", "blue")])
+            .with_attribute_naming(crate::AttributeNaming::dotted().with_rename("http.route", "route"))
+            .with_cardinality_limit("server.address", 1)
+            .build();
+        let app: Router = Router::new().route("/users", get(|| async { "users" })).layer(metrics.clone());
+        for host in ["example.com", "example.org"] {
+            let request = axum::http::Request::builder()
+                .uri("/users")
+                .header("host", host)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        // the const attributes are appended to the renamed and limited attributes of every series
+        let families = metrics.registry().unwrap().gather();
+        for name in ["http_server_request_duration_seconds", "requests_total"] {
+            let family = families.iter().find(|f| f.get_name() == name).unwrap();
+            let mut hosts = vec![];
+            for metric in family.get_metric() {
+                let label = |key: &str| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .find(|l| l.get_name() == key)
+                        .map(|l| l.get_value().to_string())
+                };
+                assert_eq!(label("region").as_deref(), Some("eu-west-1"));
+                assert_eq!(label("cluster").as_deref(), Some("blue"));
+                assert_eq!(label("route").as_deref(), Some("/users"));
+                hosts.push(label("server_address").unwrap());
+            }
+            hosts.sort();
+            assert_eq!(hosts, [crate::OVERFLOW_VALUE, "example.com"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_labels_resource() {
+        let labels: std::collections::HashMap<_, _> = [("env".to_string(), "testing".to_string())].into();
+
+        // the otlp exporter gets the labels as resource attributes
+        let builder = HttpMetricsLayerBuilder::new()
+            .with_exporter("otlp".to_string())
+            .with_labels(labels.clone());
+        let attributes = builder.explicit_resource_attributes();
+        assert!(attributes.contains(&KeyValue::new("env", "testing")));
+
+        // the prometheus exporter only as const labels of the registry, which `target_info` gets once
+        let builder = HttpMetricsLayerBuilder::new()
+            .with_prefix("myapp".to_string())
+            .with_labels(labels);
+        let attributes = builder.explicit_resource_attributes();
+        assert!(!attributes.iter().any(|kv| kv.key.as_str() == "env"));
+        let metrics = builder.build();
+        let request = axum::http::Request::builder().uri("/users").body(axum::body::Body::empty()).unwrap();
+        let families = gather(&metrics, request).await;
+        let env_labels = |name: &str| {
+            families
+                .iter()
+                .filter(|f| f.get_name().ends_with(name))
+                .flat_map(|f| f.get_metric())
+                .flat_map(|m| m.get_label())
+                .filter(|l| l.get_name() == "env")
+                .count()
+        };
+        assert_eq!(env_labels("target_info"), 1);
+        assert!(env_labels("requests_total") > 0);
+    }
+
+    #[test]
+    fn test_builder_without_resource_detection() {
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_default_resource_detectors(false)
+            .with_resource_detectors(vec![Box::new(opentelemetry_sdk::resource::TelemetryResourceDetector)])
+            .with_resource_detection_timeout(std::time::Duration::from_millis(100))
+            .build();
+        let _app = Router::new().merge(metrics.routes::<()>()).layer(metrics);
+    }
+
+    #[test]
+    fn test_layer_meter() {
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let jobs = metrics.meter().u64_counter("jobs.processed").init();
+        jobs.add(1, &[KeyValue::new("queue", "default")]);
+    }
+
+    #[test]
+    fn test_app_metrics_in_state() {
+        #[derive(Clone)]
+        struct AppState {
+            metrics: crate::AppMetrics,
+        }
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_instrument_prefix("myapp".to_string())
+            .build();
+        let state = AppState {
+            metrics: metrics.app_metrics(),
+        };
+        let _app: Router = Router::new()
+            .route("/", get(handler))
+            .layer(metrics)
+            .with_state(state);
+
+        async fn handler(State(state): State<AppState>) -> &'static str {
+            state.metrics.counter("jobs.processed").init().add(1, &[]);
+            "<h1>Hello, World!</h1>"
+        }
+    }
+
+    #[test]
+    fn test_layer_registry() {
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let counter = prometheus::IntCounter::new("custom_collector_total", "A custom collector").unwrap();
+        metrics.registry().unwrap().register(Box::new(counter.clone())).unwrap();
+        counter.inc();
+
+        let families = metrics.registry().unwrap().gather();
+        assert!(families.iter().any(|f| f.get_name().ends_with("custom_collector_total")));
+    }
+
+    #[test]
+    fn test_builder_with_admin_routes() {
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_service_name("admin-test".to_string())
+            .build();
+        let _app = Router::new()
+            .merge(metrics.admin_routes::<()>())
+            .route("/", get(handler))
+            .layer(metrics);
+
+        async fn handler() -> &'static str {
+            "<h1>Hello, World!</h1>"
+        }
+    }
+
+    #[test]
+    fn test_uptime_metrics() {
+        let metrics = HttpMetricsLayerBuilder::new().with_uptime_metrics(true).build();
+        assert!(metrics.metric_state().metric.process_start_time.is_some());
+        assert!(metrics.metric_state().metric.process_uptime.is_some());
+
+        let families = metrics.registry().unwrap().gather();
+        assert!(families.iter().any(|f| f.get_name().ends_with("process_uptime_seconds")));
+    }
+
+    #[tokio::test]
+    async fn test_scrape_metrics() {
+        let metrics = HttpMetricsLayerBuilder::new().with_scrape_metrics(true).build();
+        let _ = crate::exporter_request_handler(State(metrics.metric_state()), Default::default()).await;
+        let body = crate::exporter_request_handler(State(metrics.metric_state()), Default::default()).await;
+        let body = axum::body::to_bytes(body.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("metrics_scrape_duration_seconds"));
+    }
+
+    #[tokio::test]
+    async fn test_exporter_handler_without_request() {
+        use axum::response::IntoResponse;
+
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let response = crate::exporter_handler(State(metrics.metric_state())).await.into_response();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let content_type = response.headers().get(axum::http::header::CONTENT_TYPE).unwrap();
+        assert!(content_type.to_str().unwrap().starts_with("text/plain"));
+
+        let metrics = HttpMetricsLayerBuilder::new().with_metrics_auth_token("secret".to_string()).build();
+        let response = crate::exporter_handler(State(metrics.metric_state())).await.into_response();
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_native_duration_histogram() {
+        let builder = HttpMetricsLayerBuilder::new().with_duration_histogram(crate::DurationHistogram::ClassicAndNative {
+            max_size: 160,
+            max_scale: 20,
+        });
+        assert_eq!(builder.duration_histogram_views().len(), 2);
+        let _metrics = builder.build();
+    }
+
+    #[tokio::test]
+    async fn test_conditional_get() {
+        let metrics = HttpMetricsLayerBuilder::new().with_conditional_get(true).build();
+        let response = crate::exporter_request_handler(State(metrics.metric_state()), Default::default()).await;
+        let etag = response.headers().get(axum::http::header::ETAG).unwrap().clone();
+
+        let request = axum::http::Request::builder()
+            .header(axum::http::header::IF_NONE_MATCH, etag)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = crate::exporter_request_handler(State(metrics.metric_state()), request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_allowed_networks() {
+        use axum::extract::ConnectInfo;
+        use axum::http::StatusCode;
+        use std::net::SocketAddr;
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_metrics_allowed_networks(vec!["10.0.0.0/8".parse().unwrap()])
+            .with_trusted_proxies(vec!["192.168.0.1/32".parse().unwrap()])
+            .build();
+        let request = |peer: &str, forwarded_for: Option<&str>| {
+            let mut request = axum::http::Request::builder();
+            if let Some(forwarded_for) = forwarded_for {
+                request = request.header("X-Forwarded-For", forwarded_for);
+            }
+            let mut request = request.body(axum::body::Body::empty()).unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+            request
+        };
+        let cases = [
+            (request("10.1.2.3:4000", None), StatusCode::OK),
+            (request("192.0.2.1:4000", None), StatusCode::FORBIDDEN),
+            // only the trusted proxy can forward for an allowed client
+            (request("192.0.2.1:4000", Some("10.1.2.3")), StatusCode::FORBIDDEN),
+            (request("192.168.0.1:4000", Some("10.1.2.3")), StatusCode::OK),
+            (Default::default(), StatusCode::FORBIDDEN),
+        ];
+        for (request, status) in cases {
+            let response = crate::exporter_request_handler(State(metrics.metric_state()), request).await;
+            assert_eq!(response.status(), status);
         }
     }
-}
 
-impl HttpMetricsLayerBuilder {
-    pub fn new() -> Self {
-        HttpMetricsLayerBuilder::default()
+    #[tokio::test]
+    async fn test_metrics_authorizer() {
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_metrics_authorizer(|req| {
+                let token = req.headers().get("x-scrape-token").cloned();
+                async move { token.is_some_and(|token| token == "secret") }
+            })
+            .build();
+        let request = axum::http::Request::builder()
+            .header("x-scrape-token", "secret")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = crate::exporter_request_handler(State(metrics.metric_state()), request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        let response = crate::exporter_request_handler(State(metrics.metric_state()), Default::default()).await;
+        assert_eq!(response.status(), axum::http::StatusCode::FORBIDDEN);
     }
 
-    pub fn with_service_name(mut self, service_name: String) -> Self {
-        self.service_name = Some(service_name);
-        self
+    #[test]
+    fn test_router_with_metrics() {
+        use crate::RouterMetricsExt;
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_path("/internal/metrics".to_string())
+            .build();
+        let _app: Router = Router::new().route("/", get(handler)).with_metrics(metrics);
+
+        async fn handler() -> &'static str {
+            "<h1>Hello, World!</h1>"
+        }
     }
 
-    pub fn with_service_version(mut self, service_version: String) -> Self {
-        self.service_version = Some(service_version);
-        self
+    #[tokio::test]
+    async fn test_layer_applied_twice() {
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        // each layer wraps the request body
+        type Body = crate::RequestBody<crate::RequestBody<axum::body::Body>>;
+        let service = metrics.layer(metrics.layer(tower::service_fn(|_req: axum::http::Request<Body>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        })));
+        let request = axum::http::Request::builder()
+            .uri("/hello")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        let families = metrics.registry().unwrap().gather();
+        let requests = families.iter().find(|f| f.get_name() == "requests_total").unwrap();
+        assert_eq!(requests.get_metric()[0].get_counter().get_value(), 1.0);
     }
 
-    pub fn with_prefix(mut self, prefix: String) -> Self {
-        self.prefix = Some(prefix);
-        self
+    #[tokio::test]
+    async fn test_route_extractor_without_router() {
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_route_extractor(crate::RouteExtractor::new(|uri, _| {
+                uri.path().starts_with("/users/").then(|| "/users/{id}".to_string())
+            }))
+            .build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        }));
+        let request = axum::http::Request::builder()
+            .uri("/users/42")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        service.oneshot(request).await.unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        let requests = families.iter().find(|f| f.get_name() == "requests_total").unwrap();
+        let route = requests.get_metric()[0]
+            .get_label()
+            .iter()
+            .find(|l| l.get_name() == "http_route")
+            .unwrap();
+        assert_eq!(route.get_value(), "/users/{id}");
     }
 
-    pub fn with_path(mut self, path: String) -> Self {
-        self.path = path;
-        self
+    #[tokio::test]
+    async fn test_grpc_metrics() {
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new().with_grpc_metrics(true).build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            let response = axum::http::Response::builder()
+                .header("content-type", "application/grpc")
+                .header("grpc-status", "5")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            Ok::<_, std::convert::Infallible>(response)
+        }));
+        let request = axum::http::Request::builder()
+            .uri("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        service.oneshot(request).await.unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        let duration = families
+            .iter()
+            .find(|f| f.get_name() == "rpc_server_duration_milliseconds")
+            .unwrap();
+        let labels = duration.get_metric()[0].get_label();
+        let label = |name: &str| labels.iter().find(|l| l.get_name() == name).unwrap().get_value();
+        assert_eq!(label("rpc_service"), "helloworld.Greeter");
+        assert_eq!(label("rpc_method"), "SayHello");
+        assert_eq!(label("rpc_grpc_status_code"), "5");
+        assert!(!families.iter().any(|f| f.get_name() == "requests_total"));
     }
 
-    pub fn with_labels(mut self, labels: HashMap<String, String>) -> Self {
-        self.labels = Some(labels);
-        self
+    #[tokio::test]
+    async fn test_graphql_metrics() {
+        use crate::{GraphQlOperation, GraphQlOperationType};
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new().with_graphql_metrics(true).build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            let mut response = axum::http::Response::new(axum::body::Body::empty());
+            response.extensions_mut().insert(GraphQlOperation {
+                name: Some("GetUser".to_string()),
+                operation_type: GraphQlOperationType::Query,
+                errors: 2,
+            });
+            Ok::<_, std::convert::Infallible>(response)
+        }));
+        let request = axum::http::Request::builder()
+            .uri("/graphql")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        service.oneshot(request).await.unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        let errors = families.iter().find(|f| f.get_name() == "graphql_errors_total").unwrap();
+        let metric = &errors.get_metric()[0];
+        assert_eq!(metric.get_counter().get_value(), 2.0);
+        assert!(metric
+            .get_label()
+            .iter()
+            .any(|l| l.get_name() == "graphql_operation_name" && l.get_value() == "GetUser"));
     }
 
-    pub fn with_skipper(mut self, skipper: PathSkipper) -> Self {
-        self.skipper = skipper;
-        self
+    #[tokio::test]
+    async fn test_sse_metrics() {
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new().with_sse_metrics(true).build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            let response = axum::http::Response::builder()
+                .header("content-type", "text/event-stream")
+                .body(axum::body::Body::from("data: a\n\nevent: b\r\ndata: c\r\n\r\n"))
+                .unwrap();
+            Ok::<_, std::convert::Infallible>(response)
+        }));
+        let request = axum::http::Request::builder()
+            .uri("/events")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        axum::body::to_bytes(axum::body::Body::new(response.into_body()), usize::MAX)
+            .await
+            .unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        let value = |name: &str| families.iter().find(|f| f.get_name() == name).unwrap().get_metric()[0].clone();
+        assert_eq!(value("sse_server_events_total").get_counter().get_value(), 2.0);
+        assert_eq!(value("sse_server_active_streams").get_gauge().get_value(), 0.0);
+        assert_eq!(value("sse_server_stream_duration_seconds").get_histogram().get_sample_count(), 1);
     }
 
-    pub fn with_exporter(mut self, exporter: String) -> Self {
-        self.exporter = Some(exporter);
-        self
+    #[tokio::test]
+    async fn test_throughput_metrics() {
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new().with_throughput_metrics(true).build();
+        let service = metrics.layer(tower::service_fn(|req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            let body = axum::body::to_bytes(axum::body::Body::new(req.into_body()), usize::MAX)
+                .await
+                .unwrap();
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::from(body.repeat(2))))
+        }));
+        let request = axum::http::Request::builder()
+            .uri("/upload")
+            .body(axum::body::Body::from("hello"))
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        axum::body::to_bytes(axum::body::Body::new(response.into_body()), usize::MAX)
+            .await
+            .unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        let value = |name: &str| {
+            let family = families.iter().find(|f| f.get_name() == name).unwrap();
+            family.get_metric()[0].get_counter().get_value()
+        };
+        assert_eq!(value("http_server_request_body_bytes_total"), 5.0);
+        assert_eq!(value("http_server_response_body_bytes_total"), 10.0);
     }
 
-    pub fn build(self) -> HttpMetricsLayer {
-        let mut resource = vec![];
+    #[tokio::test]
+    async fn test_client_layer() {
+        use tower::{Layer, ServiceExt};
 
-        let ns = env::var("INSTANCE_NAMESPACE").unwrap_or_default();
-        if !ns.is_empty() {
-            resource.push(KeyValue::new(SERVICE_NAMESPACE, ns.clone()));
-        }
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let client = metrics.client_layer().layer(tower::service_fn(|_req: axum::http::Request<axum::body::Body>| async {
+            let response = axum::http::Response::builder()
+                .status(503)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            Ok::<_, std::convert::Infallible>(response)
+        }));
+        let request = axum::http::Request::builder()
+            .uri("https://api.example.com/v1/users")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        client.oneshot(request).await.unwrap();
 
-        let instance_ip = env::var("INSTANCE_IP").unwrap_or_default();
-        if !instance_ip.is_empty() {
-            resource.push(KeyValue::new(SERVICE_INSTANCE, instance_ip));
-        }
+        let families = metrics.registry().unwrap().gather();
+        let duration = families
+            .iter()
+            .find(|f| f.get_name() == "http_client_request_duration_seconds")
+            .unwrap();
+        let labels = duration.get_metric()[0].get_label();
+        let label = |name: &str| labels.iter().find(|l| l.get_name() == name).unwrap().get_value();
+        assert_eq!(label("server_address"), "api.example.com");
+        assert_eq!(label("server_port"), "443");
+        assert_eq!(label("http_response_status_code"), "503");
+    }
 
-        if let Some(service_name) = self.service_name.clone() {
-            // `foo.ns`
-            if !ns.is_empty() && !service_name.starts_with(format!("{}.", &ns).as_str()) {
-                resource.push(KeyValue::new(SERVICE_NAME, format!("{}.{}", service_name, &ns)));
-            } else {
-                resource.push(KeyValue::new(SERVICE_NAME, service_name));
-            }
+    #[tokio::test]
+    async fn test_upstream_duration() {
+        use crate::UpstreamDuration;
+        use std::time::Duration;
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            let mut response = axum::http::Response::new(axum::body::Body::empty());
+            response
+                .extensions_mut()
+                .insert(UpstreamDuration(Duration::from_millis(20)));
+            Ok::<_, std::convert::Infallible>(response)
+        }));
+        let request = axum::http::Request::builder()
+            .uri("/proxy")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        service.oneshot(request).await.unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        let upstream = families
+            .iter()
+            .find(|f| f.get_name() == "http_server_upstream_duration_seconds")
+            .unwrap();
+        let histogram = upstream.get_metric()[0].get_histogram();
+        assert_eq!(histogram.get_sample_count(), 1);
+        assert!((histogram.get_sample_sum() - 0.02).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_registries() {
+        use crate::TenantExtractor;
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_tenant_registries(TenantExtractor::from_header("x-tenant-id"), 1)
+            .build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        }));
+        for tenant in ["acme", "globex"] {
+            let request = axum::http::Request::builder()
+                .uri("/orders")
+                .header("x-tenant-id", tenant)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            service.clone().oneshot(request).await.unwrap();
         }
-        if let Some(service_version) = self.service_version.clone() {
-            resource.push(KeyValue::new(SERVICE_VERSION, service_version));
+
+        let scrape = |tenant: &str| {
+            let request = axum::http::Request::builder()
+                .uri(format!("/metrics/{}", tenant))
+                .body(axum::body::Body::empty())
+                .unwrap();
+            metrics.routes::<()>().oneshot(request)
+        };
+        let response = scrape("acme").await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("requests_total"));
+        // past max_tenants, only recorded in the main registry
+        assert_eq!(scrape("globex").await.unwrap().status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_attribute() {
+        use crate::{TenantExtractor, OTHER_TENANT_LABEL};
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_tenant_attribute(TenantExtractor::from_header("x-tenant-id"), 1)
+            .build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        }));
+        for tenant in ["acme", "globex", "initech"] {
+            let request = axum::http::Request::builder()
+                .uri("/orders")
+                .header("x-tenant-id", tenant)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            service.clone().oneshot(request).await.unwrap();
         }
 
-        let res = Resource::from_detectors(
-            Duration::from_secs(6),
-            vec![
-                // set service.name from env OTEL_SERVICE_NAME > env OTEL_RESOURCE_ATTRIBUTES > option_env! CARGO_BIN_NAME > unknown_service
-                Box::new(SdkProvidedResourceDetector),
-                // detect res from env OTEL_RESOURCE_ATTRIBUTES (resources string like key1=value1,key2=value2,...)
-                Box::new(EnvResourceDetector::new()),
-                // set telemetry.sdk.{name, language, version}
-                Box::new(TelemetryResourceDetector),
-            ],
-        );
+        let families = metrics.registry().unwrap().gather();
+        let requests = families.iter().find(|f| f.get_name() == "requests_total").unwrap();
+        let mut tenants: Vec<(String, f64)> = requests
+            .get_metric()
+            .iter()
+            .map(|m| {
+                let tenant = m.get_label().iter().find(|l| l.get_name() == "tenant_id").unwrap();
+                (tenant.get_value().to_string(), m.get_counter().get_value())
+            })
+            .collect();
+        tenants.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(tenants, vec![("acme".to_string(), 1.0), (OTHER_TENANT_LABEL.to_string(), 2.0)]);
+    }
 
-        let res = if !resource.is_empty() {
-            res.merge(&mut Resource::new(resource))
-        } else {
-            res
-        };
+    #[tokio::test]
+    async fn test_api_key_attribute() {
+        use crate::CredentialExtractor;
+        use tower::{Layer, ServiceExt};
 
-        let mut registry = None;
-        let mut builder = SdkMeterProvider::builder().with_resource(res);
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_api_key_attribute(CredentialExtractor::bearer_token(), "salt")
+            .build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        }));
+        let request = axum::http::Request::builder()
+            .uri("/orders")
+            .header("authorization", "Bearer secret-key")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        service.oneshot(request).await.unwrap();
 
-        // exporter
+        let families = metrics.registry().unwrap().gather();
+        let requests = families.iter().find(|f| f.get_name() == "requests_total").unwrap();
+        let labels = requests.get_metric()[0].get_label();
+        let hash = labels.iter().find(|l| l.get_name() == "api_key_hash").unwrap().get_value();
+        assert_eq!(hash, crate::api_key::hash("salt", "secret-key"));
+        assert!(labels.iter().all(|l| !l.get_value().contains("secret")));
+    }
 
-        if self.exporter == Some("otlp".to_string()) {
-            builder = builder.with_reader(self.build_otlp());
-        } else {
-            let (reg, exporter) = self.build_prometheus();
-            registry = Some(reg);
-            builder = builder.with_reader(exporter);
+    #[tokio::test]
+    async fn test_attribute_redactor() {
+        use crate::{redact_pii, UnmatchedRoute};
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_unmatched_route(UnmatchedRoute::RawPath { max_paths: 10 })
+            .with_attribute_redactor(redact_pii)
+            .build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        }));
+        let request = axum::http::Request::builder()
+            .uri("/users/jane@example.com")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        service.oneshot(request).await.unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        let requests = families.iter().find(|f| f.get_name() == "requests_total").unwrap();
+        let route = requests.get_metric()[0]
+            .get_label()
+            .iter()
+            .find(|l| l.get_name() == "http_route")
+            .unwrap();
+        assert_eq!(route.get_value(), "/users/{email}");
+    }
+
+    #[tokio::test]
+    async fn test_attribute_denylist() {
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_attribute_denylist(["server.address", "url.scheme"])
+            .build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        }));
+        let request = axum::http::Request::builder()
+            .uri("/hello")
+            .header("host", "pod-1234:8080")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        service.oneshot(request).await.unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        for name in ["requests_total", "http_server_active_requests"] {
+            let family = families.iter().find(|f| f.get_name() == name).unwrap();
+            let labels = family.get_metric()[0].get_label();
+            assert!(labels.iter().all(|l| l.get_name() != "server_address" && l.get_name() != "url_scheme"));
+            assert!(labels.iter().any(|l| l.get_name() == "http_request_method"));
         }
+    }
 
-        let provider = builder.build();
+    #[tokio::test]
+    async fn test_tls_connection() {
+        use crate::TlsConnection;
+        use tower::{Layer, ServiceExt};
 
-        // init the global meter provider
-        global::set_meter_provider(provider.clone());
-        // this must called after the global meter provider has ben initialized
-        // let meter = global::meter("axum-app");
-        // let meter = provider.meter("axum-app");
-        let meter = provider.versioned_meter(
-            env!("CARGO_PKG_NAME"),
-            Some(env!("CARGO_PKG_VERSION")),
-            Some("https://opentelemetry.io/schema/1.0.0"),
-            None,
-        );
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        }));
+        for tls in [false, true] {
+            let mut request = axum::http::Request::builder()
+                .uri("/hello")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            if tls {
+                request.extensions_mut().insert(TlsConnection);
+            }
+            service.clone().oneshot(request).await.unwrap();
+        }
 
-        // requests_total
-        let requests_total = meter
-            .u64_counter("requests")
-            .with_description("How many HTTP requests processed, partitioned by status code and HTTP method.")
-            .init();
+        let families = metrics.registry().unwrap().gather();
+        let active = families.iter().find(|f| f.get_name() == "http_server_active_requests").unwrap();
+        let mut schemes: Vec<&str> = active
+            .get_metric()
+            .iter()
+            .map(|m| m.get_label().iter().find(|l| l.get_name() == "url_scheme").unwrap().get_value())
+            .collect();
+        schemes.sort();
+        assert_eq!(schemes, vec!["http", "https"]);
 
-        // request_duration_seconds
-        let req_duration = meter
-            .f64_histogram("http.server.request.duration")
-            .with_unit("s")
-            .with_description("The HTTP request latencies in seconds.")
-            .with_boundaries(HTTP_REQ_DURATION_HISTOGRAM_BUCKETS.to_vec())
-            .init();
+        let builder = HttpMetricsLayerBuilder::new().with_tls(true);
+        assert!(builder.is_tls);
+    }
 
-        // request_size_bytes
-        let req_size = meter
-            .u64_histogram("http.server.request.size")
-            .with_unit("By")
-            .with_description("The HTTP request sizes in bytes.")
-            .with_boundaries(HTTP_REQ_SIZE_HISTOGRAM_BUCKETS.to_vec())
-            .init();
+    #[tokio::test]
+    async fn test_network_peer() {
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+        use tower::{Layer, ServiceExt};
 
-        let res_size = meter
-            .u64_histogram("http.server.response.size")
-            .with_unit("By")
-            .with_description("The HTTP reponse sizes in bytes.")
-            .with_boundaries(HTTP_REQ_SIZE_HISTOGRAM_BUCKETS.to_vec())
-            .init();
+        let metrics = HttpMetricsLayerBuilder::new().with_network_peer(true).build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        }));
+        let mut request = axum::http::Request::builder()
+            .uri("/hello")
+            .header("x-forwarded-for", "203.0.113.7")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo("127.0.0.1:15001".parse::<SocketAddr>().unwrap()));
+        service.oneshot(request).await.unwrap();
 
-        // no u64_up_down_counter because up_down_counter maybe < 0 since it allow negative values
-        let req_active = meter
-            .i64_up_down_counter("http.server.active_requests")
-            .with_description("The number of active HTTP requests.")
-            .init();
+        let families = metrics.registry().unwrap().gather();
+        let requests = families.iter().find(|f| f.get_name() == "requests_total").unwrap();
+        let labels = requests.get_metric()[0].get_label();
+        let label = |name: &str| labels.iter().find(|l| l.get_name() == name).unwrap().get_value();
+        assert_eq!(label("network_peer_address"), "127.0.0.1");
+        assert_eq!(label("network_peer_port"), "15001");
+    }
 
-        let meter_state = MetricState {
-            registry,
-            metric: Metric {
-                requests_total,
-                req_duration,
-                req_size,
-                res_size,
-                req_active,
-            },
-            skipper: self.skipper,
-            is_tls: self.is_tls,
-        };
+    #[test]
+    fn test_split_host_port() {
+        use crate::split_host_port;
 
-        HttpMetricsLayer {
-            state: meter_state,
-            path: self.path,
+        assert_eq!(split_host_port("example.com"), ("example.com", None));
+        assert_eq!(split_host_port("example.com:8080"), ("example.com", Some(8080)));
+        assert_eq!(split_host_port("[::1]:3000"), ("::1", Some(3000)));
+        assert_eq!(split_host_port("[::1]"), ("::1", None));
+        assert_eq!(split_host_port("::1"), ("::1", None));
+    }
+
+    #[tokio::test]
+    async fn test_server_port() {
+        for (enabled, address, port) in [(false, "example.com:8080", None), (true, "example.com", Some("8080"))] {
+            let metrics = HttpMetricsLayerBuilder::new().with_server_port(enabled).build();
+            let request = axum::http::Request::builder()
+                .uri("/users")
+                .header("host", "example.com:8080")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let families = gather(&metrics, request).await;
+            let requests = families.iter().find(|f| f.get_name() == "requests_total").unwrap();
+            let labels = requests.get_metric()[0].get_label();
+            let label = |name: &str| labels.iter().find(|l| l.get_name() == name).map(|l| l.get_value());
+            assert_eq!(label("server_address"), Some(address));
+            assert_eq!(label("server_port"), port);
         }
     }
 
-    fn build_prometheus(&self) -> (Registry, impl opentelemetry_sdk::metrics::reader::MetricReader) {
-        let registry = if let Some(prefix) = self.prefix.clone() {
-            Registry::new_custom(Some(prefix), self.labels.clone()).expect("create prometheus registry")
-        } else {
-            Registry::new()
-        };
-        // init prometheus exporter
-        let exporter = opentelemetry_prometheus::exporter()
-            .with_registry(registry.clone())
-            .build()
-            .unwrap();
-        (registry, exporter)
+    #[test]
+    fn test_normalize_route() {
+        use crate::normalize_route;
+
+        assert_eq!(normalize_route("/users/:id"), "/users/:id");
+        assert_eq!(normalize_route("/users/{id}"), "/users/:id");
+        assert_eq!(normalize_route("/files/{*path}"), "/files/*path");
+        assert_eq!(normalize_route("/api/*__private__axum_nest_tail_param"), "/api/*");
+        assert_eq!(normalize_route("/api/{*__private__axum_nest_tail_param}"), "/api/*");
     }
 
-    /// init otlp metrics exporter
-    /// read from env var:
-    /// OTEL_EXPORTER_OTLP_METRICS_ENDPOINT, OTEL_EXPORTER_OTLP_METRICS_HEADERS,OTEL_EXPORTER_OTLP_METRICS_TIMEOUT
-    /// ref https://github.com/tokio-rs/tracing-opentelemetry/blob/5e3354ec24debcfbf856bfd1eb7022459dca1e6a/examples/opentelemetry-otlp.rs#L32
-    fn build_otlp(&self) -> impl opentelemetry_sdk::metrics::reader::MetricReader {
-        let protocol = match env::var("OTEL_EXPORTER_OTLP_METRICS_PROTOCOL")
-            .ok()
-            .or(env::var("OTEL_EXPORTER_OTLP_PROTOCOL").ok())
-        {
-            Some(val) => val,
-            None => "http/protobuf".to_string(),
-        };
+    #[tokio::test]
+    async fn test_nested_matched_path() {
+        use crate::propagate_matched_path;
+        use axum::middleware;
+        use tower::ServiceExt;
 
-        let exporter = if protocol.starts_with("http") {
-            opentelemetry_otlp::new_exporter()
-                .http()
-                .build_metrics_exporter(
-                    Box::new(DefaultTemporalitySelector::new()),
-                )
-                .unwrap()
-        } else {
-            opentelemetry_otlp::new_exporter()
-                .tonic()
-                .build_metrics_exporter(
-                    Box::new(DefaultTemporalitySelector::new()),
-                )
-                .unwrap()
-        };
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let inner = Router::new()
+            .route("/users/:id", get(|| async { "user" }))
+            .route_layer(middleware::from_fn(propagate_matched_path));
+        let app: Router = Router::new().nest_service("/api", inner).layer(metrics.clone());
+        let request = axum::http::Request::builder()
+            .uri("/api/users/42")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap();
 
-        let reader = PeriodicReader::builder(exporter, opentelemetry_sdk::runtime::Tokio)
-            .with_interval(std::time::Duration::from_secs(30))
+        let families = metrics.registry().unwrap().gather();
+        let requests = families.iter().find(|f| f.get_name() == "requests_total").unwrap();
+        let route = requests.get_metric()[0]
+            .get_label()
+            .iter()
+            .find(|l| l.get_name() == "http_route")
+            .unwrap();
+        assert_eq!(route.get_value(), "/api/users/:id");
+    }
+
+    #[tokio::test]
+    async fn test_nested_matched_path_skipper_and_preflight() {
+        use crate::{propagate_matched_path, CorsPreflight, PathSkipper, CORS_PREFLIGHT_ROUTE_LABEL};
+        use axum::middleware;
+        use axum::routing::post;
+        use tower::ServiceExt;
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_skipper(PathSkipper::new(|path| path.starts_with("/metrics") || path == "/api/health"))
+            .with_cors_preflight(CorsPreflight::Label)
+            .with_throughput_metrics(true)
             .build();
-        reader
+        let inner = Router::new()
+            .route("/health", get(|| async { "ok" }))
+            .route("/users/:id", post(|body: String| async move { body }).options(|| async { "" }))
+            .route_layer(middleware::from_fn(propagate_matched_path));
+        let app: Router = Router::new().nest_service("/api", inner).layer(metrics.clone());
+        let requests = [
+            axum::http::Request::builder()
+                .uri("/api/health")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+            axum::http::Request::builder()
+                .method("OPTIONS")
+                .uri("/api/users/42")
+                .header("origin", "https://example.com")
+                .header("access-control-request-method", "POST")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+            axum::http::Request::builder()
+                .method("POST")
+                .uri("/api/users/42")
+                .body(axum::body::Body::from("hello"))
+                .unwrap(),
+        ];
+        for request in requests {
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let families = metrics.registry().unwrap().gather();
+        let routes = |name: &str| {
+            let family = families.iter().find(|f| f.get_name() == name).unwrap();
+            let mut routes: Vec<String> = family
+                .get_metric()
+                .iter()
+                .map(|m| {
+                    let labels = m.get_label();
+                    labels.iter().find(|l| l.get_name() == "http_route").unwrap().get_value().to_string()
+                })
+                .collect();
+            routes.sort();
+            routes
+        };
+        assert_eq!(routes("requests_total"), ["/api/users/:id", CORS_PREFLIGHT_ROUTE_LABEL]);
+        assert_eq!(routes("http_server_request_body_bytes_total"), ["/api/users/:id"]);
     }
-}
 
-impl<S> Layer<S> for HttpMetricsLayer {
-    type Service = HttpMetrics<S>;
+    #[tokio::test]
+    async fn test_route_extractor_with_templates() {
+        use crate::RouteExtractor;
+        use tower::ServiceExt;
 
-    fn layer(&self, service: S) -> Self::Service {
-        HttpMetrics {
-            state: self.state.clone(),
-            service,
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_route_extractor(RouteExtractor::with_templates(["/legacy/users/{id}"]))
+            .build();
+        let app: Router = Router::new()
+            .route("/hello", get(|| async { "hello" }))
+            .fallback(|| async { "legacy" })
+            .layer(metrics.clone());
+        for uri in ["/hello", "/legacy/users/42"] {
+            let request = axum::http::Request::builder().uri(uri).body(axum::body::Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
         }
-    }
-}
 
-pin_project! {
-    /// Response future for [`HttpMetrics`] Service.
-    pub struct ResponseFuture<F> {
-        #[pin]
-        inner: F,
-        start: Instant,
-        state: MetricState,
-        path: String,
-        method: String,
-        url_scheme: String,
-        host: String,
-        req_size: u64,
+        let families = metrics.registry().unwrap().gather();
+        let requests = families.iter().find(|f| f.get_name() == "requests_total").unwrap();
+        let mut routes: Vec<&str> = requests
+            .get_metric()
+            .iter()
+            .map(|m| m.get_label().iter().find(|l| l.get_name() == "http_route").unwrap().get_value())
+            .collect();
+        routes.sort();
+        assert_eq!(routes, vec!["/hello", "/legacy/users/:id"]);
     }
-}
 
-impl<S, R, ResBody> Service<Request<R>> for HttpMetrics<S>
-where
-    S: Service<Request<R>, Response = Response<ResBody>>,
-    ResBody: httpBody,
-{
-    type Response = S::Response;
-    type Error = S::Error;
-    type Future = ResponseFuture<S::Future>;
+    #[test]
+    fn test_query_keys() {
+        use crate::query_keys;
 
-    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.service.poll_ready(cx)
+        assert_eq!(query_keys("offset=20&limit=10").as_deref(), Some("limit,offset"));
+        assert_eq!(query_keys("tag=a&tag=b&q").as_deref(), Some("q,tag"));
+        assert_eq!(query_keys("&="), None);
+        assert_eq!(query_keys(""), None);
     }
 
-    fn call(&mut self, req: Request<R>) -> Self::Future {
-        let url_scheme = if self.state.is_tls {
-            "https".to_string()
-        } else {
-            (|| {
-                if let Some(scheme) = req.headers().get("X-Forwarded-Proto") {
-                    return scheme.to_str().unwrap().to_string();
-                } else if let Some(scheme) = req.headers().get("X-Forwarded-Protocol") {
-                    return scheme.to_str().unwrap().to_string();
-                }
-                if req.headers().get("X-Forwarded-Ssl").is_some().to_string() == "on" {
-                    return "https".to_string();
-                }
-                if let Some(scheme) = req.headers().get("X-Url-Scheme") {
-                    return scheme.to_str().unwrap().to_string();
-                } else {
-                    return "http".to_string();
-                }
-            })()
-        };
-        // ref https://github.com/open-telemetry/semantic-conventions/blob/main/docs/http/http-metrics.md#metric-httpserveractive_requests
-        // http.request.method and url.scheme is required
-        self.state.metric.req_active.add(
-            1,
-            &[
-                KeyValue::new("http.request.method", req.method().as_str().to_string()),
-                KeyValue::new("url.scheme", url_scheme.clone()),
-            ],
-        );
-        let start = Instant::now();
-        let method = req.method().clone().to_string();
-        let path = if let Some(matched_path) = req.extensions().get::<MatchedPath>() {
-            matched_path.as_str().to_owned()
-        } else {
-            "".to_owned()
-        };
+    #[tokio::test]
+    async fn test_cors_preflight() {
+        use crate::{CorsPreflight, CORS_PREFLIGHT_ROUTE_LABEL};
+        use tower::ServiceExt;
 
-        let host = req
-            .headers()
-            .get(http::header::HOST)
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("unknown")
-            .to_string();
+        for (mode, expected) in [(CorsPreflight::Skip, None), (CorsPreflight::Label, Some(CORS_PREFLIGHT_ROUTE_LABEL))] {
+            let metrics = HttpMetricsLayerBuilder::new().with_cors_preflight(mode).build();
+            let app: Router = Router::new()
+                .route("/users", get(|| async { "users" }).options(|| async { "" }))
+                .layer(metrics.clone());
+            let request = axum::http::Request::builder()
+                .method("OPTIONS")
+                .uri("/users")
+                .header("origin", "https://example.com")
+                .header("access-control-request-method", "POST")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            app.oneshot(request).await.unwrap();
 
-        let req_size = compute_approximate_request_size(&req);
+            let families = metrics.registry().unwrap().gather();
+            let route = families.iter().find(|f| f.get_name() == "requests_total").map(|requests| {
+                let labels = requests.get_metric()[0].get_label();
+                labels.iter().find(|l| l.get_name() == "http_route").unwrap().get_value().to_string()
+            });
+            assert_eq!(route.as_deref(), expected);
+        }
+    }
 
-        // for scheme, see github.com/labstack/echo/v4@v4.11.1/context.go
-        // we can not use req.uri().scheme() since for non-absolute uri, it is always None
+    #[tokio::test]
+    async fn test_head_requests() {
+        use crate::HeadRequests;
+        use tower::ServiceExt;
 
-        ResponseFuture {
-            inner: self.service.call(req),
-            start,
-            method,
-            path,
-            host,
-            req_size: req_size as u64,
-            state: self.state.clone(),
-            url_scheme,
+        for (mode, expected) in [(HeadRequests::Record, Some("HEAD")), (HeadRequests::FoldIntoGet, Some("GET")), (HeadRequests::Skip, None)] {
+            let metrics = HttpMetricsLayerBuilder::new().with_head_requests(mode).build();
+            let app: Router = Router::new().route("/users", get(|| async { "users" })).layer(metrics.clone());
+            let request = axum::http::Request::builder()
+                .method("HEAD")
+                .uri("/users")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            app.oneshot(request).await.unwrap();
+
+            let families = metrics.registry().unwrap().gather();
+            let method = families.iter().find(|f| f.get_name() == "requests_total").map(|requests| {
+                let labels = requests.get_metric()[0].get_label();
+                labels.iter().find(|l| l.get_name() == "http_request_method").unwrap().get_value().to_string()
+            });
+            assert_eq!(method.as_deref(), expected);
         }
     }
-}
 
-/// compute approximate request size
-///
-/// the implimentation refs [labstack/echo-contrib 's prometheus middleware](https://github.com/labstack/echo-contrib/blob/db8911a1af7abb6bdafbd999adada548fd9c0849/echoprometheus/prometheus.go#L329)
-fn compute_approximate_request_size<T>(req: &Request<T>) -> usize {
-    let mut s = 0;
-    s += req.uri().path().len();
-    s += req.method().as_str().len();
+    #[test]
+    fn test_path_bucket() {
+        use crate::path_bucket;
 
-    req.headers().iter().for_each(|(k, v)| {
-        s += k.as_str().len();
-        s += v.as_bytes().len();
-    });
+        assert_eq!(path_bucket("/wp-admin/setup.php"), "/wp-admin");
+        assert_eq!(path_bucket("/favicon.ico"), "/favicon.ico");
+        assert_eq!(path_bucket("/"), "/");
+        assert_eq!(path_bucket(""), "/");
+    }
 
-    s += req.uri().host().map(|h| h.len()).unwrap_or(0);
+    #[test]
+    fn test_referer_class() {
+        use crate::referer_class;
 
-    s += req
-        .headers()
-        .get(http::header::CONTENT_LENGTH)
-        .map(|v| v.to_str().unwrap().parse::<usize>().unwrap_or(0))
-        .unwrap_or(0);
-    s
-}
+        assert_eq!(referer_class(None, "example.com"), "none");
+        assert_eq!(referer_class(Some("https://example.com/users"), "example.com"), "internal");
+        assert_eq!(referer_class(Some("https://search.example/?q=users"), "example.com"), "external");
+        assert_eq!(referer_class(Some("not a uri"), "example.com"), "external");
+    }
 
-impl<F, B: httpBody, E> Future for ResponseFuture<F>
-where
-    F: Future<Output = Result<Response<B>, E>>,
-{
-    type Output = Result<Response<B>, E>;
+    #[tokio::test]
+    async fn test_redirect_and_not_found_metrics() {
+        use crate::NotFoundLabel;
+        use tower::ServiceExt;
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.project();
-        let response = ready!(this.inner.poll(cx))?;
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_redirect_metrics(true)
+            .with_not_found_metrics(NotFoundLabel::PathBucket { max_buckets: 1 })
+            .build();
+        let app: Router = Router::new()
+            .route("/old", get(|| async { axum::response::Redirect::permanent("/new") }))
+            .layer(metrics.clone());
+        for uri in ["/old", "/wp-admin/setup.php", "/wp-admin/install.php", "/.env"] {
+            let request = axum::http::Request::builder().uri(uri).body(axum::body::Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
 
-        this.state.metric.req_active.add(
-            -1,
-            &[
-                KeyValue::new("http.request.method", this.method.clone()),
-                KeyValue::new("url.scheme", this.url_scheme.clone()),
-            ],
-        );
+        let families = metrics.registry().unwrap().gather();
+        let redirects = families.iter().find(|f| f.get_name() == "http_server_redirects_total").unwrap();
+        let metric = &redirects.get_metric()[0];
+        assert_eq!(metric.get_counter().get_value(), 1.0);
+        assert!(metric
+            .get_label()
+            .iter()
+            .any(|l| l.get_name() == "http_response_status_code" && l.get_value() == "308"));
 
-        if (this.state.skipper.skip)(this.path.as_str()) {
-            return Poll::Ready(Ok(response));
-        }
+        let not_found = families.iter().find(|f| f.get_name() == "http_server_not_found_total").unwrap();
+        let mut buckets: Vec<(String, f64)> = not_found
+            .get_metric()
+            .iter()
+            .map(|m| {
+                let bucket = m.get_label().iter().find(|l| l.get_name() == "url_path_bucket").unwrap();
+                (bucket.get_value().to_string(), m.get_counter().get_value())
+            })
+            .collect();
+        buckets.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(buckets, vec![("/wp-admin".to_string(), 2.0), ("__overflow__".to_string(), 1.0)]);
+    }
 
-        let latency = this.start.elapsed().as_secs_f64();
-        let status = response.status().as_u16().to_string();
+    #[tokio::test]
+    async fn test_split_sizes() {
+        use tower::{Layer, ServiceExt};
 
-        let res_size = response.body().size_hint().upper().unwrap_or(0);
+        let metrics = HttpMetricsLayerBuilder::new().with_split_sizes(true).build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::from("hello")))
+        }));
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri("/users")
+            .header("cookie", "a".repeat(100))
+            .header("content-length", "3")
+            .body(axum::body::Body::from("abc"))
+            .unwrap();
+        service.oneshot(request).await.unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        let sum = |name: &str| {
+            let family = families.iter().find(|f| f.get_name() == name).unwrap();
+            family.get_metric()[0].get_histogram().get_sample_sum()
+        };
+        assert_eq!(sum("http_server_request_header_size_bytes"), (4 + 6 + 106 + 15) as f64);
+        assert_eq!(sum("http_server_request_body_size_bytes"), 3.0);
+        assert_eq!(sum("http_server_response_body_size_bytes"), 5.0);
+        assert_eq!(
+            sum("http_server_request_size_bytes"),
+            sum("http_server_request_header_size_bytes") + sum("http_server_request_body_size_bytes")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compression_metrics() {
+        use crate::UncompressedSizeLayer;
+        use tower::{Layer, ServiceExt};
 
-        let labels = [
-            KeyValue {
-                key: Key::from("http.request.method"),
-                value: Value::from(this.method.clone()),
+        let metrics = HttpMetricsLayerBuilder::new().with_compression_metrics(true).build();
+        let handler = UncompressedSizeLayer::new().layer(tower::service_fn(
+            |_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+                Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::from("a".repeat(1000))))
             },
-            KeyValue::new("http.route", this.path.clone()),
-            KeyValue::new("http.response.status_code", status),
-            // server.address: Name of the local HTTP server that received the request.
-            // Determined by using the first of the following that applies
-            //
-            // 1. The primary server name of the matched virtual host. MUST only include host identifier.
-            // 2. Host identifier of the request target if it's sent in absolute-form.
-            // 3. Host identifier of the Host header
-            KeyValue::new("server.address", this.host.clone()),
-        ];
+        ));
+        // stands in for a compression layer, reading the whole body and sending 100 bytes
+        let compression = tower::service_fn(move |req| {
+            let handler = handler.clone();
+            async move {
+                let response = handler.oneshot(req).await?;
+                let body = axum::body::to_bytes(axum::body::Body::new(response.into_body()), usize::MAX)
+                    .await
+                    .unwrap();
+                assert_eq!(body.len(), 1000);
+                let mut response = axum::http::Response::new(axum::body::Body::from(vec![0u8; 100]));
+                response
+                    .headers_mut()
+                    .insert(http::header::CONTENT_ENCODING, "gzip".parse().unwrap());
+                Ok::<_, std::convert::Infallible>(response)
+            }
+        });
+        let service = metrics.layer(compression);
+        let request = axum::http::Request::builder()
+            .uri("/users")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        axum::body::to_bytes(axum::body::Body::new(response.into_body()), usize::MAX)
+            .await
+            .unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        let ratio = families
+            .iter()
+            .find(|f| f.get_name().starts_with("http_server_response_compression"))
+            .unwrap();
+        let metric = &ratio.get_metric()[0];
+        assert_eq!(metric.get_histogram().get_sample_sum(), 10.0);
+        assert!(metric
+            .get_label()
+            .iter()
+            .any(|l| l.get_name() == "http_response_content_encoding" && l.get_value() == "gzip"));
+    }
 
-        this.state.metric.requests_total.add(1, &labels);
+    #[tokio::test]
+    async fn test_handler_timing() {
+        use crate::HandlerTimingLayer;
+        use std::time::Duration;
+        use tower::ServiceExt;
 
-        this.state.metric.req_size.record(*this.req_size, &labels);
+        let metrics = HttpMetricsLayerBuilder::new().with_handler_timing(true).build();
+        let app: Router = Router::new()
+            .route(
+                "/users",
+                get(|| async {
+                    std::thread::sleep(Duration::from_millis(20));
+                    "users"
+                }),
+            )
+            .route_layer(HandlerTimingLayer::new())
+            .layer(axum::middleware::from_fn(
+                |req: axum::extract::Request, next: axum::middleware::Next| async move {
+                    std::thread::sleep(Duration::from_millis(10));
+                    next.run(req).await
+                },
+            ))
+            .layer(metrics.clone());
+        let request = axum::http::Request::builder()
+            .uri("/users")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap();
 
-        this.state.metric.res_size.record(res_size, &labels);
+        let families = metrics.registry().unwrap().gather();
+        let sum = |name: &str| {
+            let family = families.iter().find(|f| f.get_name() == name).unwrap();
+            family.get_metric()[0].get_histogram().get_sample_sum()
+        };
+        assert!(sum("http_server_handler_duration_seconds") >= 0.02);
+        assert!(sum("http_server_middleware_duration_seconds") >= 0.01);
+    }
 
-        this.state.metric.req_duration.record(latency, &labels);
+    #[test]
+    fn test_parse_queue_start() {
+        use crate::parse_queue_start;
+        use std::time::{Duration, UNIX_EPOCH};
 
-        Ready(Ok(response))
+        let start = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let close = |value: &str| {
+            let parsed = parse_queue_start(value).unwrap();
+            let diff = parsed.duration_since(start).unwrap_or_else(|e| e.duration());
+            diff < Duration::from_micros(10)
+        };
+        assert!(close("t=1700000000.123"));
+        assert!(close("1700000000123"));
+        assert!(close("t=1700000000123000"));
+        assert_eq!(parse_queue_start("t=abc"), None);
+        assert_eq!(parse_queue_start("-1"), None);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::HttpMetricsLayerBuilder;
-    use axum::extract::State;
-    use axum::routing::get;
-    use axum::Router;
-    use opentelemetry::{global, Context, KeyValue};
-    use opentelemetry_sdk::metrics::SdkMeterProvider;
-    use prometheus::{Encoder, Registry, TextEncoder};
-    use std::sync::Arc;
+    #[tokio::test]
+    async fn test_throttling_metrics() {
+        use tower::{Layer, ServiceExt};
 
-    #[test]
-    fn test_prometheus_exporter() {
-        let _cx = Context::current();
+        let metrics = HttpMetricsLayerBuilder::new().with_throttling_metrics(true).build();
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            let response = axum::http::Response::builder()
+                .status(429)
+                .header("retry-after", "30")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            Ok::<_, std::convert::Infallible>(response)
+        }));
+        let request = axum::http::Request::builder()
+            .uri("/users")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        service.oneshot(request).await.unwrap();
 
-        let registry = Registry::new();
+        let families = metrics.registry().unwrap().gather();
+        let throttled = families.iter().find(|f| f.get_name() == "http_server_throttled_total").unwrap();
+        assert_eq!(throttled.get_metric()[0].get_counter().get_value(), 1.0);
+        let retry_after = families
+            .iter()
+            .find(|f| f.get_name() == "http_server_retry_after_seconds")
+            .unwrap();
+        assert_eq!(retry_after.get_metric()[0].get_histogram().get_sample_sum(), 30.0);
+    }
 
-        // init prometheus exporter
-        let exporter = opentelemetry_prometheus::exporter()
-            .with_registry(registry.clone())
-            .build()
+    #[tokio::test]
+    async fn test_route_active_requests() {
+        use tower::{Layer, Service, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new().with_route_active_requests(true).build();
+        let registry = metrics.registry().unwrap().clone();
+        let active = move || {
+            let families = registry.gather();
+            let family = families
+                .iter()
+                .find(|f| f.get_name() == "http_server_route_active_requests")
+                .unwrap();
+            let metric = &family.get_metric()[0];
+            let route = metric.get_label().iter().find(|l| l.get_name() == "http_route").unwrap();
+            (route.get_value().to_string(), metric.get_gauge().get_value())
+        };
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let rx = Arc::new(std::sync::Mutex::new(Some(rx)));
+        let mut service = metrics.layer(tower::service_fn(move |_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| {
+            let rx = rx.lock().unwrap().take();
+            async move {
+                if let Some(rx) = rx {
+                    let _ = rx.await;
+                }
+                Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+            }
+        }));
+        let request = axum::http::Request::builder()
+            .uri("/export")
+            .body(axum::body::Body::empty())
             .unwrap();
+        let future = service.ready().await.unwrap().call(request);
+        assert_eq!(active(), (crate::UNMATCHED_ROUTE_LABEL.to_string(), 1.0));
+        tx.send(()).unwrap();
+        future.await.unwrap();
+        assert_eq!(active().1, 0.0);
+    }
 
-        let provider = SdkMeterProvider::builder().with_reader(exporter).build();
+    #[tokio::test]
+    async fn test_concurrency_layer() {
+        use tower::limit::ConcurrencyLimitLayer;
+        use tower::load_shed::LoadShedLayer;
+        use tower::{Layer, Service, ServiceExt};
 
-        // init the global meter provider
-        global::set_meter_provider(provider.clone());
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let rx = Arc::new(std::sync::Mutex::new(Some(rx)));
+        let handler = tower::service_fn(move |_req: axum::http::Request<axum::body::Body>| {
+            let rx = rx.lock().unwrap().take();
+            async move {
+                if let Some(rx) = rx {
+                    let _ = rx.await;
+                }
+                Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+            }
+        });
+        let mut service = tower::ServiceBuilder::new()
+            .layer(metrics.concurrency_layer())
+            .layer(LoadShedLayer::new())
+            .layer(metrics.concurrency_layer())
+            .layer(ConcurrencyLimitLayer::new(1))
+            .service(handler);
+        let request = || axum::http::Request::builder().uri("/").body(axum::body::Body::empty()).unwrap();
 
-        let meter = global::meter("my-app");
+        let first = service.ready().await.unwrap().call(request());
+        let err = service.ready().await.unwrap().call(request()).await.unwrap_err();
+        assert!(err.is::<tower::load_shed::error::Overloaded>());
+        tx.send(()).unwrap();
+        first.await.unwrap();
 
-        // Use two instruments
-        let counter = meter.u64_counter("a.counter").with_description("Counts things").init();
-        let recorder = meter.u64_histogram("a.histogram").with_description("Records values").init();
+        let families = metrics.registry().unwrap().gather();
+        let shed = families.iter().find(|f| f.get_name() == "http_server_shed_requests_total").unwrap();
+        assert_eq!(shed.get_metric()[0].get_counter().get_value(), 1.0);
+        let wait = families
+            .iter()
+            .find(|f| f.get_name() == "http_server_concurrency_wait_seconds")
+            .unwrap();
+        assert!(wait.get_metric()[0].get_histogram().get_sample_count() >= 1);
+    }
 
-        counter.add(100, &[KeyValue::new("key", "value")]);
-        recorder.record(100, &[KeyValue::new("key", "value")]);
+    #[test]
+    fn test_shutdown() {
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        metrics.force_flush().unwrap();
+        metrics.shutdown().unwrap();
+        assert!(metrics.shutdown().is_err());
+    }
 
-        // Encode data as text or protobuf
-        let encoder = TextEncoder::new();
-        let metric_families = registry.gather();
-        let mut result = Vec::new();
-        encoder.encode(&metric_families, &mut result).expect("encode failed");
-        println!("{}", String::from_utf8(result).unwrap());
+    #[tokio::test]
+    async fn test_flush_on_shutdown() {
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        metrics.flush_on_shutdown(std::future::ready(())).await;
+        assert!(metrics.shutdown().is_err());
     }
 
     #[test]
-    fn test_builder() {
+    fn test_services_share_state() {
+        use tower::Layer;
+
         let metrics = HttpMetricsLayerBuilder::new().build();
-        let _app = Router::new()
-            // export metrics at `/metrics` endpoint
-            .merge(metrics.routes::<()>())
-            .route("/", get(handler))
-            .route("/hello", get(handler))
-            .route("/world", get(handler))
-            // add the metrics middleware
-            .layer(metrics);
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        }));
+        assert!(Arc::ptr_eq(&metrics.state, &service.state));
+        assert!(Arc::ptr_eq(&metrics.state, &service.clone().state));
+    }
 
-        async fn handler() -> &'static str {
-            "<h1>Hello, World!</h1>"
+    #[tokio::test]
+    async fn test_attribute_cache() {
+        use crate::attribute_cache::AttributeKey;
+        use tower::ServiceExt;
+
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let app: Router = Router::new().route("/users", get(|| async { "users" })).layer(metrics.clone());
+        for host in ["example.com", "example.com", "example.org"] {
+            let request = axum::http::Request::builder()
+                .uri("/users")
+                .header("host", host)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            app.clone().oneshot(request).await.unwrap();
         }
+
+        // the host is not part of the cached set
+        let key = AttributeKey {
+            method: "GET",
+            route: "/users",
+            status: 200,
+            protocol_version: None,
+        };
+        let cached = metrics.state.attribute_cache.get(&key).unwrap();
+        assert!(cached.iter().any(|kv| kv.key.as_str() == "http.route" && kv.value.as_str() == "/users"));
+        assert!(!cached.iter().any(|kv| kv.key.as_str() == "server.address"));
+
+        let families = metrics.registry().unwrap().gather();
+        let requests = families.iter().find(|f| f.get_name() == "requests_total").unwrap();
+        let mut counts = requests
+            .get_metric()
+            .iter()
+            .map(|m| {
+                let host = m.get_label().iter().find(|l| l.get_name() == "server_address").unwrap();
+                (host.get_value().to_string(), m.get_counter().get_value())
+            })
+            .collect::<Vec<_>>();
+        counts.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(counts, [("example.com".to_string(), 2.0), ("example.org".to_string(), 1.0)]);
     }
 
     #[test]
-    fn test_builder_with_state_router() {
-        #[derive(Clone)]
-        struct AppState {}
+    fn test_method_and_status_str() {
+        use crate::{method_str, status_str};
+        use std::borrow::Cow;
+
+        assert!(matches!(method_str(&http::Method::GET), Cow::Borrowed("GET")));
+        let purge = http::Method::from_bytes(b"PURGE").unwrap();
+        assert_eq!(method_str(&purge), "PURGE");
+        assert_eq!(status_str(200), "200");
+        assert_eq!(status_str(599), "599");
+        assert!(std::ptr::eq(status_str(404), status_str(404)));
+    }
+
+    #[tokio::test]
+    async fn test_size_metrics_disabled() {
+        use tower::{Layer, ServiceExt};
+
+        let metrics = HttpMetricsLayerBuilder::new().with_size_metrics(false).build();
+        assert!(!metrics.state.records_request_size());
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::from("hello")))
+        }));
+        let request = axum::http::Request::builder().uri("/users").body(axum::body::Body::empty()).unwrap();
+        service.oneshot(request).await.unwrap();
+
+        let families = metrics.registry().unwrap().gather();
+        assert!(families.iter().any(|f| f.get_name() == "http_server_request_duration_seconds"));
+        assert!(!families.iter().any(|f| f.get_name() == "http_server_request_size_bytes"));
+        assert!(!families.iter().any(|f| f.get_name() == "http_server_response_size_bytes"));
+    }
+
+    #[tokio::test]
+    async fn test_default_registry() {
+        let scrape = |metrics: &HttpMetricsLayer| {
+            let state = metrics.metric_state();
+            async move {
+                let body = crate::exporter_request_handler(State(state), Default::default()).await;
+                let body = axum::body::to_bytes(body.into_body(), usize::MAX).await.unwrap();
+                String::from_utf8_lossy(&body).into_owned()
+            }
+        };
+
+        let shared = prometheus::IntCounter::new("test_default_registry_shared", "shared").unwrap();
+        let global = prometheus::IntCounter::new("test_default_registry_global", "global").unwrap();
+        prometheus::default_registry().register(Box::new(shared.clone())).unwrap();
+        prometheus::default_registry().register(Box::new(global.clone())).unwrap();
 
         let metrics = HttpMetricsLayerBuilder::new().build();
-        let _app: Router<AppState> = Router::new()
-            // export metrics at `/metrics` endpoint
-            .merge(metrics.routes::<AppState>())
-            .route("/", get(handler))
-            .route("/hello", get(handler))
-            .route("/world", get(handler))
-            // add the metrics middleware
-            .layer(metrics)
-            .with_state(AppState {});
+        metrics.registry().unwrap().register(Box::new(shared.clone())).unwrap();
+        let body = scrape(&metrics).await;
+        assert_eq!(body.matches("# TYPE test_default_registry_shared counter").count(), 1);
+        assert!(body.contains("test_default_registry_global"));
 
-        async fn handler(_state: State<AppState>) -> &'static str {
-            "<h1>Hello, World!</h1>"
-        }
+        let metrics = HttpMetricsLayerBuilder::new().with_default_registry(false).build();
+        let body = scrape(&metrics).await;
+        assert!(!body.contains("test_default_registry_shared"));
+        assert!(!body.contains("test_default_registry_global"));
+    }
+
+    #[tokio::test]
+    async fn test_encode_error() {
+        let response = crate::scrape_error(prometheus::Error::Msg("no metrics".to_string()).into());
+        assert_eq!(response.status(), axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            response.headers().get(axum::http::header::CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"failed to encode the metrics: no metrics");
+    }
+
+    #[tokio::test]
+    async fn test_exporter_content_type() {
+        let metrics = HttpMetricsLayerBuilder::new().build();
+        let response = crate::exporter_request_handler(State(metrics.metric_state()), Default::default()).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(
+            response.headers().get(axum::http::header::CONTENT_TYPE).unwrap(),
+            "text/plain; version=0.0.4; charset=utf-8"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_scrape_size() {
+        let metrics = HttpMetricsLayerBuilder::new().with_max_scrape_size(1).build();
+        let response = crate::exporter_request_handler(State(metrics.metric_state()), Default::default()).await;
+        assert_eq!(response.status(), axum::http::StatusCode::SERVICE_UNAVAILABLE);
+
+        let families = metrics.registry().unwrap().gather();
+        let rejected = families
+            .iter()
+            .find(|f| f.get_name() == "metrics_scrape_rejected_total")
+            .unwrap();
+        let metric = &rejected.get_metric()[0];
+        assert_eq!(metric.get_counter().get_value(), 1.0);
+        assert!(metric
+            .get_label()
+            .iter()
+            .any(|l| l.get_name() == "reason" && l.get_value() == "size"));
+    }
+
+    #[tokio::test]
+    async fn test_scrape_rate_limit() {
+        let metrics = HttpMetricsLayerBuilder::new().with_scrape_rate_limit(1).build();
+        let response = crate::exporter_request_handler(State(metrics.metric_state()), Default::default()).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let response = crate::exporter_request_handler(State(metrics.metric_state()), Default::default()).await;
+        assert_eq!(response.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(axum::http::header::RETRY_AFTER).unwrap(), "1");
+    }
+
+    #[tokio::test]
+    async fn test_without_target_and_scope_info() {
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_target_info(false)
+            .with_scope_info(false)
+            .build();
+        let request = axum::http::Request::builder().uri("/users").body(axum::body::Body::empty()).unwrap();
+        let families = gather(&metrics, request).await;
+        assert!(families.iter().any(|f| f.get_name() == "http_server_request_duration_seconds"));
+        assert!(!families.iter().any(|f| f.get_name() == "target_info" || f.get_name() == "otel_scope_info"));
+        assert!(families
+            .iter()
+            .flat_map(|f| f.get_metric())
+            .flat_map(|m| m.get_label())
+            .all(|l| !l.get_name().starts_with("otel_scope_")));
+    }
+
+    /// the metric families recorded by `metrics` for a `request` to an empty service
+    async fn gather(
+        metrics: &crate::HttpMetricsLayer,
+        request: axum::http::Request<axum::body::Body>,
+    ) -> Vec<prometheus::proto::MetricFamily> {
+        use tower::{Layer, ServiceExt};
+
+        let service = metrics.layer(tower::service_fn(|_req: axum::http::Request<crate::RequestBody<axum::body::Body>>| async {
+            Ok::<_, std::convert::Infallible>(axum::http::Response::new(axum::body::Body::empty()))
+        }));
+        service.oneshot(request).await.unwrap();
+        metrics.registry().unwrap().gather()
     }
 
     #[test]
-    fn test_builder_with_arced_skipper() {
-        #[derive(Clone)]
-        struct AppState {}
+    fn test_attribute_naming() {
+        use crate::AttributeNaming;
 
+        let mut attributes = vec![
+            KeyValue::new("http.request.method", "GET"),
+            KeyValue::new("http.route", "/users"),
+            KeyValue::new("region", "eu"),
+        ];
+        AttributeNaming::dotted().apply(&mut attributes);
+        assert_eq!(attributes[0].key.as_str(), "http.request.method");
+
+        AttributeNaming::underscored()
+            .with_rename("http.route", "route")
+            .apply(&mut attributes);
+        let keys = attributes.iter().map(|kv| kv.key.as_str()).collect::<Vec<_>>();
+        assert_eq!(keys, ["http_request_method", "route", "region"]);
+    }
+
+    #[tokio::test]
+    async fn test_attribute_naming_rename() {
         let metrics = HttpMetricsLayerBuilder::new()
-            .with_skipper(crate::PathSkipper::new_with_fn(Arc::new(|_: &str| true)))
+            .with_attribute_naming(crate::AttributeNaming::dotted().with_rename("http.route", "route"))
             .build();
-        let _app: Router<AppState> = Router::new()
-            // export metrics at `/metrics` endpoint
-            .merge(metrics.routes::<AppState>())
-            .route("/", get(handler))
-            // add the metrics middleware
-            .layer(metrics)
-            .with_state(AppState {});
+        let request = axum::http::Request::builder().uri("/users").body(axum::body::Body::empty()).unwrap();
+        let families = gather(&metrics, request).await;
+        let family = families
+            .iter()
+            .find(|f| f.get_name() == "http_server_request_duration_seconds")
+            .unwrap();
+        let labels = family.get_metric()[0].get_label();
+        assert!(labels.iter().any(|l| l.get_name() == "route"));
+        assert!(!labels.iter().any(|l| l.get_name() == "http_route"));
+    }
 
-        async fn handler(_state: State<AppState>) -> &'static str {
-            "<h1>Hello, World!</h1>"
-        }
+    #[tokio::test]
+    async fn test_metrics_handle() {
+        use crate::{PathSkipper, UnmatchedRoute};
+        use std::time::Duration;
+
+        let metrics = HttpMetricsLayerBuilder::new()
+            .with_unmatched_route(UnmatchedRoute::RawPath { max_paths: 10 })
+            .build();
+        let handle = metrics.handle();
+        let request = |uri: &str| axum::http::Request::builder().uri(uri).body(axum::body::Body::empty()).unwrap();
+        let routes = |families: Vec<prometheus::proto::MetricFamily>| {
+            let family = families
+                .iter()
+                .find(|f| f.get_name() == "http_server_request_duration_seconds")
+                .unwrap();
+            family
+                .get_metric()
+                .iter()
+                .flat_map(|m| m.get_label())
+                .filter(|l| l.get_name() == "http_route")
+                .map(|l| l.get_value().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        handle.set_skipper(PathSkipper::new(|path| path == "/noisy"));
+        gather(&metrics, request("/noisy")).await;
+        assert_eq!(routes(gather(&metrics, request("/quiet")).await), ["/quiet"]);
+
+        handle.set_sample_rate(0.5);
+        assert_eq!(metrics.sample_rate().get(), 0.5);
+
+        handle.set_slow_request_threshold(Duration::from_millis(10));
+        assert_eq!(handle.slow_request_thresholds(), [("slow".to_string(), Duration::from_millis(10))]);
+
+        // no threshold was set on the builder, the counter still records the ones set by the handle
+        handle.set_slow_request_threshold(Duration::ZERO);
+        let families = gather(&metrics, request("/slow")).await;
+        assert!(families.iter().any(|f| f.get_name() == "http_server_slow_requests_total"));
+    }
+
+    #[test]
+    fn test_builder_from_vars() {
+        use std::collections::HashMap;
+
+        let vars = HashMap::from([
+            ("AXUM_OTEL_METRICS_EXPORTER", "prometheus"),
+            ("AXUM_OTEL_METRICS_PATH", "/internal/metrics"),
+            ("AXUM_OTEL_METRICS_PREFIX", "myapp"),
+            ("AXUM_OTEL_METRICS_SKIP_PATHS", "/health*, /static/**"),
+            ("AXUM_OTEL_METRICS_DURATION_BUCKETS", "0.01, 0.1,1,10"),
+            ("AXUM_OTEL_METRICS_CONST_LABELS", "region=eu-west-1, cluster = blue"),
+        ]);
+        let builder = HttpMetricsLayerBuilder::from_vars(|name| vars.get(name).map(|v| v.to_string()));
+        assert_eq!(builder.exporter.as_deref(), Some("prometheus"));
+        assert_eq!(builder.path, "/internal/metrics");
+        assert_eq!(builder.prefix.as_deref(), Some("myapp"));
+        assert!((builder.skipper.skip)("/healthz"));
+        assert!((builder.skipper.skip)("/static/css/app.css"));
+        assert!((builder.skipper.skip)("/metrics"));
+        assert!((builder.skipper.skip)("/internal/metrics"));
+        assert!(!(builder.skipper.skip)("/api/users"));
+        assert_eq!(builder.duration_buckets, Some(vec![0.01, 0.1, 1.0, 10.0]));
+        assert_eq!(
+            builder.const_attributes,
+            vec![KeyValue::new("region", "eu-west-1"), KeyValue::new("cluster", "blue")]
+        );
+
+        // the invalid values are ignored
+        let vars = HashMap::from([
+            ("AXUM_OTEL_METRICS_EXPORTER", "zipkin"),
+            ("AXUM_OTEL_METRICS_DURATION_BUCKETS", "1,0.1"),
+            ("AXUM_OTEL_METRICS_CONST_LABELS", "region"),
+        ]);
+        let builder = HttpMetricsLayerBuilder::from_vars(|name| vars.get(name).map(|v| v.to_string()));
+        assert_eq!(builder.exporter.as_deref(), Some("prometheus"));
+        assert_eq!(builder.duration_buckets, None);
+        assert!(builder.const_attributes.is_empty());
+
+        // the configured metrics endpoint is skipped without `AXUM_OTEL_METRICS_SKIP_PATHS`
+        let vars = HashMap::from([("AXUM_OTEL_METRICS_PATH", "/internal/metrics")]);
+        let builder = HttpMetricsLayerBuilder::from_vars(|name| vars.get(name).map(|v| v.to_string()));
+        assert!((builder.skipper.skip)("/internal/metrics"));
+        assert!(!(builder.skipper.skip)("/healthz"));
     }
 }
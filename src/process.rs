@@ -0,0 +1,110 @@
+//! process level metrics read from procfs, the equivalent of the prometheus `process_*` collector
+//! but registered on the meter so they reach every exporter
+
+use opentelemetry::metrics::{Meter, ObservableCounter, ObservableGauge};
+use opentelemetry::KeyValue;
+use procfs::process::Process;
+
+/// the process instruments, the callbacks stay registered as long as the instruments are alive
+pub(crate) struct ProcessMetrics {
+    _cpu: ObservableCounter<f64>,
+    _resident_memory: ObservableGauge<u64>,
+    _virtual_memory: ObservableGauge<u64>,
+    _open_fds: ObservableGauge<u64>,
+    _threads: ObservableGauge<u64>,
+    _start_time: ObservableGauge<f64>,
+}
+
+impl ProcessMetrics {
+    /// register the process instruments on `meter`, `name` applies the instrument prefix
+    pub(crate) fn register(meter: &Meter, name: impl Fn(&str) -> String, attributes: Vec<KeyValue>) -> Self {
+        let attrs = attributes.clone();
+        let cpu = meter
+            .f64_observable_counter(name("process.cpu"))
+            .with_unit("s")
+            .with_description("Total user and system CPU time spent in seconds.")
+            .with_callback(move |observer| {
+                if let Some(stat) = stat() {
+                    let ticks = procfs::ticks_per_second() as f64;
+                    observer.observe((stat.utime + stat.stime) as f64 / ticks, &attrs);
+                }
+            })
+            .init();
+
+        let attrs = attributes.clone();
+        let resident_memory = meter
+            .u64_observable_gauge(name("process.resident_memory"))
+            .with_unit("By")
+            .with_description("Resident memory size in bytes.")
+            .with_callback(move |observer| {
+                if let Some(stat) = stat() {
+                    observer.observe(stat.rss * procfs::page_size(), &attrs);
+                }
+            })
+            .init();
+
+        let attrs = attributes.clone();
+        let virtual_memory = meter
+            .u64_observable_gauge(name("process.virtual_memory"))
+            .with_unit("By")
+            .with_description("Virtual memory size in bytes.")
+            .with_callback(move |observer| {
+                if let Some(stat) = stat() {
+                    observer.observe(stat.vsize, &attrs);
+                }
+            })
+            .init();
+
+        let attrs = attributes.clone();
+        let open_fds = meter
+            .u64_observable_gauge(name("process.open_fds"))
+            .with_description("Number of open file descriptors.")
+            .with_callback(move |observer| {
+                if let Some(count) = Process::myself().and_then(|p| p.fd_count()).ok() {
+                    observer.observe(count as u64, &attrs);
+                }
+            })
+            .init();
+
+        let attrs = attributes.clone();
+        let threads = meter
+            .u64_observable_gauge(name("process.threads"))
+            .with_description("Number of OS threads in the process.")
+            .with_callback(move |observer| {
+                if let Some(stat) = stat() {
+                    observer.observe(stat.num_threads.max(0) as u64, &attrs);
+                }
+            })
+            .init();
+
+        // the start time never changes, compute it once
+        let start_time = stat().and_then(|stat| {
+            let boot_time = procfs::boot_time_secs().ok()?;
+            Some(boot_time as f64 + stat.starttime as f64 / procfs::ticks_per_second() as f64)
+        });
+        let attrs = attributes;
+        let start_time = meter
+            .f64_observable_gauge(name("process.start_time"))
+            .with_unit("s")
+            .with_description("Start time of the process since unix epoch in seconds.")
+            .with_callback(move |observer| {
+                if let Some(start_time) = start_time {
+                    observer.observe(start_time, &attrs);
+                }
+            })
+            .init();
+
+        Self {
+            _cpu: cpu,
+            _resident_memory: resident_memory,
+            _virtual_memory: virtual_memory,
+            _open_fds: open_fds,
+            _threads: threads,
+            _start_time: start_time,
+        }
+    }
+}
+
+fn stat() -> Option<procfs::process::Stat> {
+    Process::myself().and_then(|p| p.stat()).ok()
+}
@@ -0,0 +1,60 @@
+//! a token bucket limiting the scrapes of the metrics endpoint, independent of any limit of the app routes
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// allows `per_second` scrapes per second, in bursts of up to `per_second` scrapes
+pub(crate) struct RateLimiter {
+    per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(per_second: u32) -> Self {
+        let per_second = f64::from(per_second.max(1));
+        Self {
+            per_second,
+            bucket: Mutex::new(Bucket {
+                tokens: per_second,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// take a token, `Err` with the time until the next one when the bucket is empty
+    pub(crate) fn acquire(&self) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(self.per_second);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_second))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2);
+        assert!(limiter.acquire().is_ok());
+        assert!(limiter.acquire().is_ok());
+        let wait = limiter.acquire().unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(500));
+
+        std::thread::sleep(Duration::from_millis(550));
+        assert!(limiter.acquire().is_ok());
+    }
+}
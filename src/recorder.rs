@@ -0,0 +1,73 @@
+//! the recording of the optional per-feature instruments once a response is known
+//!
+//! each feature builds its instruments from the [HttpMetricsLayerBuilder] and records them in its own module,
+//! the layer only hands every recorded response to the recorders of the enabled features.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::{Extensions, HeaderMap, StatusCode};
+use opentelemetry::metrics::Meter;
+use opentelemetry::KeyValue;
+
+use crate::handle::{SlowRequestThresholds, Swappable};
+use crate::{graphql, responses, slo, HttpMetricsLayerBuilder, MetricState};
+
+/// a response of a recorded request, with its final route
+pub(crate) struct RecordedResponse<'a> {
+    pub(crate) method: &'a Cow<'static, str>,
+    pub(crate) route: &'a Arc<str>,
+    pub(crate) status: StatusCode,
+    pub(crate) headers: &'a HeaderMap,
+    pub(crate) extensions: &'a Extensions,
+    pub(crate) elapsed: Duration,
+}
+
+impl RecordedResponse<'_> {
+    /// the `http.request.method` and `http.route` attributes, most feature instruments are labeled by them
+    pub(crate) fn route_attributes(&self) -> Vec<KeyValue> {
+        vec![
+            KeyValue::new("http.request.method", self.method.clone()),
+            KeyValue::new("http.route", self.route.clone()),
+        ]
+    }
+}
+
+/// the instruments of a feature, recorded for every response that is not skipped
+pub(crate) trait ResponseRecorder: Send + Sync {
+    /// record the instruments of the feature, `state` processes their attributes
+    fn record(&self, state: &MetricState, response: &RecordedResponse<'_>);
+
+    /// append the attributes the feature adds to the request metrics, none by default
+    fn request_attributes(&self, _response: &RecordedResponse<'_>, _attributes: &mut Vec<KeyValue>) {}
+}
+
+/// the recorders of the features enabled on `builder`
+pub(crate) fn recorders(
+    builder: &HttpMetricsLayerBuilder,
+    meter: &Meter,
+    slow_request_thresholds: &Swappable<SlowRequestThresholds>,
+) -> Vec<Box<dyn ResponseRecorder>> {
+    let mut recorders: Vec<Box<dyn ResponseRecorder>> = vec![Box::new(slo::SlowRequests::new(
+        builder,
+        meter,
+        slow_request_thresholds.clone(),
+    ))];
+    if let Some(apdex) = slo::Apdex::new(builder, meter) {
+        recorders.push(Box::new(apdex));
+    }
+    if let Some(objectives) = slo::ObjectiveMetrics::new(builder, meter) {
+        recorders.push(Box::new(objectives));
+    }
+    if let Some(graphql) = graphql::GraphQlMetrics::new(builder, meter) {
+        recorders.push(Box::new(graphql));
+    }
+    if let Some(redirects) = responses::RedirectMetrics::new(builder, meter) {
+        recorders.push(Box::new(redirects));
+    }
+    if let Some(throttling) = responses::ThrottlingMetrics::new(builder, meter) {
+        recorders.push(Box::new(throttling));
+    }
+    recorders
+}
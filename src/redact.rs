@@ -0,0 +1,69 @@
+//! scrubbing of the attribute values, so the metrics can not leak personal data or secrets
+
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use opentelemetry::Key;
+use regex::Regex;
+
+/// the replaced patterns and their placeholders, the emails first since they may contain hex runs
+fn patterns() -> &'static [(Regex, &'static str)] {
+    static PATTERNS: OnceLock<Vec<(Regex, &'static str)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [
+            (r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}", "{email}"),
+            (
+                r"[0-9A-Fa-f]{8}-[0-9A-Fa-f]{4}-[0-9A-Fa-f]{4}-[0-9A-Fa-f]{4}-[0-9A-Fa-f]{12}",
+                "{uuid}",
+            ),
+            // hex digests and opaque tokens, e.g. session ids or JWT segments
+            (r"[0-9A-Fa-f]{16,}|[A-Za-z0-9_-]{32,}", "{token}"),
+        ]
+        .into_iter()
+        .map(|(pattern, placeholder)| (Regex::new(pattern).expect("valid redaction pattern"), placeholder))
+        .collect()
+    })
+}
+
+/// A ready-made redactor for [crate::HttpMetricsLayerBuilder::with_attribute_redactor].
+///
+/// replaces the emails with `{email}`, the UUIDs with `{uuid}` and the long hex or base64url
+/// tokens with `{token}`, in the values of every attribute.
+///
+/// ```
+/// use axum_otel_metrics::redact_pii;
+/// use opentelemetry::Key;
+///
+/// let key = Key::from_static_str("http.route");
+/// assert_eq!(redact_pii(&key, "/users/jane@example.com"), "/users/{email}");
+/// ```
+pub fn redact_pii<'a>(_key: &Key, value: &'a str) -> Cow<'a, str> {
+    let mut value = Cow::Borrowed(value);
+    for (regex, placeholder) in patterns() {
+        if regex.is_match(&value) {
+            value = Cow::Owned(regex.replace_all(&value, *placeholder).into_owned());
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_pii() {
+        let key = Key::from_static_str("http.route");
+        assert_eq!(redact_pii(&key, "/users/:id"), "/users/:id");
+        assert!(matches!(redact_pii(&key, "/users/:id"), Cow::Borrowed(_)));
+        assert_eq!(
+            redact_pii(&key, "/orders/123e4567-e89b-12d3-a456-426614174000"),
+            "/orders/{uuid}"
+        );
+        assert_eq!(redact_pii(&key, "/reset/0123456789abcdef0123"), "/reset/{token}");
+        assert_eq!(
+            redact_pii(&key, "/users/jane.doe@example.com/avatar"),
+            "/users/{email}/avatar"
+        );
+    }
+}
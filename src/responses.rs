@@ -0,0 +1,108 @@
+//! counters of the redirect and throttled responses, whose request duration says little about them
+
+use std::time::{Duration, SystemTime};
+
+use http::StatusCode;
+use opentelemetry::metrics::{Counter, Histogram, Meter};
+use opentelemetry::KeyValue;
+
+use crate::recorder::{RecordedResponse, ResponseRecorder};
+use crate::{header_str, HttpInstrument, HttpMetricsLayerBuilder, MetricState};
+
+/// from a second to an hour
+const RETRY_AFTER_HISTOGRAM_BUCKETS: &[f64] = &[1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0];
+
+/// counts the 3xx responses
+pub(crate) struct RedirectMetrics {
+    redirects: Counter<u64>,
+}
+
+impl RedirectMetrics {
+    /// `None` without [HttpMetricsLayerBuilder::with_redirect_metrics]
+    pub(crate) fn new(builder: &HttpMetricsLayerBuilder, meter: &Meter) -> Option<Self> {
+        builder.redirect_metrics.then(|| Self {
+            redirects: meter
+                .u64_counter(builder.instrument_name("http.server.redirects"))
+                .with_unit(builder.unit(HttpInstrument::Redirects, ""))
+                .with_description(builder.description(HttpInstrument::Redirects, "The number of HTTP redirect responses."))
+                .init(),
+        })
+    }
+}
+
+impl ResponseRecorder for RedirectMetrics {
+    fn record(&self, state: &MetricState, response: &RecordedResponse<'_>) {
+        if response.status.is_redirection() {
+            let mut attributes = response.route_attributes();
+            attributes.push(KeyValue::new("http.response.status_code", response.status.as_u16() as i64));
+            self.redirects.add(1, &state.attributes(attributes));
+        }
+    }
+}
+
+/// counts the 429 responses and records the `Retry-After` delays
+pub(crate) struct ThrottlingMetrics {
+    throttled: Counter<u64>,
+    retry_after: Histogram<f64>,
+}
+
+impl ThrottlingMetrics {
+    /// `None` without [HttpMetricsLayerBuilder::with_throttling_metrics]
+    pub(crate) fn new(builder: &HttpMetricsLayerBuilder, meter: &Meter) -> Option<Self> {
+        builder.throttling_metrics.then(|| Self {
+            throttled: meter
+                .u64_counter(builder.instrument_name("http.server.throttled"))
+                .with_unit(builder.unit(HttpInstrument::Throttled, ""))
+                .with_description(
+                    builder.description(HttpInstrument::Throttled, "The number of HTTP too many requests responses."),
+                )
+                .init(),
+            retry_after: meter
+                .f64_histogram(builder.instrument_name("http.server.retry_after"))
+                .with_unit(builder.unit(HttpInstrument::RetryAfter, "s"))
+                .with_description(builder.description(
+                    HttpInstrument::RetryAfter,
+                    "The Retry-After delays of the HTTP responses in seconds.",
+                ))
+                .with_boundaries(RETRY_AFTER_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+        })
+    }
+}
+
+impl ResponseRecorder for ThrottlingMetrics {
+    fn record(&self, state: &MetricState, response: &RecordedResponse<'_>) {
+        if response.status == StatusCode::TOO_MANY_REQUESTS {
+            self.throttled.add(1, &state.attributes(response.route_attributes()));
+        }
+        if let Some(delay) = header_str(response.headers, "Retry-After").and_then(parse_retry_after) {
+            let mut attributes = response.route_attributes();
+            attributes.push(KeyValue::new("http.response.status_code", response.status.as_u16() as i64));
+            self.retry_after.record(delay.as_secs_f64(), &state.attributes(attributes));
+        }
+    }
+}
+
+/// the delay of a `Retry-After` header value, either in seconds or an HTTP date, a date in the past is no delay
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(60));
+        assert!(parse_retry_after(&date).unwrap() > Duration::from_secs(50));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
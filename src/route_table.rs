@@ -0,0 +1,96 @@
+//! matching of raw paths against a user supplied table of route templates
+
+/// a segment of a route template
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    /// `:id` or `{id}`, any single non-empty segment
+    Param,
+    /// `*rest` or `{*rest}`, the remainder of the path
+    CatchAll,
+}
+
+/// the route templates, the most specific match wins
+#[derive(Debug)]
+pub(crate) struct RouteTable {
+    routes: Vec<(String, Vec<Segment>)>,
+}
+
+impl RouteTable {
+    pub(crate) fn new(templates: impl IntoIterator<Item = String>) -> Self {
+        let routes = templates
+            .into_iter()
+            .map(|template| {
+                let segments = template.trim_start_matches('/').split('/').map(parse_segment).collect();
+                (template, segments)
+            })
+            .collect();
+        Self { routes }
+    }
+
+    /// returns the template matching `path` with the most literal segments, a catch-all loses the ties
+    pub(crate) fn find(&self, path: &str) -> Option<&str> {
+        let parts: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        self.routes
+            .iter()
+            .filter(|(_, segments)| matches(segments, &parts))
+            .max_by_key(|(_, segments)| {
+                let literals = segments.iter().filter(|s| matches!(s, Segment::Literal(_))).count();
+                let catch_all = segments.last() == Some(&Segment::CatchAll);
+                (literals, !catch_all, segments.len())
+            })
+            .map(|(template, _)| template.as_str())
+    }
+}
+
+fn parse_segment(segment: &str) -> Segment {
+    let param = segment
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .or_else(|| segment.strip_prefix(':').map(|_| segment));
+    match param {
+        Some(name) if name.starts_with('*') => Segment::CatchAll,
+        Some(_) => Segment::Param,
+        None if segment.starts_with('*') => Segment::CatchAll,
+        None => Segment::Literal(segment.to_string()),
+    }
+}
+
+fn matches(segments: &[Segment], parts: &[&str]) -> bool {
+    let mut parts = parts.iter();
+    for segment in segments {
+        match segment {
+            Segment::CatchAll => return true,
+            Segment::Param => match parts.next() {
+                Some(part) if !part.is_empty() => {}
+                _ => return false,
+            },
+            Segment::Literal(literal) => match parts.next() {
+                Some(part) if part == literal => {}
+                _ => return false,
+            },
+        }
+    }
+    parts.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let table = RouteTable::new(
+            ["/users/:id", "/users/me", "/files/{*path}", "/", "/proxy/*rest"]
+                .into_iter()
+                .map(String::from),
+        );
+        assert_eq!(table.find("/users/42"), Some("/users/:id"));
+        assert_eq!(table.find("/users/me"), Some("/users/me"));
+        assert_eq!(table.find("/users/42/posts"), None);
+        assert_eq!(table.find("/files/a/b/c"), Some("/files/{*path}"));
+        assert_eq!(table.find("/proxy/upstream/x"), Some("/proxy/*rest"));
+        assert_eq!(table.find("/"), Some("/"));
+        assert_eq!(table.find("/unknown"), None);
+    }
+}
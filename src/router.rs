@@ -0,0 +1,64 @@
+//! wiring the metrics endpoint and the metrics middleware into an axum [Router] at once
+
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Router;
+
+use crate::HttpMetricsLayer;
+
+/// Extends [Router] with [RouterMetricsExt::with_metrics].
+///
+/// ```
+/// use axum_otel_metrics::{HttpMetricsLayerBuilder, RouterMetricsExt};
+/// use axum::{routing::get, Router};
+///
+/// let metrics = HttpMetricsLayerBuilder::new().build();
+/// let app: Router = Router::new()
+///     .route("/", get(|| async { "Hello, World!" }))
+///     .with_metrics(metrics);
+/// ```
+pub trait RouterMetricsExt {
+    /// merge the metrics endpoint of `metrics` into the router and add the metrics middleware.
+    ///
+    /// the metrics endpoint is never recorded, whatever its path and the [crate::PathSkipper] of the builder.
+    /// like [Router::layer], the middleware only wraps the routes added before, so call it last.
+    /// a request is recorded once even if the same layer is applied again.
+    fn with_metrics(self, metrics: HttpMetricsLayer) -> Self;
+}
+
+impl<S> RouterMetricsExt for Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn with_metrics(self, metrics: HttpMetricsLayer) -> Self {
+        self.merge(metrics.unrecorded_routes()).layer(metrics)
+    }
+}
+
+/// A middleware copying the [MatchedPath] of a nested router into the response extensions,
+/// so the metrics layer of the outer router records the deepest route template.
+///
+/// the route is only refined when it comes from the [MatchedPath] of the outer router, e.g. not for a CORS preflight
+/// or a custom `RouteExtractor`, and the [crate::PathSkipper] is asked again about it.
+/// the [crate::RequestSkipper] only sees the route of the outer router, it runs before the request is handed over.
+/// the in-flight and active requests per route are tracked before the response, with the route of the outer router.
+///
+/// the outer router of a `nest_service` only sees the nest prefix, add it to the nested router with `route_layer`:
+///
+/// ```
+/// use axum::{middleware, routing::get, Router};
+/// use axum_otel_metrics::{propagate_matched_path, HttpMetricsLayerBuilder};
+///
+/// let users: Router = Router::new()
+///     .route("/users/:id", get(|| async { "user" }))
+///     .route_layer(middleware::from_fn(propagate_matched_path));
+/// let app: Router = Router::new()
+///     .nest_service("/api", users)
+///     .layer(HttpMetricsLayerBuilder::new().build());
+/// ```
+pub async fn propagate_matched_path(matched_path: MatchedPath, request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    response.extensions_mut().insert(matched_path);
+    response
+}
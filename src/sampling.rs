@@ -0,0 +1,86 @@
+//! deterministic sampling of the histogram recordings
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A shared handle to the sampling rate of the metrics layer.
+///
+/// The rate is the fraction of requests, between `0.0` and `1.0`,
+/// whose duration and size histograms are recorded.  `requests_total`
+/// is always counted.  The sampling is deterministic: with a rate of
+/// `0.25` exactly every fourth request is recorded.
+///
+/// Cloning the handle shares the rate, so it can be adjusted at
+/// runtime, e.g. from an admin endpoint, via [SampleRate::set].
+#[derive(Clone, Debug)]
+pub struct SampleRate {
+    /// the rate as `f64` bits
+    rate: Arc<AtomicU64>,
+    /// number of requests seen so far
+    seen: Arc<AtomicU64>,
+}
+
+impl SampleRate {
+    pub(crate) fn new(rate: f64) -> Self {
+        Self {
+            rate: Arc::new(AtomicU64::new(clamp_rate(rate).to_bits())),
+            seen: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// returns the current sampling rate
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.rate.load(Ordering::Relaxed))
+    }
+
+    /// set the sampling rate, values outside of `0.0..=1.0` are clamped
+    pub fn set(&self, rate: f64) {
+        self.rate.store(clamp_rate(rate).to_bits(), Ordering::Relaxed);
+    }
+
+    /// returns whether the current request should be recorded
+    pub(crate) fn sample(&self) -> bool {
+        let rate = self.get();
+        if rate >= 1.0 {
+            return true;
+        }
+        if rate <= 0.0 {
+            return false;
+        }
+        let n = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * rate).floor() > (n * rate).floor()
+    }
+}
+
+impl Default for SampleRate {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+fn clamp_rate(rate: f64) -> f64 {
+    if rate.is_nan() {
+        1.0
+    } else {
+        rate.clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_rate() {
+        let rate = SampleRate::new(0.25);
+        let sampled = (0..100).filter(|_| rate.sample()).count();
+        assert_eq!(sampled, 25);
+
+        rate.set(2.0);
+        assert_eq!(rate.get(), 1.0);
+        assert!((0..10).all(|_| rate.sample()));
+
+        rate.set(0.0);
+        assert!(!(0..10).any(|_| rate.sample()));
+    }
+}
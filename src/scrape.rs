@@ -0,0 +1,166 @@
+//! self-metrics of the prometheus metrics endpoint, to detect slow or failing scrapes of the service itself
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use opentelemetry::metrics::{Counter, Gauge, Histogram, Meter};
+use opentelemetry::KeyValue;
+
+/// the scrape durations are expected to be well below the request durations
+const SCRAPE_DURATION_HISTOGRAM_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+const SCRAPE_SIZE_HISTOGRAM_BUCKETS: &[f64] = &[
+    1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0,
+];
+
+/// the scrape instruments, recorded by the metrics endpoint after each scrape
+///
+/// the measurements of a scrape are exported by the next one.
+#[derive(Clone)]
+pub(crate) struct ScrapeMetrics {
+    duration: Histogram<f64>,
+    size: Histogram<u64>,
+    families: Gauge<u64>,
+    last_timestamp: Gauge<f64>,
+    attributes: Arc<[KeyValue]>,
+}
+
+impl ScrapeMetrics {
+    /// register the scrape instruments on `meter`, `name` applies the instrument prefix
+    pub(crate) fn register(meter: &Meter, name: impl Fn(&str) -> String, attributes: Arc<[KeyValue]>) -> Self {
+        Self {
+            duration: meter
+                .f64_histogram(name("metrics.scrape.duration"))
+                .with_unit("s")
+                .with_description("The duration of gathering and encoding the metrics in seconds.")
+                .with_boundaries(SCRAPE_DURATION_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            size: meter
+                .u64_histogram(name("metrics.scrape.size"))
+                .with_unit("By")
+                .with_description("The size of the encoded metrics payload in bytes.")
+                .with_boundaries(SCRAPE_SIZE_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            families: meter
+                .u64_gauge(name("metrics.scrape.families"))
+                .with_description("The number of metric families of the last scrape.")
+                .init(),
+            last_timestamp: meter
+                .f64_gauge(name("metrics.scrape.last_timestamp"))
+                .with_unit("s")
+                .with_description("The time of the last scrape since unix epoch in seconds.")
+                .init(),
+            attributes,
+        }
+    }
+
+    /// record a scrape started at `start` which encoded `families` metric families into `size` bytes
+    pub(crate) fn record(&self, start: Instant, size: usize, families: usize) {
+        let attributes = &self.attributes;
+        self.duration.record(start.elapsed().as_secs_f64(), attributes);
+        self.size.record(size as u64, attributes);
+        self.families.record(families as u64, attributes);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        self.last_timestamp.record(now, attributes);
+    }
+}
+
+/// why a scrape failed
+#[derive(Debug)]
+pub(crate) enum ScrapeError {
+    /// the prometheus encoder failed
+    Encode(prometheus::Error),
+    /// the scrape took longer than the timeout
+    Timeout(Duration),
+    /// the payload grew larger than the max size
+    TooLarge(usize),
+}
+
+impl From<prometheus::Error> for ScrapeError {
+    fn from(err: prometheus::Error) -> Self {
+        Self::Encode(err)
+    }
+}
+
+impl fmt::Display for ScrapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Encode(err) => write!(f, "failed to encode the metrics: {err}"),
+            Self::Timeout(timeout) => write!(f, "the metrics were not encoded within {timeout:?}"),
+            Self::TooLarge(max_size) => write!(f, "the encoded metrics are larger than {max_size} bytes"),
+        }
+    }
+}
+
+/// the time and size budget of a scrape, checked while the metric families are encoded one by one
+///
+/// the scrapes over budget are abandoned and counted by `metrics.scrape.rejected`, with a `reason` attribute.
+#[derive(Clone)]
+pub(crate) struct ScrapeGuard {
+    timeout: Option<Duration>,
+    max_size: Option<usize>,
+    rejected: Counter<u64>,
+    attributes: Arc<[KeyValue]>,
+}
+
+impl ScrapeGuard {
+    /// register the rejected scrapes counter on `meter`, `name` applies the instrument prefix
+    pub(crate) fn register(
+        meter: &Meter,
+        name: impl Fn(&str) -> String,
+        attributes: Arc<[KeyValue]>,
+        timeout: Option<Duration>,
+        max_size: Option<usize>,
+    ) -> Self {
+        Self {
+            timeout,
+            max_size,
+            rejected: meter
+                .u64_counter(name("metrics.scrape.rejected"))
+                .with_description("The number of scrapes abandoned for exceeding the scrape timeout or max size.")
+                .init(),
+            attributes,
+        }
+    }
+
+    /// check a scrape started at `start` which encoded `size` bytes so far
+    pub(crate) fn check(&self, start: Instant, size: usize) -> Result<(), ScrapeError> {
+        let err = match (self.timeout, self.max_size) {
+            (Some(timeout), _) if start.elapsed() > timeout => ScrapeError::Timeout(timeout),
+            (_, Some(max_size)) if size > max_size => ScrapeError::TooLarge(max_size),
+            _ => return Ok(()),
+        };
+        let reason = match err {
+            ScrapeError::Timeout(_) => "timeout",
+            _ => "size",
+        };
+        let mut attributes = self.attributes.to_vec();
+        attributes.push(KeyValue::new("reason", reason));
+        self.rejected.add(1, &attributes);
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrape_guard() {
+        let meter = opentelemetry::global::meter("test");
+        let guard = ScrapeGuard::register(&meter, str::to_string, Arc::new([]), Some(Duration::from_secs(60)), Some(10));
+        assert!(guard.check(Instant::now(), 10).is_ok());
+        assert!(matches!(guard.check(Instant::now(), 11), Err(ScrapeError::TooLarge(10))));
+
+        let guard = ScrapeGuard::register(&meter, str::to_string, Arc::new([]), Some(Duration::ZERO), None);
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(matches!(guard.check(start, 0), Err(ScrapeError::Timeout(_))));
+    }
+}
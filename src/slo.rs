@@ -0,0 +1,272 @@
+//! service level objective helper metrics
+//!
+//! every request matching an [Objective] is counted in `slo.requests`, and in `slo.requests.good`
+//! when it met the objective, both with the `slo.name` attribute. this gives the good/total ratio
+//! needed by multi-window burn-rate alerts without deriving it from histogram buckets.
+//!
+//! the latency targets of the `http.server.slow_requests` and `http.server.apdex` counters are recorded here too.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use http::{Method, StatusCode};
+use opentelemetry::metrics::{Counter, Meter};
+use opentelemetry::KeyValue;
+
+use crate::handle::{SlowRequestThresholds, Swappable};
+use crate::recorder::{RecordedResponse, ResponseRecorder};
+use crate::{HttpInstrument, HttpMetricsLayerBuilder, MetricState};
+
+/// A service level objective, e.g. "GET /api/** under 300ms and not 5xx".
+///
+/// ```
+/// use axum_otel_metrics::Objective;
+/// use axum::http::Method;
+/// use std::time::Duration;
+///
+/// let objective = Objective::new("api-latency")
+///     .with_methods([Method::GET])
+///     .with_routes(["/api/**"])
+///     .unwrap()
+///     .with_latency(Duration::from_millis(300));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Objective {
+    name: String,
+    methods: Option<Vec<Method>>,
+    routes: Option<GlobSet>,
+    latency: Option<Duration>,
+    server_errors_are_bad: bool,
+}
+
+impl Objective {
+    /// returns an objective matching every request, where a request is good unless it failed with a 5xx
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            methods: None,
+            routes: None,
+            latency: None,
+            server_errors_are_bad: true,
+        }
+    }
+
+    /// only match requests with one of `methods`
+    pub fn with_methods<I>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = Method>,
+    {
+        self.methods = Some(methods.into_iter().collect());
+        self
+    }
+
+    /// only match requests whose `http.route` matches one of the glob `patterns`,
+    /// with the same syntax as [crate::PathSkipper::from_globs]
+    pub fn with_routes<I, P>(mut self, patterns: I) -> Result<Self, globset::Error>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<str>,
+    {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(GlobBuilder::new(pattern.as_ref()).literal_separator(true).build()?);
+        }
+        self.routes = Some(builder.build()?);
+        Ok(self)
+    }
+
+    /// a request is only good when it is served within `latency`
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// whether a 5xx response makes a request bad, `true` by default
+    pub fn with_server_errors_are_bad(mut self, server_errors_are_bad: bool) -> Self {
+        self.server_errors_are_bad = server_errors_are_bad;
+        self
+    }
+
+    /// the name of the objective, recorded as the `slo.name` attribute
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn matches(&self, method: &str, route: &str) -> bool {
+        self.methods
+            .as_ref()
+            .map_or(true, |methods| methods.iter().any(|m| m.as_str() == method))
+            && self.routes.as_ref().map_or(true, |routes| routes.is_match(route))
+    }
+
+    pub(crate) fn is_good(&self, status: StatusCode, latency: Duration) -> bool {
+        !(self.server_errors_are_bad && status.is_server_error()) && self.latency.map_or(true, |max| latency <= max)
+    }
+}
+
+/// counts the requests slower than each of the named thresholds, replaced at runtime by the [crate::MetricsHandle]
+pub(crate) struct SlowRequests {
+    counter: Counter<u64>,
+    thresholds: Swappable<SlowRequestThresholds>,
+}
+
+impl SlowRequests {
+    /// created even without a threshold, so the thresholds set later by the [crate::MetricsHandle] are recorded,
+    /// a counter without any data point is not exported
+    pub(crate) fn new(builder: &HttpMetricsLayerBuilder, meter: &Meter, thresholds: Swappable<SlowRequestThresholds>) -> Self {
+        let counter = meter
+            .u64_counter(builder.instrument_name("http.server.slow_requests"))
+            .with_unit(builder.unit(HttpInstrument::SlowRequests, ""))
+            .with_description(builder.description(
+                HttpInstrument::SlowRequests,
+                "The number of HTTP requests slower than the configured threshold.",
+            ))
+            .init();
+        Self { counter, thresholds }
+    }
+}
+
+impl ResponseRecorder for SlowRequests {
+    fn record(&self, state: &MetricState, response: &RecordedResponse<'_>) {
+        for (name, threshold) in self.thresholds.load().iter() {
+            if response.elapsed > *threshold {
+                let mut attributes = response.route_attributes();
+                attributes.push(KeyValue::new("threshold", name.clone()));
+                self.counter.add(1, &state.attributes(attributes));
+            }
+        }
+    }
+}
+
+/// counts the requests by apdex satisfaction with the target latency `T`
+pub(crate) struct Apdex {
+    counter: Counter<u64>,
+    target: Duration,
+}
+
+impl Apdex {
+    /// `None` without [HttpMetricsLayerBuilder::with_apdex_target]
+    pub(crate) fn new(builder: &HttpMetricsLayerBuilder, meter: &Meter) -> Option<Self> {
+        let target = builder.apdex_target?;
+        let counter = meter
+            .u64_counter(builder.instrument_name("http.server.apdex"))
+            .with_unit(builder.unit(HttpInstrument::Apdex, ""))
+            .with_description(builder.description(HttpInstrument::Apdex, "The number of HTTP requests by apdex satisfaction."))
+            .init();
+        Some(Self { counter, target })
+    }
+}
+
+impl ResponseRecorder for Apdex {
+    fn record(&self, state: &MetricState, response: &RecordedResponse<'_>) {
+        let mut attributes = response.route_attributes();
+        attributes.push(KeyValue::new(
+            "apdex.result",
+            apdex_result(response.status, response.elapsed, self.target),
+        ));
+        self.counter.add(1, &state.attributes(attributes));
+    }
+}
+
+/// classify a request for the apdex score with target latency `target`
+///
+/// a request is satisfied when it is served within `target`, tolerating within 4 times `target`,
+/// and frustrated when slower or failed with a server error.
+///
+/// ref https://www.apdex.org/wp-content/uploads/2020/09/ApdexTechnicalSpecificationV11_000.pdf
+fn apdex_result(status: StatusCode, latency: Duration, target: Duration) -> &'static str {
+    if status.is_server_error() {
+        "frustrated"
+    } else if latency <= target {
+        "satisfied"
+    } else if latency <= target * 4 {
+        "tolerating"
+    } else {
+        "frustrated"
+    }
+}
+
+/// counts the requests matching each objective, and the ones that met it
+pub(crate) struct ObjectiveMetrics {
+    requests: Counter<u64>,
+    requests_good: Counter<u64>,
+    objectives: Arc<[Objective]>,
+}
+
+impl ObjectiveMetrics {
+    /// `None` without any [HttpMetricsLayerBuilder::with_slo]
+    pub(crate) fn new(builder: &HttpMetricsLayerBuilder, meter: &Meter) -> Option<Self> {
+        if builder.objectives.is_empty() {
+            return None;
+        }
+        Some(Self {
+            requests: meter
+                .u64_counter(builder.instrument_name("slo.requests"))
+                .with_unit(builder.unit(HttpInstrument::SloRequests, ""))
+                .with_description(builder.description(
+                    HttpInstrument::SloRequests,
+                    "The number of HTTP requests matching the service level objective.",
+                ))
+                .init(),
+            requests_good: meter
+                .u64_counter(builder.instrument_name("slo.requests.good"))
+                .with_unit(builder.unit(HttpInstrument::SloRequestsGood, ""))
+                .with_description(builder.description(
+                    HttpInstrument::SloRequestsGood,
+                    "The number of HTTP requests that met the service level objective.",
+                ))
+                .init(),
+            objectives: builder.objectives.clone().into(),
+        })
+    }
+}
+
+impl ResponseRecorder for ObjectiveMetrics {
+    fn record(&self, state: &MetricState, response: &RecordedResponse<'_>) {
+        for objective in self.objectives.iter() {
+            if objective.matches(response.method, response.route) {
+                let attributes = state.attributes(vec![KeyValue::new("slo.name", objective.name().to_string())]);
+                self.requests.add(1, &attributes);
+                if objective.is_good(response.status, response.elapsed) {
+                    self.requests_good.add(1, &attributes);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_objective() {
+        let objective = Objective::new("api")
+            .with_methods([Method::GET])
+            .with_routes(["/api/**"])
+            .unwrap()
+            .with_latency(Duration::from_millis(300));
+
+        assert!(objective.matches("GET", "/api/users/:id"));
+        assert!(!objective.matches("POST", "/api/users/:id"));
+        assert!(!objective.matches("GET", "/hello"));
+
+        assert!(objective.is_good(StatusCode::OK, Duration::from_millis(300)));
+        assert!(objective.is_good(StatusCode::NOT_FOUND, Duration::from_millis(10)));
+        assert!(!objective.is_good(StatusCode::OK, Duration::from_millis(301)));
+        assert!(!objective.is_good(StatusCode::SERVICE_UNAVAILABLE, Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_apdex_result() {
+        let target = Duration::from_millis(100);
+        assert_eq!(apdex_result(StatusCode::OK, Duration::from_millis(100), target), "satisfied");
+        assert_eq!(apdex_result(StatusCode::OK, Duration::from_millis(400), target), "tolerating");
+        assert_eq!(apdex_result(StatusCode::OK, Duration::from_millis(401), target), "frustrated");
+        assert_eq!(
+            apdex_result(StatusCode::INTERNAL_SERVER_ERROR, Duration::ZERO, target),
+            "frustrated"
+        );
+    }
+}
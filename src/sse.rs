@@ -0,0 +1,117 @@
+//! metrics of the Server-Sent Events streams, whose request duration only covers the time to the headers
+
+use std::time::Instant;
+
+use axum::http::HeaderMap;
+use opentelemetry::metrics::{Counter, Histogram, UpDownCounter};
+use opentelemetry::KeyValue;
+
+use crate::AppMetrics;
+
+/// the streams last much longer than requests
+const SSE_STREAM_DURATION_HISTOGRAM_BUCKETS: &[f64] =
+    &[1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 14400.0, 86400.0];
+
+/// whether the response is an event stream
+pub(crate) fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("text/event-stream"))
+}
+
+/// the SSE instruments
+#[derive(Clone)]
+pub(crate) struct SseMetrics {
+    active_streams: UpDownCounter<i64>,
+    stream_duration: Histogram<f64>,
+    events: Counter<u64>,
+}
+
+impl SseMetrics {
+    pub(crate) fn new(metrics: &AppMetrics) -> Self {
+        Self {
+            active_streams: metrics
+                .up_down_counter("sse.server.active_streams")
+                .with_description("The number of open Server-Sent Events streams.")
+                .init(),
+            stream_duration: metrics
+                .histogram("sse.server.stream.duration")
+                .with_unit("s")
+                .with_description("The Server-Sent Events stream durations in seconds.")
+                .with_boundaries(SSE_STREAM_DURATION_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            events: metrics
+                .counter("sse.server.events")
+                .with_description("The number of sent Server-Sent Events.")
+                .init(),
+        }
+    }
+
+    /// start recording a stream with `attributes`
+    pub(crate) fn start(&self, attributes: Vec<KeyValue>, start: Instant) -> SseStream {
+        self.active_streams.add(1, &attributes);
+        SseStream {
+            metrics: self.clone(),
+            attributes,
+            start,
+            line_start: true,
+        }
+    }
+}
+
+/// an event stream in flight, recorded as closed when dropped
+pub(crate) struct SseStream {
+    metrics: SseMetrics,
+    attributes: Vec<KeyValue>,
+    start: Instant,
+    /// whether the last byte sent ended a line, an empty line ends an event
+    line_start: bool,
+}
+
+impl SseStream {
+    /// count the events ended in the `chunk` sent
+    pub(crate) fn count(&mut self, chunk: &[u8]) {
+        let mut events = 0;
+        for &byte in chunk {
+            match byte {
+                // a `\r\n` line ending counts as a `\n`
+                b'\r' => {}
+                b'\n' if self.line_start => events += 1,
+                b'\n' => self.line_start = true,
+                _ => self.line_start = false,
+            }
+        }
+        if events > 0 {
+            self.metrics.events.add(events, &self.attributes);
+        }
+    }
+}
+
+impl Drop for SseStream {
+    fn drop(&mut self) {
+        self.metrics.active_streams.add(-1, &self.attributes);
+        self.metrics
+            .stream_duration
+            .record(self.start.elapsed().as_secs_f64(), &self.attributes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_event_stream() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_event_stream(&headers));
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            "text/event-stream; charset=utf-8".parse().unwrap(),
+        );
+        assert!(is_event_stream(&headers));
+        headers.insert(http::header::CONTENT_TYPE, "text/plain".parse().unwrap());
+        assert!(!is_event_stream(&headers));
+    }
+}
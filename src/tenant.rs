@@ -0,0 +1,132 @@
+//! per-tenant prometheus registries, served at `/metrics/:tenant`
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use opentelemetry::metrics::{Counter, Histogram, MeterProvider};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use prometheus::Registry;
+
+use crate::{HTTP_REQ_DURATION_HISTOGRAM_BUCKETS, HTTP_REQ_SIZE_HISTOGRAM_BUCKETS};
+
+/// the registries of the tenants seen so far, up to `max_tenants`
+pub(crate) struct TenantRegistries {
+    max_tenants: usize,
+    prefix: Option<String>,
+    tenants: RwLock<HashMap<String, Arc<TenantMetrics>>>,
+}
+
+/// the HTTP instruments of a tenant, on a provider exporting to the tenant registry only
+pub(crate) struct TenantMetrics {
+    registry: Registry,
+    /// keeps the tenant pipeline alive
+    _provider: SdkMeterProvider,
+    requests_total: Counter<u64>,
+    duration: Histogram<f64>,
+    request_size: Histogram<u64>,
+    response_size: Histogram<u64>,
+}
+
+impl TenantRegistries {
+    pub(crate) fn new(max_tenants: usize, prefix: Option<String>) -> Self {
+        Self {
+            max_tenants,
+            prefix,
+            tenants: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// returns the metrics of `tenant`, registering it if there is room left.
+    ///
+    /// the tenants past `max_tenants` are only recorded in the main registry.
+    pub(crate) fn get_or_create(&self, tenant: &str) -> Option<Arc<TenantMetrics>> {
+        if let Some(metrics) = self.tenants.read().unwrap().get(tenant) {
+            return Some(metrics.clone());
+        }
+        let mut tenants = self.tenants.write().unwrap();
+        if let Some(metrics) = tenants.get(tenant) {
+            return Some(metrics.clone());
+        }
+        if tenants.len() >= self.max_tenants {
+            return None;
+        }
+        let metrics = Arc::new(TenantMetrics::new(self.prefix.as_deref()));
+        tenants.insert(tenant.to_string(), metrics.clone());
+        Some(metrics)
+    }
+
+    /// returns the registry of `tenant`, `None` if it has not been seen yet
+    pub(crate) fn registry(&self, tenant: &str) -> Option<Registry> {
+        self.tenants
+            .read()
+            .unwrap()
+            .get(tenant)
+            .map(|metrics| metrics.registry.clone())
+    }
+}
+
+impl TenantMetrics {
+    fn new(prefix: Option<&str>) -> Self {
+        let registry = Registry::new();
+        let exporter = opentelemetry_prometheus::exporter()
+            .with_registry(registry.clone())
+            .build()
+            .expect("create tenant prometheus exporter");
+        let provider = SdkMeterProvider::builder().with_reader(exporter).build();
+        let meter = provider.meter(env!("CARGO_PKG_NAME"));
+        let name = |name: &str| match prefix {
+            Some(prefix) => format!("{}.{}", prefix, name),
+            None => name.to_string(),
+        };
+        Self {
+            requests_total: meter
+                .u64_counter(name("requests"))
+                .with_description("How many HTTP requests processed, partitioned by status code and HTTP method.")
+                .init(),
+            duration: meter
+                .f64_histogram(name("http.server.request.duration"))
+                .with_unit("s")
+                .with_description("The HTTP request latencies in seconds.")
+                .with_boundaries(HTTP_REQ_DURATION_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            request_size: meter
+                .u64_histogram(name("http.server.request.size"))
+                .with_unit("By")
+                .with_description("The HTTP request sizes in bytes.")
+                .with_boundaries(HTTP_REQ_SIZE_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            response_size: meter
+                .u64_histogram(name("http.server.response.size"))
+                .with_unit("By")
+                .with_description("The HTTP response sizes in bytes.")
+                .with_boundaries(HTTP_REQ_SIZE_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            registry,
+            _provider: provider,
+        }
+    }
+
+    /// record a request of the tenant
+    pub(crate) fn record(&self, attributes: &[KeyValue], latency: f64, request_size: u64, response_size: u64) {
+        self.requests_total.add(1, attributes);
+        self.duration.record(latency, attributes);
+        self.request_size.record(request_size, attributes);
+        self.response_size.record(response_size, attributes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_tenants() {
+        let registries = TenantRegistries::new(1, None);
+        assert!(registries.get_or_create("acme").is_some());
+        assert!(registries.get_or_create("acme").is_some());
+        assert!(registries.get_or_create("globex").is_none());
+        assert!(registries.registry("acme").is_some());
+        assert!(registries.registry("globex").is_none());
+    }
+}
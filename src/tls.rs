@@ -0,0 +1,92 @@
+//! TLS handshake metrics for rustls based servers
+
+use std::io;
+use std::time::Instant;
+
+use opentelemetry::metrics::{Counter, Histogram};
+use opentelemetry::KeyValue;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::ProtocolVersion;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::AppMetrics;
+
+/// the handshake durations are much shorter than the request durations
+const TLS_HANDSHAKE_DURATION_HISTOGRAM_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// A [TlsAcceptor] wrapper recording the TLS handshakes.
+///
+/// - `tls.server.handshake.duration`: histogram of the successful handshakes,
+///   with the negotiated `tls.protocol.version`
+/// - `tls.server.handshake.failures`: counter of the failed handshakes, with the `error.type`
+///
+/// create it with [crate::HttpMetricsLayer::tls_acceptor] and use it in place of the wrapped acceptor.
+#[derive(Clone)]
+pub struct MeteredTlsAcceptor {
+    inner: TlsAcceptor,
+    handshake_duration: Histogram<f64>,
+    handshake_failures: Counter<u64>,
+}
+
+impl MeteredTlsAcceptor {
+    pub(crate) fn new(inner: TlsAcceptor, metrics: &AppMetrics) -> Self {
+        Self {
+            inner,
+            handshake_duration: metrics
+                .histogram("tls.server.handshake.duration")
+                .with_unit("s")
+                .with_description("The TLS handshake durations in seconds.")
+                .with_boundaries(TLS_HANDSHAKE_DURATION_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            handshake_failures: metrics
+                .counter("tls.server.handshake.failures")
+                .with_description("The number of failed TLS handshakes.")
+                .init(),
+        }
+    }
+
+    /// returns the wrapped acceptor
+    pub fn inner(&self) -> &TlsAcceptor {
+        &self.inner
+    }
+
+    /// perform the TLS handshake on `stream`, recording its duration or failure
+    pub async fn accept<IO>(&self, stream: IO) -> io::Result<TlsStream<IO>>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let start = Instant::now();
+        match self.inner.accept(stream).await {
+            Ok(stream) => {
+                let version = stream.get_ref().1.protocol_version().map(protocol_version);
+                let mut attributes = vec![KeyValue::new("tls.protocol.name", "tls")];
+                if let Some(version) = version {
+                    attributes.push(KeyValue::new("tls.protocol.version", version));
+                }
+                self.handshake_duration
+                    .record(start.elapsed().as_secs_f64(), &attributes);
+                Ok(stream)
+            }
+            Err(err) => {
+                self.handshake_failures
+                    .add(1, &[KeyValue::new("error.type", format!("{:?}", err.kind()))]);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// map the negotiated version to the semconv `tls.protocol.version` value
+fn protocol_version(version: ProtocolVersion) -> &'static str {
+    match version {
+        ProtocolVersion::SSLv3 => "3.0",
+        ProtocolVersion::TLSv1_0 => "1.0",
+        ProtocolVersion::TLSv1_1 => "1.1",
+        ProtocolVersion::TLSv1_2 => "1.2",
+        ProtocolVersion::TLSv1_3 => "1.3",
+        _ => "unknown",
+    }
+}
@@ -0,0 +1,49 @@
+//! the trace context of a request, used to sample the exemplars
+//!
+//! with the `tracing` feature the OTel context of the current `tracing` span is used first,
+//! so the exemplars link to the span created by e.g. `tower_http::trace::TraceLayer`.
+
+use axum::http::HeaderMap;
+use opentelemetry::global;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{SpanContext, TraceContextExt};
+use opentelemetry_http::HeaderExtractor;
+
+/// the `tracing` span the request is handled in, entered while recording the response
+#[cfg(feature = "tracing")]
+pub(crate) type RequestSpan = tracing::Span;
+
+/// the `tracing` span the request is handled in, nothing without the `tracing` feature
+#[cfg(not(feature = "tracing"))]
+pub(crate) type RequestSpan = ();
+
+#[cfg(feature = "tracing")]
+pub(crate) fn current_span() -> RequestSpan {
+    tracing::Span::current()
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn current_span() -> RequestSpan {}
+
+/// the span context of the request: the current `tracing` span with the `tracing` feature,
+/// otherwise the one propagated in `headers`, otherwise the current OTel context
+pub(crate) fn span_context(headers: &HeaderMap, span: &RequestSpan) -> SpanContext {
+    #[cfg(feature = "tracing")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let cx = span.context();
+        if cx.span().span_context().is_valid() {
+            return cx.span().span_context().clone();
+        }
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = span;
+
+    let cx = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    if cx.span().span_context().is_valid() {
+        cx.span().span_context().clone()
+    } else {
+        opentelemetry::Context::current().span().span_context().clone()
+    }
+}
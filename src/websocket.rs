@@ -0,0 +1,214 @@
+//! WebSocket connection metrics, for the requests upgraded to a WebSocket
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{ready, Sink, Stream};
+use http::HeaderMap;
+use opentelemetry::metrics::{Counter, Histogram, UpDownCounter};
+use opentelemetry::KeyValue;
+
+use crate::AppMetrics;
+
+/// WebSocket connections last much longer than requests
+const WEBSOCKET_CONNECTION_DURATION_HISTOGRAM_BUCKETS: &[f64] =
+    &[1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 14400.0, 86400.0];
+
+/// returns whether the request asks for a WebSocket upgrade
+pub(crate) fn is_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get(http::header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"))
+}
+
+/// The WebSocket instruments, handed to the WebSocket handlers as a request extension.
+///
+/// - `websocket.server.active_connections`: up/down counter of the open connections
+/// - `websocket.server.connection.duration`: histogram of the closed connection durations
+/// - `websocket.server.messages`: counter of the messages, with the `websocket.direction`
+///   (`receive` or `send`) and the `websocket.message.type`
+/// - `websocket.server.bytes`: counter of the text and binary payload bytes, with the `websocket.direction`
+///
+/// all of them carry the `http.route` of the upgrade request.
+/// the middleware inserts it in the upgrade requests when [crate::HttpMetricsLayerBuilder::with_websocket_metrics]
+/// is enabled, wrap the upgraded socket with [WebSocketMetrics::wrap] to record the connection:
+///
+/// ```
+/// use axum::extract::ws::{WebSocket, WebSocketUpgrade};
+/// use axum::response::Response;
+/// use axum::Extension;
+/// use axum_otel_metrics::WebSocketMetrics;
+///
+/// async fn ws(upgrade: WebSocketUpgrade, Extension(metrics): Extension<WebSocketMetrics>) -> Response {
+///     upgrade.on_upgrade(move |socket| async move {
+///         let mut socket = metrics.wrap(socket);
+///         while let Some(Ok(message)) = socket.recv().await {
+///             if socket.send(message).await.is_err() {
+///                 break;
+///             }
+///         }
+///     })
+/// }
+/// ```
+#[derive(Clone)]
+pub struct WebSocketMetrics {
+    active_connections: UpDownCounter<i64>,
+    connection_duration: Histogram<f64>,
+    messages: Counter<u64>,
+    bytes: Counter<u64>,
+    attributes: Vec<KeyValue>,
+}
+
+impl WebSocketMetrics {
+    pub(crate) fn new(metrics: &AppMetrics) -> Self {
+        Self {
+            active_connections: metrics
+                .up_down_counter("websocket.server.active_connections")
+                .with_description("The number of open WebSocket connections.")
+                .init(),
+            connection_duration: metrics
+                .histogram("websocket.server.connection.duration")
+                .with_unit("s")
+                .with_description("The WebSocket connection durations in seconds.")
+                .with_boundaries(WEBSOCKET_CONNECTION_DURATION_HISTOGRAM_BUCKETS.to_vec())
+                .init(),
+            messages: metrics
+                .counter("websocket.server.messages")
+                .with_description("The number of WebSocket messages.")
+                .init(),
+            bytes: metrics
+                .counter("websocket.server.bytes")
+                .with_unit("By")
+                .with_description("The WebSocket message payload bytes.")
+                .init(),
+            attributes: Vec::new(),
+        }
+    }
+
+    /// the instruments for the connections upgraded from a request with `attributes`
+    pub(crate) fn with_attributes(&self, attributes: Vec<KeyValue>) -> Self {
+        Self {
+            attributes,
+            ..self.clone()
+        }
+    }
+
+    /// wrap the upgraded `socket` to record the connection, see [MeteredWebSocket]
+    pub fn wrap(self, socket: WebSocket) -> MeteredWebSocket {
+        self.active_connections.add(1, &self.attributes);
+        MeteredWebSocket {
+            inner: socket,
+            metrics: self,
+            start: Instant::now(),
+        }
+    }
+
+    fn record(&self, direction: &'static str, message: &Message) {
+        let (message_type, len) = match message {
+            Message::Text(text) => ("text", text.len()),
+            Message::Binary(data) => ("binary", data.len()),
+            Message::Ping(_) => ("ping", 0),
+            Message::Pong(_) => ("pong", 0),
+            Message::Close(_) => ("close", 0),
+        };
+        let mut attributes = self.attributes.clone();
+        attributes.push(KeyValue::new("websocket.direction", direction));
+        if len > 0 {
+            self.bytes.add(len as u64, &attributes);
+        }
+        attributes.push(KeyValue::new("websocket.message.type", message_type));
+        self.messages.add(1, &attributes);
+    }
+}
+
+/// A [WebSocket] wrapper recording the connection and its messages, see [WebSocketMetrics].
+///
+/// it is a [Stream] and a [Sink] of [Message] like the wrapped socket,
+/// the connection is recorded as closed when the wrapper is dropped.
+pub struct MeteredWebSocket {
+    inner: WebSocket,
+    metrics: WebSocketMetrics,
+    start: Instant,
+}
+
+impl MeteredWebSocket {
+    /// receive another message, `None` once the stream has closed
+    pub async fn recv(&mut self) -> Option<Result<Message, axum::Error>> {
+        let message = self.inner.recv().await;
+        if let Some(Ok(ref message)) = message {
+            self.metrics.record("receive", message);
+        }
+        message
+    }
+
+    /// send a message
+    pub async fn send(&mut self, message: Message) -> Result<(), axum::Error> {
+        self.metrics.record("send", &message);
+        self.inner.send(message).await
+    }
+
+    /// returns the wrapped socket
+    pub fn inner(&self) -> &WebSocket {
+        &self.inner
+    }
+}
+
+impl Drop for MeteredWebSocket {
+    fn drop(&mut self) {
+        self.metrics.active_connections.add(-1, &self.metrics.attributes);
+        self.metrics
+            .connection_duration
+            .record(self.start.elapsed().as_secs_f64(), &self.metrics.attributes);
+    }
+}
+
+impl Stream for MeteredWebSocket {
+    type Item = Result<Message, axum::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let message = ready!(Pin::new(&mut self.inner).poll_next(cx));
+        if let Some(Ok(ref message)) = message {
+            self.metrics.record("receive", message);
+        }
+        Poll::Ready(message)
+    }
+}
+
+impl Sink<Message> for MeteredWebSocket {
+    type Error = axum::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message) -> Result<(), Self::Error> {
+        self.metrics.record("send", &message);
+        Pin::new(&mut self.inner).start_send(message)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_upgrade() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_upgrade(&headers));
+        headers.insert(http::header::UPGRADE, "WebSocket".parse().unwrap());
+        assert!(is_upgrade(&headers));
+        headers.insert(http::header::UPGRADE, "h2c".parse().unwrap());
+        assert!(!is_upgrade(&headers));
+    }
+}